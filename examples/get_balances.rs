@@ -17,14 +17,14 @@
 //! OWNER_KEYPAIR=$(cat ~/.config/solana/id.json) \
 //! cargo run --example get_balances
 
+use conf_balances_examples::{decryption, signer::load_signer};
 use solana_client::rpc_client::RpcClient;
 use solana_commitment_config::CommitmentConfig;
 use solana_sdk::{
     pubkey::Pubkey,
-    signature::{read_keypair_file, Keypair, Signer},
+    signature::Signer,
 };
 use spl_associated_token_account::get_associated_token_address_with_program_id;
-use serde_json;
 use spl_token_2022::{
     extension::{
         confidential_transfer::ConfidentialTransferAccount,
@@ -49,7 +49,7 @@ struct BalanceBreakdown {
 /// Get all balance types for a confidential token account
 fn get_balances(
     client: &RpcClient,
-    owner: &Keypair,
+    owner: &dyn Signer,
     mint: &Pubkey,
 ) -> Result<BalanceBreakdown, Box<dyn std::error::Error>> {
     let token_account = get_associated_token_address_with_program_id(
@@ -106,18 +106,25 @@ fn get_balances(
     let public_balance = account.base.amount;
     println!("\n💵 Public Balance (visible to all): {}", public_balance);
 
-    // 2. Decrypt pending balance (ElGamal encrypted, split into lo/hi)
+    // 2. Decrypt pending balance (ElGamal encrypted, split into lo/hi) and
+    //    the ElGamal available balance, all three in parallel
     let pending_lo: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
         ct_extension.pending_balance_lo.try_into()
             .map_err(|_| "Failed to convert pending_balance_lo")?;
     let pending_hi: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
         ct_extension.pending_balance_hi.try_into()
             .map_err(|_| "Failed to convert pending_balance_hi")?;
+    let available_balance_elgamal: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
+        ct_extension.available_balance.try_into()
+            .map_err(|_| "Failed to convert available_balance")?;
 
-    let pending_lo_amount = pending_lo.decrypt_u32(elgamal_keypair.secret())
-        .ok_or("Failed to decrypt pending_balance_lo")?;
-    let pending_hi_amount = pending_hi.decrypt_u32(elgamal_keypair.secret())
-        .ok_or("Failed to decrypt pending_balance_hi")?;
+    // The shared engine loads (or builds once) a precomputed discrete-log table
+    let decrypted = decryption::engine().decrypt_many(
+        elgamal_keypair.secret(),
+        &[pending_lo, pending_hi, available_balance_elgamal],
+    );
+    let pending_lo_amount = decrypted[0].ok_or("Failed to decrypt pending_balance_lo")?;
+    let pending_hi_amount = decrypted[1].ok_or("Failed to decrypt pending_balance_hi")?;
 
     // Combine lo and hi parts (pending is split for range proofs)
     let pending_total = pending_lo_amount + (pending_hi_amount << 16);
@@ -127,13 +134,8 @@ fn get_balances(
     println!("   High bits: {} (decrypted with ElGamal secret key)", pending_hi_amount);
     println!("   Combined:  {}", pending_total);
 
-    // 3. Decrypt available balance (ElGamal encrypted)
-    let available_balance_elgamal: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
-        ct_extension.available_balance.try_into()
-            .map_err(|_| "Failed to convert available_balance")?;
-
-    let available_elgamal = available_balance_elgamal.decrypt_u32(elgamal_keypair.secret())
-        .ok_or("Failed to decrypt available_balance with ElGamal")?;
+    // 3. Available balance (ElGamal encrypted), decrypted above
+    let available_elgamal = decrypted[2].ok_or("Failed to decrypt available_balance with ElGamal")?;
 
     // 4. Also decrypt using the AES-encrypted decryptable balance (faster for owner)
     let decryptable_balance: spl_token_2022::solana_zk_sdk::encryption::auth_encryption::AeCiphertext =
@@ -201,25 +203,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mint = mint_str.parse::<Pubkey>()
         .map_err(|_| format!("Invalid mint address: {}", mint_str))?;
 
-    // Load owner keypair (from args, env var as path, or env var as JSON)
-    let owner = if args.len() >= 3 {
-        // From command line argument (file path)
-        let keypair_path = &args[2];
-        read_keypair_file(keypair_path)
-            .map_err(|e| format!("Failed to read keypair from {}: {}", keypair_path, e))?
-    } else if let Ok(keypair_json) = env::var("OWNER_KEYPAIR") {
-        // From environment variable (JSON array)
-        let bytes: Vec<u8> = serde_json::from_str(&keypair_json)
-            .map_err(|e| format!("Failed to parse OWNER_KEYPAIR as JSON: {}", e))?;
-
-        if bytes.len() != 64 {
-            return Err(format!("Invalid keypair: expected 64 bytes, got {}", bytes.len()).into());
-        }
-
-        // Extract first 32 bytes (secret key)
-        let mut secret_key = [0u8; 32];
-        secret_key.copy_from_slice(&bytes[0..32]);
-        Keypair::new_from_array(secret_key)
+    // Load owner signer (from args or env var: path, JSON bytes, base58, seed phrase, ASK)
+    let owner_source = if args.len() >= 3 {
+        args[2].clone()
+    } else if let Ok(source) = env::var("OWNER_KEYPAIR") {
+        source
     } else {
         eprintln!("Error: Owner keypair not provided");
         eprintln!("\nProvide keypair via:");
@@ -227,6 +215,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("  2. Environment var:   OWNER_KEYPAIR=$(cat ~/.config/solana/id.json)");
         std::process::exit(1);
     };
+    let owner = load_signer(&owner_source)?;
 
     // Connect to RPC
     let rpc_url = env::var("SOLANA_RPC_URL")
@@ -239,7 +228,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("🪙 Mint: {}\n", mint);
 
     // Get balances
-    let balances = get_balances(&client, &owner, &mint)?;
+    let balances = get_balances(&client, owner.as_ref(), &mint)?;
 
     // Display formatted
     display_balances(&balances, 9); // Assuming 9 decimals
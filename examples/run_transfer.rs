@@ -56,17 +56,10 @@ fn display_balances(
     // Public balance
     let public_balance = account.base.amount;
 
-    // Decrypt pending balance (lo + hi)
-    let pending_lo: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
-        ct_extension.pending_balance_lo.try_into()?;
-    let pending_hi: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
-        ct_extension.pending_balance_hi.try_into()?;
-
-    let pending_lo_amount = pending_lo.decrypt_u32(elgamal_keypair.secret())
-        .unwrap_or(0);
-    let pending_hi_amount = pending_hi.decrypt_u32(elgamal_keypair.secret())
+    // Decrypt pending balance (lo + hi, in parallel with the shared table)
+    let pending_total = decryption::engine()
+        .decrypt_pending_balance(elgamal_keypair.secret(), ct_extension)
         .unwrap_or(0);
-    let pending_total = pending_lo_amount + (pending_hi_amount << 16);
 
     // Decrypt available balance using AES (most efficient)
     let decryptable_balance: spl_token_2022::solana_zk_sdk::encryption::auth_encryption::AeCiphertext =
@@ -99,6 +92,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("🔗 Connecting to: {}", rpc_url);
 
+    // Print progress of each operation as it happens
+    events::print_events();
+
     let client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
 
     // Load payer from environment
@@ -253,18 +249,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     client.send_and_confirm_transaction(&transaction)?;
 
     // Show initial balances
-    display_balances(&client, "Sender (after mint)", &sender, &mint.pubkey(), 9)?;
+    display_balances(&client, "Sender (after mint)", sender, &mint.pubkey(), 9)?;
     display_balances(&client, "Recipient (initial)", &recipient, &mint.pubkey(), 9)?;
 
     // Deposit to confidential
     println!("\n💰 Depositing to confidential balance...");
     deposit::deposit_to_confidential(&client, &payer, &sender, &mint.pubkey(), 800_000_000, 9).await?;
-    display_balances(&client, "Sender (after deposit)", &sender, &mint.pubkey(), 9)?;
+    display_balances(&client, "Sender (after deposit)", sender, &mint.pubkey(), 9)?;
 
     // Apply pending
     println!("\n🔄 Applying pending balance...");
     apply_pending::apply_pending_balance(&client, &payer, &sender, &mint.pubkey()).await?;
-    display_balances(&client, "Sender (after apply)", &sender, &mint.pubkey(), 9)?;
+    display_balances(&client, "Sender (after apply)", sender, &mint.pubkey(), 9)?;
 
     // Transfer confidentially
     println!("\n🔐 Executing confidential transfer...");
@@ -273,10 +269,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("   - 1 confidential transfer");
     println!("   - 3 proof account closures");
 
-    let signatures = transfer::transfer_confidential(
+    let receipt = transfer::transfer_confidential(
         &client,
         &payer,
-        &sender,
+        sender,
         &mint.pubkey(),
         &recipient.pubkey(),
         50_000_000,
@@ -285,7 +281,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("\n✅ Confidential transfer complete!");
 
     // Show balances after transfer
-    display_balances(&client, "Sender (after transfer)", &sender, &mint.pubkey(), 9)?;
+    display_balances(&client, "Sender (after transfer)", sender, &mint.pubkey(), 9)?;
     display_balances(&client, "Recipient (after transfer - before apply)", &recipient, &mint.pubkey(), 9)?;
 
     // Recipient applies pending balance
@@ -294,12 +290,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     display_balances(&client, "Recipient (after apply)", &recipient, &mint.pubkey(), 9)?;
 
     println!("\n📝 Transaction signatures:");
-    for (i, sig) in signatures.iter().enumerate() {
-        println!("   {}. {}", i + 1, sig);
+    for (i, transaction) in receipt.transactions.iter().enumerate() {
+        println!("   {}. {:?} {}", i + 1, transaction.role, transaction.signature);
     }
+    println!("   Fees: {} lamports, proof account rent: {} spent / {} reclaimed",
+        receipt.fee, receipt.rent_spent, receipt.rent_reclaimed);
 
     println!("\n🔗 View on explorer:");
-    println!("   https://explorer.solana.com/tx/{}?cluster=custom&customUrl=https%3A%2F%2Fzk-edge.surfnet.dev%3A8899", signatures[3]);
+    println!("   https://explorer.solana.com/tx/{}?cluster=custom&customUrl=https%3A%2F%2Fzk-edge.surfnet.dev%3A8899",
+        receipt.signature(receipt::TransactionRole::Transfer).unwrap());
 
     println!("\n📋 Account Addresses (for querying balances):");
     println!("   Mint:                   {}", mint.pubkey());
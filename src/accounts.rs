@@ -0,0 +1,233 @@
+//! Create and close confidential mints and token accounts
+
+use crate::decryption;
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
+use crate::types::*;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+use solana_system_interface::instruction as system_instruction;
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::{
+            account_info::EmptyAccountAccountInfo,
+            instruction::{empty_account, initialize_mint},
+            ConfidentialTransferAccount,
+        },
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    instruction::{close_account, initialize_mint as initialize_mint_base},
+    solana_zk_sdk::encryption::{
+        elgamal::ElGamalKeypair,
+        pod::elgamal::PodElGamalPubkey,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+
+/// Create a mint with the ConfidentialTransferMint extension
+///
+/// Steps:
+/// 1. Allocate the mint account with room for the extension
+/// 2. Initialize the confidential transfer extension (optional auditor)
+/// 3. Initialize the base mint
+#[tracing::instrument(skip_all, fields(mint = %mint.pubkey()))]
+pub async fn create_confidential_mint(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    mint: &dyn Signer,
+    mint_authority: &Pubkey,
+    decimals: u8,
+    auditor_elgamal_pubkey: Option<PodElGamalPubkey>,
+    auto_approve_new_accounts: bool,
+) -> ReceiptResult {
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&[
+        ExtensionType::ConfidentialTransferMint,
+    ])?;
+    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            rent,
+            space as u64,
+            &spl_token_2022::id(),
+        ),
+        initialize_mint(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            Some(*mint_authority), // confidential transfer authority
+            auto_approve_new_accounts,
+            auditor_elgamal_pubkey,
+        )?,
+        initialize_mint_base(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            mint_authority,
+            None, // freeze_authority
+            decimals,
+        )?,
+    ];
+
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer, mint],
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::CreateMint, &transaction)?;
+    events::completed(Operation::CreateMint, format!("Created confidential mint {}: {}", mint.pubkey(), signature));
+
+    let sent = SentTransactions::single(TransactionRole::CreateMint, signature, &[mint.pubkey()]);
+    Ok(fetch_receipt(client, Operation::CreateMint, None, &sent, None))
+}
+
+/// Create the owner's associated token account (no-op if it exists)
+#[tracing::instrument(skip_all, fields(%owner, %mint))]
+pub async fn create_token_account(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> ReceiptResult {
+    let token_account = get_associated_token_address_with_program_id(owner, mint, &spl_token_2022::id());
+    let create_ix = create_associated_token_account_idempotent(
+        &payer.pubkey(),
+        owner,
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[create_ix],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::CreateAccount, &transaction)?;
+    events::completed(Operation::CreateAccount, format!("Created token account {}: {}", token_account, signature));
+
+    // Rent is only spent if the account didn't exist yet
+    let sent = SentTransactions::single(TransactionRole::CreateAccount, signature, &[token_account]);
+    Ok(fetch_receipt(client, Operation::CreateAccount, Some(&token_account), &sent, None))
+}
+
+/// Close the authority's confidential token account
+///
+/// Steps:
+/// 1. Check public, pending and available balances are all zero
+/// 2. Empty the confidential extension with a zero-balance proof
+/// 3. Close the account, sending its rent to `destination`
+pub async fn close_confidential_account(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &Pubkey,
+    destination: &Pubkey,
+) -> ReceiptResult {
+    close_confidential_account_with_keys(
+        client, payer, authority, mint, destination, &SignerKeyProvider::new(authority),
+    ).await
+}
+
+/// Close a confidential token account with keys from a key provider
+///
+/// Same as `close_confidential_account`, for accounts whose keys aren't
+/// derived from the authority.
+#[tracing::instrument(skip_all, fields(authority = %authority.pubkey(), %mint))]
+pub async fn close_confidential_account_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    let token_account = get_associated_token_address_with_program_id(
+        &authority.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    let elgamal_keypair = keys.elgamal_keypair(&token_account)?;
+
+    // Only empty accounts can be closed
+    let account_data = client.get_account(&token_account)?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+    if account.base.amount > 0 {
+        return Err(format!("Account still holds {} public tokens", account.base.amount).into());
+    }
+    let ct_extension = account.get_extension::<ConfidentialTransferAccount>()?;
+    let (pending, available) = decryption::engine()
+        .decrypt_balances(elgamal_keypair.secret(), ct_extension)?;
+    for (amount, name) in [(pending, "pending"), (available, "available")] {
+        if amount > 0 {
+            return Err(format!("Account still holds a confidential {} balance; withdraw it first", name).into());
+        }
+    }
+
+    let mut instructions = empty_account_instructions(client, &authority.pubkey(), &token_account, &elgamal_keypair)?;
+    instructions.push(close_account(
+        &spl_token_2022::id(),
+        &token_account,
+        destination,
+        &authority.pubkey(),
+        &[],
+    )?);
+
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer, authority],
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::CloseAccount, &transaction)?;
+    events::completed(Operation::CloseAccount, format!("Closed confidential account {}: {}", token_account, signature));
+
+    let sent = SentTransactions::single(TransactionRole::CloseAccount, signature, &[token_account]);
+    Ok(fetch_receipt(client, Operation::CloseAccount, Some(&token_account), &sent, None))
+}
+
+/// Instructions zeroing out the confidential extension of an account
+///
+/// The zero-ciphertext proof goes inline in the next instruction.
+pub(crate) fn empty_account_instructions(
+    client: &RpcClient,
+    authority: &Pubkey,
+    token_account: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+) -> CtResult<Vec<Instruction>> {
+    let account_data = client.get_account(token_account)?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+    let ct_extension = account.get_extension::<ConfidentialTransferAccount>()?;
+
+    // Proof that the available balance encrypts zero
+    let proof_data = EmptyAccountAccountInfo::new(ct_extension)
+        .generate_proof_data(elgamal_keypair)
+        .map_err(|_| "Failed to generate zero ciphertext proof")?;
+
+    Ok(empty_account(
+        &spl_token_2022::id(),
+        token_account,
+        authority,
+        &[],
+        ProofLocation::InstructionOffset(1.try_into().unwrap(), &proof_data),
+    )?)
+}
@@ -1,5 +1,10 @@
 //! Apply pending balance to available balance
 
+use crate::authority::{unique_signers, Authority};
+use crate::decryption;
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
 use crate::types::*;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
@@ -15,10 +20,6 @@ use spl_token_2022::{
         },
         BaseStateWithExtensions, StateWithExtensions,
     },
-    solana_zk_sdk::encryption::{
-        auth_encryption::AeKey,
-        elgamal::ElGamalKeypair,
-    },
     state::Account as TokenAccount,
 };
 
@@ -36,22 +37,59 @@ pub async fn apply_pending_balance(
     payer: &dyn Signer,
     authority: &dyn Signer,
     mint: &solana_sdk::pubkey::Pubkey,
+) -> ReceiptResult {
+    apply_pending_balance_with_keys(client, payer, authority, mint, &SignerKeyProvider::new(authority)).await
+}
+
+/// Apply pending balance with explicit encryption keys
+///
+/// Same as `apply_pending_balance`, for accounts whose keys aren't derived
+/// from the authority.
+pub async fn apply_pending_balance_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    apply_pending_balance_with_authority(client, payer, &Authority::Signer(authority), mint, keys).await
+}
+
+/// Apply pending balance for a single-signer or multisig authority
+///
+/// Same as `apply_pending_balance_with_keys`; a multisig's signers are
+/// checked against the multisig account before sending.
+pub async fn apply_pending_balance_with_authority(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    let signature = send_apply_pending_balance(client, payer, authority, mint, keys)?;
+    let token_account = get_associated_token_address_with_program_id(&authority.pubkey(), mint, &spl_token_2022::id());
+    let sent = SentTransactions::single(TransactionRole::ApplyPendingBalance, signature, &[]);
+    Ok(fetch_receipt(client, Operation::ApplyPendingBalance, Some(&token_account), &sent, Some(keys)))
+}
+
+#[tracing::instrument(skip_all, fields(authority = %authority.pubkey(), %mint))]
+fn send_apply_pending_balance(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    keys: &dyn KeyProvider,
 ) -> SigResult {
+    authority.check(client)?;
     let token_account = get_associated_token_address_with_program_id(
         &authority.pubkey(),
         mint,
         &spl_token_2022::id(),
     );
 
-    // Derive encryption keys
-    let elgamal_keypair = ElGamalKeypair::new_from_signer(
-        authority,
-        &token_account.to_bytes(),
-    )?;
-    let aes_key = AeKey::new_from_signer(
-        authority,
-        &token_account.to_bytes(),
-    )?;
+    // Fetch encryption keys from the provider
+    let elgamal_keypair = keys.elgamal_keypair(&token_account)?;
+    let aes_key = keys.aes_key(&token_account)?;
 
     // Fetch account state
     let account_data = client.get_account(&token_account)?;
@@ -59,25 +97,10 @@ pub async fn apply_pending_balance(
     let ct_extension = account.get_extension::<ConfidentialTransferAccount>()?;
 
     // Decrypt current balances
-    let pending_balance_lo: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
-        ct_extension.pending_balance_lo.try_into()
-            .map_err(|_| "Failed to convert pending_balance_lo")?;
-    let pending_balance_hi: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
-        ct_extension.pending_balance_hi.try_into()
-            .map_err(|_| "Failed to convert pending_balance_hi")?;
-    let available_balance: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
-        ct_extension.available_balance.try_into()
-            .map_err(|_| "Failed to convert available_balance")?;
-
-    let pending_lo = pending_balance_lo.decrypt_u32(elgamal_keypair.secret())
-        .ok_or("Failed to decrypt pending_balance_lo")?;
-    let pending_hi = pending_balance_hi.decrypt_u32(elgamal_keypair.secret())
-        .ok_or("Failed to decrypt pending_balance_hi")?;
-    let current_available = available_balance.decrypt_u32(elgamal_keypair.secret())
-        .ok_or("Failed to decrypt available_balance")?;
+    let (pending_total, current_available) = decryption::engine()
+        .decrypt_balances(elgamal_keypair.secret(), ct_extension)?;
 
     // Calculate new available balance
-    let pending_total = pending_lo + (pending_hi << 16);
     let new_available = current_available + pending_total;
 
     // Encrypt new available balance with AES for owner
@@ -86,6 +109,9 @@ pub async fn apply_pending_balance(
     // Get expected pending balance credit counter
     let expected_counter: u64 = ct_extension.pending_balance_credit_counter.into();
 
+    let multisig_signers = authority.multisig_signers();
+    let multisig_signers: Vec<_> = multisig_signers.iter().collect();
+
     // Create apply pending balance instruction
     let apply_ix = apply_pending_balance_instruction(
         &spl_token_2022::id(),
@@ -93,20 +119,25 @@ pub async fn apply_pending_balance(
         expected_counter,
         &new_decryptable_balance.into(),
         &authority.pubkey(),
-        &[&authority.pubkey()],
+        &multisig_signers,
     )?;
 
     // Send transaction
     let recent_blockhash = client.get_latest_blockhash()?;
+    let mut signers = vec![payer];
+    signers.extend(authority.signers());
     let transaction = Transaction::new_signed_with_payer(
         &[apply_ix],
         Some(&payer.pubkey()),
-        &[payer, authority],
+        &unique_signers(&signers),
         recent_blockhash,
     );
 
-    let signature = client.send_and_confirm_transaction(&transaction)?;
-    println!("✅ Applied pending balance. New available: {} tokens. Tx: {}", new_available, signature);
+    let signature = events::send_and_confirm(client, Operation::ApplyPendingBalance, &transaction)?;
+    events::completed(
+        Operation::ApplyPendingBalance,
+        format!("Applied pending balance. New available: {} tokens. Tx: {}", new_available, signature),
+    );
 
     Ok(signature)
 }
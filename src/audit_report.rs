@@ -0,0 +1,442 @@
+//! Mint-wide audit reports
+//!
+//! Walks the transaction history of a mint (and optionally extra token
+//! accounts), decrypts every confidential transfer with the auditor key and
+//! aggregates per-account totals. Transfers the auditor key can't decrypt are
+//! kept in the report and flagged instead of failing the whole run.
+//!
+//! Progress is persisted to a JSON cache file after every page of
+//! signatures, so reruns only fetch transactions newer than the last run
+//! (or older than the previously scanned range, if the start bound moved).
+
+use crate::auditor::{extract_transfer_ciphertexts, fetch_transaction};
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::{
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalKeypair;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+
+/// Maximum page size supported by `getSignaturesForAddress`
+const MAX_SIGNATURES_PAGE_SIZE: usize = 1000;
+
+/// Settings for an audit report run
+#[derive(Debug, Clone)]
+pub struct AuditReportConfig {
+    /// Mint whose transfers are audited
+    pub mint: Pubkey,
+    /// Extra addresses (e.g. token accounts) to walk besides the mint
+    pub addresses: Vec<Pubkey>,
+    /// Only include transfers at or after this slot
+    pub start_slot: Option<u64>,
+    /// Only include transfers at or before this slot
+    pub end_slot: Option<u64>,
+    /// Only include transfers at or after this unix timestamp
+    pub start_time: Option<i64>,
+    /// Only include transfers at or before this unix timestamp
+    pub end_time: Option<i64>,
+    /// JSON file used to persist progress between runs
+    pub cache_path: Option<PathBuf>,
+    /// Signatures fetched per `getSignaturesForAddress` page, clamped to
+    /// 1..=1000 (the RPC maximum)
+    pub page_size: usize,
+}
+
+impl AuditReportConfig {
+    /// Audit every transfer of `mint` without a cache
+    pub fn new(mint: Pubkey) -> Self {
+        Self {
+            mint,
+            addresses: Vec::new(),
+            start_slot: None,
+            end_slot: None,
+            start_time: None,
+            end_time: None,
+            cache_path: None,
+            page_size: MAX_SIGNATURES_PAGE_SIZE,
+        }
+    }
+
+    /// Page size actually requested; a shorter page is the end of history
+    fn page_limit(&self) -> usize {
+        self.page_size.clamp(1, MAX_SIGNATURES_PAGE_SIZE)
+    }
+
+    /// Whether a transaction lies before the start of the requested range
+    fn is_before_start(&self, slot: u64, block_time: Option<i64>) -> bool {
+        self.start_slot.is_some_and(|start| slot < start)
+            || matches!((self.start_time, block_time), (Some(start), Some(time)) if time < start)
+    }
+
+    /// Whether a transaction lies within the requested range
+    fn contains(&self, slot: u64, block_time: Option<i64>) -> bool {
+        !self.is_before_start(slot, block_time)
+            && self.end_slot.is_none_or(|end| slot <= end)
+            && !matches!((self.end_time, block_time), (Some(end), Some(time)) if time > end)
+    }
+}
+
+/// A single audited transfer
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde_as(as = "DisplayFromStr")]
+    pub signature: Signature,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub source: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub destination: Pubkey,
+    /// Decrypted amount, or `None` if the auditor key can't decrypt it
+    pub amount: Option<u64>,
+}
+
+/// Per-account totals of decrypted transfers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountTotals {
+    pub total_in: u64,
+    pub total_out: u64,
+    pub transfers_in: u64,
+    pub transfers_out: u64,
+    /// Transfers touching this account that the auditor couldn't decrypt
+    pub undecryptable: u64,
+}
+
+/// Audit report for a mint over a slot/time range
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditReport {
+    #[serde_as(as = "DisplayFromStr")]
+    pub mint: Pubkey,
+    /// All transfers in range, oldest first
+    pub entries: Vec<AuditEntry>,
+    /// Totals keyed by token account
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    pub accounts: BTreeMap<Pubkey, AccountTotals>,
+}
+
+impl AuditReport {
+    /// Transfers the auditor key could not decrypt
+    pub fn undecryptable(&self) -> impl Iterator<Item = &AuditEntry> {
+        self.entries.iter().filter(|entry| entry.amount.is_none())
+    }
+
+    /// Render the report as pretty-printed JSON
+    pub fn to_json(&self) -> CtResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Render one CSV row per transfer
+    pub fn transfers_csv(&self) -> String {
+        let mut csv = String::from("signature,slot,block_time,source,destination,amount,decryptable\n");
+        for entry in &self.entries {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                entry.signature,
+                entry.slot,
+                entry.block_time.map(|time| time.to_string()).unwrap_or_default(),
+                entry.source,
+                entry.destination,
+                entry.amount.map(|amount| amount.to_string()).unwrap_or_default(),
+                entry.amount.is_some(),
+            ));
+        }
+        csv
+    }
+
+    /// Render one CSV row per account with totals in and out
+    pub fn accounts_csv(&self) -> String {
+        let mut csv = String::from("account,total_in,total_out,transfers_in,transfers_out,undecryptable\n");
+        for (account, totals) in &self.accounts {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                account,
+                totals.total_in,
+                totals.total_out,
+                totals.transfers_in,
+                totals.transfers_out,
+                totals.undecryptable,
+            ));
+        }
+        csv
+    }
+}
+
+/// A scanned signature with its position in history
+#[serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct ScannedSignature {
+    #[serde_as(as = "DisplayFromStr")]
+    signature: Signature,
+    slot: u64,
+    block_time: Option<i64>,
+}
+
+/// Scan position for a single address
+#[serde_as]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AddressCursor {
+    /// Newest signature scanned (forward scans stop here)
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    newest: Option<Signature>,
+    /// Oldest signature scanned (backward scans resume here)
+    oldest: Option<ScannedSignature>,
+    /// Whether the address history was scanned back to its first transaction
+    exhausted: bool,
+}
+
+/// Progress persisted between runs
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditCache {
+    #[serde_as(as = "DisplayFromStr")]
+    mint: Pubkey,
+    /// Auditor pubkey the cached amounts were decrypted with
+    auditor: String,
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    cursors: BTreeMap<Pubkey, AddressCursor>,
+    #[serde_as(as = "BTreeSet<DisplayFromStr>")]
+    scanned: BTreeSet<Signature>,
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditCache {
+    fn load_or_new(config: &AuditReportConfig, auditor: &str) -> CtResult<Self> {
+        if let Some(path) = config.cache_path.as_ref().filter(|path| path.exists()) {
+            let cache: AuditCache = serde_json::from_str(&fs::read_to_string(path)?)?;
+            if cache.mint != config.mint || cache.auditor != auditor {
+                return Err(format!(
+                    "Audit cache {} belongs to another mint or auditor key",
+                    path.display()
+                ).into());
+            }
+            return Ok(cache);
+        }
+
+        Ok(Self {
+            mint: config.mint,
+            auditor: auditor.to_string(),
+            cursors: BTreeMap::new(),
+            scanned: BTreeSet::new(),
+            entries: Vec::new(),
+        })
+    }
+
+    fn save(&self, config: &AuditReportConfig) -> CtResult<()> {
+        if let Some(path) = &config.cache_path {
+            fs::write(path, serde_json::to_string(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Generate an audit report for all confidential transfers of a mint
+///
+/// Pages through `getSignaturesForAddress` for the mint and any extra
+/// addresses, decrypts each transfer with the auditor key and aggregates
+/// per-account totals for transfers within the configured range.
+pub fn generate_audit_report(
+    client: &RpcClient,
+    auditor_elgamal_keypair: &ElGamalKeypair,
+    config: &AuditReportConfig,
+) -> CtResult<AuditReport> {
+    let mut cache = AuditCache::load_or_new(config, &auditor_elgamal_keypair.pubkey().to_string())?;
+
+    let mut addresses = vec![config.mint];
+    addresses.extend(config.addresses.iter().filter(|address| **address != config.mint));
+
+    for address in addresses {
+        scan_address(client, auditor_elgamal_keypair, config, &mut cache, &address)?;
+    }
+
+    Ok(build_report(config, &cache))
+}
+
+/// Scan new (and, if needed, older) signatures of an address
+fn scan_address(
+    client: &RpcClient,
+    auditor_elgamal_keypair: &ElGamalKeypair,
+    config: &AuditReportConfig,
+    cache: &mut AuditCache,
+    address: &Pubkey,
+) -> CtResult<()> {
+    let mut cursor = cache.cursors.get(address).cloned().unwrap_or_default();
+
+    // On the first run, walk back from the newest signature to the range start
+    let Some(until) = cursor.newest else {
+        let mut before = None;
+        loop {
+            let page = signatures_page(client, config, address, before, None)?;
+            if cursor.newest.is_none() {
+                cursor.newest = page.first().map(|info| info.signature.parse()).transpose()?;
+            }
+
+            let (last, reached_start) =
+                process_page(client, auditor_elgamal_keypair, config, cache, &page, true)?;
+            cursor.oldest = last.or(cursor.oldest);
+            cursor.exhausted = page.len() < config.page_limit() && !reached_start;
+            cache.cursors.insert(*address, cursor.clone());
+            cache.save(config)?;
+
+            if page.len() < config.page_limit() || reached_start {
+                return Ok(());
+            }
+            before = last.map(|scanned| scanned.signature);
+        }
+    };
+
+    // Forward: everything newer than the last run
+    let mut before = None;
+    let mut newest = None;
+    loop {
+        let page = signatures_page(client, config, address, before, Some(until))?;
+        if newest.is_none() {
+            newest = page.first().map(|info| info.signature.parse()).transpose()?;
+        }
+
+        let (last, _) = process_page(client, auditor_elgamal_keypair, config, cache, &page, false)?;
+        cache.save(config)?;
+
+        if page.len() < config.page_limit() {
+            break;
+        }
+        before = last.map(|scanned| scanned.signature);
+    }
+    cursor.newest = newest.or(cursor.newest);
+    cache.cursors.insert(*address, cursor.clone());
+    cache.save(config)?;
+
+    // Backward: extend older history while it may still be within range
+    while let Some(oldest) = cursor.oldest {
+        if cursor.exhausted || config.is_before_start(oldest.slot, oldest.block_time) {
+            break;
+        }
+
+        let page = signatures_page(client, config, address, Some(oldest.signature), None)?;
+        let (last, reached_start) =
+            process_page(client, auditor_elgamal_keypair, config, cache, &page, true)?;
+
+        cursor.oldest = last.or(cursor.oldest);
+        cursor.exhausted = page.len() < config.page_limit() && !reached_start;
+        cache.cursors.insert(*address, cursor.clone());
+        cache.save(config)?;
+
+        if reached_start || page.is_empty() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch one page of signatures for an address, newest first
+fn signatures_page(
+    client: &RpcClient,
+    config: &AuditReportConfig,
+    address: &Pubkey,
+    before: Option<Signature>,
+    until: Option<Signature>,
+) -> CtResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+    Ok(client.get_signatures_for_address_with_config(
+        address,
+        GetConfirmedSignaturesForAddress2Config {
+            before,
+            until,
+            limit: Some(config.page_limit()),
+            commitment: Some(CommitmentConfig::confirmed()),
+        },
+    )?)
+}
+
+/// Decrypt the transfers of one page of signatures into the cache
+///
+/// Returns the oldest signature processed and whether the page crossed the
+/// start of the requested range. With `stop_at_start`, signatures before the
+/// range start are left unprocessed so a later run can still pick them up.
+fn process_page(
+    client: &RpcClient,
+    auditor_elgamal_keypair: &ElGamalKeypair,
+    config: &AuditReportConfig,
+    cache: &mut AuditCache,
+    page: &[RpcConfirmedTransactionStatusWithSignature],
+    stop_at_start: bool,
+) -> CtResult<(Option<ScannedSignature>, bool)> {
+    let mut last = None;
+
+    for info in page {
+        if stop_at_start && config.is_before_start(info.slot, info.block_time) {
+            return Ok((last, true));
+        }
+
+        let signature: Signature = info.signature.parse()?;
+        last = Some(ScannedSignature {
+            signature,
+            slot: info.slot,
+            block_time: info.block_time,
+        });
+
+        // Failed transactions and signatures seen via another address are skipped
+        if info.err.is_some() || !cache.scanned.insert(signature) {
+            continue;
+        }
+
+        let fetched = fetch_transaction(client, &signature)?;
+        let transfers = extract_transfer_ciphertexts(&fetched.transaction, &fetched.account_keys)?;
+
+        for transfer in transfers.iter().filter(|transfer| transfer.mint == config.mint) {
+            cache.entries.push(AuditEntry {
+                signature,
+                slot: info.slot,
+                block_time: info.block_time,
+                source: transfer.source,
+                destination: transfer.destination,
+                amount: transfer.decrypt(auditor_elgamal_keypair),
+            });
+        }
+    }
+
+    Ok((last, false))
+}
+
+/// Aggregate cached entries within the configured range into a report
+fn build_report(config: &AuditReportConfig, cache: &AuditCache) -> AuditReport {
+    let mut entries: Vec<AuditEntry> = cache.entries
+        .iter()
+        .filter(|entry| config.contains(entry.slot, entry.block_time))
+        .cloned()
+        .collect();
+    entries.sort_by_key(|entry| entry.slot);
+
+    let mut accounts: BTreeMap<Pubkey, AccountTotals> = BTreeMap::new();
+    for entry in &entries {
+        match entry.amount {
+            Some(amount) => {
+                let source = accounts.entry(entry.source).or_default();
+                source.total_out += amount;
+                source.transfers_out += 1;
+
+                let destination = accounts.entry(entry.destination).or_default();
+                destination.total_in += amount;
+                destination.transfers_in += 1;
+            }
+            None => {
+                accounts.entry(entry.source).or_default().undecryptable += 1;
+                accounts.entry(entry.destination).or_default().undecryptable += 1;
+            }
+        }
+    }
+
+    AuditReport {
+        mint: config.mint,
+        entries,
+        accounts,
+    }
+}
@@ -0,0 +1,243 @@
+//! Auditor-side decryption of confidential transfers
+//!
+//! When a mint has an auditor ElGamal pubkey, every `Transfer` and
+//! `TransferWithFee` instruction carries the transfer amount encrypted under
+//! the auditor key, split into a 16-bit low part and a 32-bit high part.
+//! These are the auditor's handles of the grouped ciphertexts used in the
+//! transfer proofs, stored directly in the instruction data.
+//!
+//! The auditor can therefore recover the amount of any transfer from the
+//! transaction alone, without access to the sender or recipient keys.
+
+use crate::decryption;
+use crate::types::*;
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status_client_types::{UiLoadedAddresses, UiTransactionEncoding};
+use spl_token_2022::{
+    extension::confidential_transfer::instruction::{
+        ConfidentialTransferInstruction, TransferInstructionData, TransferWithFeeInstructionData,
+    },
+    instruction::{decode_instruction_data, decode_instruction_type, TokenInstruction},
+    solana_zk_sdk::encryption::{
+        elgamal::ElGamalKeypair,
+        pod::elgamal::PodElGamalCiphertext,
+    },
+};
+
+/// A confidential transfer decrypted by the auditor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Transaction containing the transfer
+    pub signature: Signature,
+    /// Slot the transaction landed in
+    pub slot: u64,
+    /// Source token account
+    pub source: Pubkey,
+    /// Destination token account
+    pub destination: Pubkey,
+    /// Decrypted transfer amount (in base units)
+    pub amount: u64,
+}
+
+/// Auditor ciphertexts of a single transfer instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferCiphertexts {
+    /// Source token account
+    pub source: Pubkey,
+    /// Token mint
+    pub mint: Pubkey,
+    /// Destination token account
+    pub destination: Pubkey,
+    /// Low 16 bits of the amount, encrypted under the auditor pubkey
+    pub ciphertext_lo: PodElGamalCiphertext,
+    /// High 32 bits of the amount, encrypted under the auditor pubkey
+    pub ciphertext_hi: PodElGamalCiphertext,
+}
+
+impl TransferCiphertexts {
+    /// Decrypt the transfer amount, or `None` if this auditor key can't decrypt it
+    pub fn decrypt(&self, auditor_elgamal_keypair: &ElGamalKeypair) -> Option<u64> {
+        decrypt_lo_hi(auditor_elgamal_keypair, &self.ciphertext_lo, &self.ciphertext_hi)
+    }
+}
+
+/// A transaction fetched from RPC with its fully resolved account keys
+pub(crate) struct FetchedTransaction {
+    pub slot: u64,
+    pub transaction: VersionedTransaction,
+    /// Static keys followed by lookup table writable and readonly keys
+    pub account_keys: Vec<Pubkey>,
+    /// Transaction error, if it failed
+    pub error: Option<String>,
+    /// Program log messages
+    pub log_messages: Vec<String>,
+}
+
+/// Decrypt the amount of a transfer from raw Token-2022 instruction data
+///
+/// Accepts the full instruction data of a confidential `Transfer` or
+/// `TransferWithFee` instruction (including the extension prefix byte).
+pub fn decrypt_transfer_instruction_data(
+    auditor_elgamal_keypair: &ElGamalKeypair,
+    instruction_data: &[u8],
+) -> CtResult<u64> {
+    let (ciphertext_lo, ciphertext_hi) = auditor_ciphertexts(instruction_data)
+        .ok_or("Instruction is not a confidential transfer")?;
+
+    decrypt_lo_hi(auditor_elgamal_keypair, &ciphertext_lo, &ciphertext_hi)
+        .ok_or_else(|| "Failed to decrypt transfer amount with auditor key".into())
+}
+
+/// Extract the auditor ciphertexts of every confidential transfer in a transaction
+///
+/// `account_keys` must be the transaction's resolved account keys (static
+/// keys followed by any address lookup table keys). Only top-level
+/// instructions are inspected.
+pub fn extract_transfer_ciphertexts(
+    transaction: &VersionedTransaction,
+    account_keys: &[Pubkey],
+) -> CtResult<Vec<TransferCiphertexts>> {
+    let mut transfers = Vec::new();
+
+    for instruction in transaction.message.instructions() {
+        let program_id = account_keys.get(instruction.program_id_index as usize)
+            .ok_or("Instruction program index out of range")?;
+        if *program_id != spl_token_2022::id() {
+            continue;
+        }
+
+        let Some((ciphertext_lo, ciphertext_hi)) = auditor_ciphertexts(&instruction.data) else {
+            continue;
+        };
+
+        // Accounts: [source, mint, destination, ...]
+        let account = |position: usize| -> CtResult<Pubkey> {
+            let index = *instruction.accounts.get(position)
+                .ok_or("Transfer instruction is missing accounts")?;
+            Ok(*account_keys.get(index as usize)
+                .ok_or("Transfer account index out of range")?)
+        };
+
+        transfers.push(TransferCiphertexts {
+            source: account(0)?,
+            mint: account(1)?,
+            destination: account(2)?,
+            ciphertext_lo,
+            ciphertext_hi,
+        });
+    }
+
+    Ok(transfers)
+}
+
+/// Decrypt every confidential transfer in a transaction with the auditor key
+///
+/// Fails if the transaction itself failed (none of its transfers
+/// happened), or if any transfer in it can't be decrypted with the given
+/// auditor keypair (e.g. it belongs to a mint with another auditor).
+pub fn audit_transaction(
+    client: &RpcClient,
+    auditor_elgamal_keypair: &ElGamalKeypair,
+    signature: &Signature,
+) -> CtResult<Vec<AuditRecord>> {
+    let fetched = fetch_transaction(client, signature)?;
+    if let Some(error) = &fetched.error {
+        return Err(format!("Transaction {} failed, so it transferred nothing: {}", signature, error).into());
+    }
+    let transfers = extract_transfer_ciphertexts(&fetched.transaction, &fetched.account_keys)?;
+
+    transfers
+        .iter()
+        .map(|transfer| {
+            let amount = transfer.decrypt(auditor_elgamal_keypair).ok_or_else(|| format!(
+                "Failed to decrypt transfer {} -> {} in {} with auditor key",
+                transfer.source, transfer.destination, signature
+            ))?;
+            Ok(AuditRecord {
+                signature: *signature,
+                slot: fetched.slot,
+                source: transfer.source,
+                destination: transfer.destination,
+                amount,
+            })
+        })
+        .collect()
+}
+
+/// Fetch a confirmed transaction and resolve its account keys
+pub(crate) fn fetch_transaction(
+    client: &RpcClient,
+    signature: &Signature,
+) -> CtResult<FetchedTransaction> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = client.get_transaction_with_config(signature, config)?;
+
+    let transaction = confirmed.transaction.transaction.decode()
+        .ok_or_else(|| format!("Failed to decode transaction {}", signature))?;
+
+    let mut account_keys = transaction.message.static_account_keys().to_vec();
+    let meta = confirmed.transaction.meta;
+    let error = meta.as_ref().and_then(|meta| meta.err.as_ref()).map(|err| err.to_string());
+    let log_messages: Option<Vec<String>> = meta.as_ref()
+        .and_then(|meta| meta.log_messages.clone().into());
+    let loaded_addresses: Option<UiLoadedAddresses> = meta
+        .and_then(|meta| meta.loaded_addresses.into());
+    if let Some(loaded_addresses) = loaded_addresses {
+        for address in loaded_addresses.writable.iter().chain(loaded_addresses.readonly.iter()) {
+            account_keys.push(address.parse()
+                .map_err(|_| format!("Invalid loaded address {}", address))?);
+        }
+    }
+
+    Ok(FetchedTransaction {
+        slot: confirmed.slot,
+        transaction,
+        account_keys,
+        error,
+        log_messages: log_messages.unwrap_or_default(),
+    })
+}
+
+/// Read the auditor lo/hi ciphertexts from confidential transfer instruction data
+fn auditor_ciphertexts(
+    instruction_data: &[u8],
+) -> Option<(PodElGamalCiphertext, PodElGamalCiphertext)> {
+    if !matches!(
+        TokenInstruction::unpack(instruction_data),
+        Ok(TokenInstruction::ConfidentialTransferExtension)
+    ) {
+        return None;
+    }
+
+    let extension_data = &instruction_data[1..];
+    match decode_instruction_type(extension_data).ok()? {
+        ConfidentialTransferInstruction::Transfer => {
+            let data = decode_instruction_data::<TransferInstructionData>(extension_data).ok()?;
+            Some((data.transfer_amount_auditor_ciphertext_lo, data.transfer_amount_auditor_ciphertext_hi))
+        }
+        ConfidentialTransferInstruction::TransferWithFee => {
+            let data = decode_instruction_data::<TransferWithFeeInstructionData>(extension_data).ok()?;
+            Some((data.transfer_amount_auditor_ciphertext_lo, data.transfer_amount_auditor_ciphertext_hi))
+        }
+        _ => None,
+    }
+}
+
+/// Decrypt a lo/hi ciphertext pair and recombine the amount
+pub(crate) fn decrypt_lo_hi(
+    elgamal_keypair: &ElGamalKeypair,
+    ciphertext_lo: &PodElGamalCiphertext,
+    ciphertext_hi: &PodElGamalCiphertext,
+) -> Option<u64> {
+    decryption::engine().decrypt_lo_hi(elgamal_keypair.secret(), ciphertext_lo, ciphertext_hi)
+}
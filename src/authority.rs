@@ -0,0 +1,111 @@
+//! Token account authorities: a single signer or an M-of-N multisig
+//!
+//! Token-2022 accepts an SPL multisig account as a token account owner;
+//! its instructions then list the signing members instead of the owner
+//! signing. `Authority` carries either form to the `_with_authority`
+//! operations. A multisig can't sign, so it can't derive ElGamal or AE
+//! keys: those operations always take a `KeyProvider`.
+
+use crate::types::*;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{account::Account, program_pack::Pack, pubkey::Pubkey, signature::Signer};
+use spl_token_2022::state::Multisig;
+
+/// Owner of a token account, with the keys signing for it
+#[derive(Clone, Copy)]
+pub enum Authority<'a> {
+    /// The owner signs itself
+    Signer(&'a dyn Signer),
+    /// An SPL Token-2022 multisig account, with at least M of its N signers
+    Multisig {
+        multisig: Pubkey,
+        signers: &'a [&'a dyn Signer],
+    },
+}
+
+impl<'a> Authority<'a> {
+    /// A multisig owner signed by `signers`
+    pub fn multisig(multisig: Pubkey, signers: &'a [&'a dyn Signer]) -> Self {
+        Self::Multisig { multisig, signers }
+    }
+
+    /// Owner of the token account
+    pub fn pubkey(&self) -> Pubkey {
+        match self {
+            Self::Signer(signer) => signer.pubkey(),
+            Self::Multisig { multisig, .. } => *multisig,
+        }
+    }
+
+    /// Multisig members signing, as passed to Token-2022 instructions (empty for a single signer)
+    pub fn multisig_signers(&self) -> Vec<Pubkey> {
+        match self {
+            Self::Signer(_) => Vec::new(),
+            Self::Multisig { signers, .. } => signers.iter().map(|signer| signer.pubkey()).collect(),
+        }
+    }
+
+    /// Keys that sign the transactions
+    pub fn signers(&self) -> Vec<&'a dyn Signer> {
+        match self {
+            Self::Signer(signer) => vec![*signer],
+            Self::Multisig { signers, .. } => signers.to_vec(),
+        }
+    }
+
+    /// Check a multisig authority's signers against the on-chain account
+    ///
+    /// Fails before anything is generated or sent when the account isn't
+    /// an initialized Token-2022 multisig, a signer isn't a member, or
+    /// fewer than M members sign. A single signer always passes.
+    pub fn check(&self, client: &RpcClient) -> CtResult<()> {
+        match self {
+            Self::Signer(_) => Ok(()),
+            Self::Multisig { multisig, .. } => self.check_multisig_account(&client.get_account(multisig)?),
+        }
+    }
+
+    /// Check already fetched multisig account data (see `check`)
+    pub fn check_multisig_account(&self, account: &Account) -> CtResult<()> {
+        let Self::Multisig { multisig, .. } = self else {
+            return Ok(());
+        };
+        if account.owner != spl_token_2022::id() {
+            return Err(format!("{} isn't a Token-2022 multisig account", multisig).into());
+        }
+        let state = Multisig::unpack(&account.data)
+            .map_err(|_| format!("{} isn't an initialized multisig account", multisig))?;
+        let members = &state.signers[..state.n as usize];
+
+        let mut signing = self.multisig_signers();
+        signing.sort();
+        signing.dedup();
+        if let Some(outsider) = signing.iter().find(|signer| !members.contains(signer)) {
+            return Err(format!("{} isn't a signer of multisig {}", outsider, multisig).into());
+        }
+        if signing.len() < state.m as usize {
+            return Err(format!(
+                "Multisig {} needs {} of {} signers, got {}",
+                multisig, state.m, state.n, signing.len()
+            ).into());
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a dyn Signer> for Authority<'a> {
+    fn from(signer: &'a dyn Signer) -> Self {
+        Self::Signer(signer)
+    }
+}
+
+/// `signers` without repeated keys, in order
+pub(crate) fn unique_signers<'a>(signers: &[&'a dyn Signer]) -> Vec<&'a dyn Signer> {
+    let mut unique: Vec<&dyn Signer> = Vec::new();
+    for signer in signers {
+        if unique.iter().all(|kept| kept.pubkey() != signer.pubkey()) {
+            unique.push(*signer);
+        }
+    }
+    unique
+}
@@ -0,0 +1,479 @@
+//! `conf-balances`: command line interface for the confidential token lifecycle
+//!
+//! Reads the RPC URL, commitment and default keypair from the Solana CLI
+//! config (`~/.config/solana/cli/config.yml`), like `solana` and `spl-token`.
+//! The default keypair pays fees and owns the token account.
+//!
+//! Usage:
+//! conf-balances create-mint --decimals 9 --auditor-keypair auditor.json
+//! conf-balances create-account <MINT>
+//! conf-balances configure <MINT>
+//! conf-balances deposit <MINT> <AMOUNT>
+//! conf-balances apply <MINT>
+//! conf-balances transfer <MINT> <RECIPIENT> <AMOUNT> [--create-recipient-account] [--memo <MEMO>]
+//! conf-balances withdraw <MINT> <AMOUNT>
+//! conf-balances balance <MINT> --output json
+//! conf-balances close <MINT>
+//! conf-balances audit --auditor-keypair auditor.json --mint <MINT>
+//!
+//! Amounts are in base units. `--dry-run` checks inputs and balances and
+//! prints what would be sent without sending anything.
+
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use conf_balances_examples::*;
+use serde_json::{json, Value};
+use solana_cli_config::{Config, CONFIG_FILE};
+use solana_client::rpc_client::RpcClient;
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    solana_zk_sdk::encryption::{elgamal::ElGamalKeypair, pod::elgamal::PodElGamalPubkey},
+    state::{Account as TokenAccount, Mint},
+};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Parser)]
+#[command(name = "conf-balances", version, about = "Confidential balances on Token-2022")]
+struct Cli {
+    /// Solana CLI config file [default: ~/.config/solana/cli/config.yml]
+    #[arg(short = 'C', long, global = true)]
+    config: Option<String>,
+
+    /// JSON RPC URL, overriding the config
+    #[arg(short = 'u', long, global = true)]
+    url: Option<String>,
+
+    /// Owner keypair (path, JSON bytes, base58, seed:<phrase>, ASK, remote://<address> with the
+    /// token in CT_REMOTE_SIGNER_TOKEN), overriding the config
+    #[arg(short = 'k', long, global = true)]
+    keypair: Option<String>,
+
+    /// Signer paying fees and rent, from any owner keypair source [default: owner keypair]
+    #[arg(long, global = true)]
+    fee_payer: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Display, global = true)]
+    output: OutputFormat,
+
+    /// Check inputs and print what would be sent, without sending
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Display,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a mint with confidential transfers enabled
+    CreateMint {
+        #[arg(long, default_value_t = 9)]
+        decimals: u8,
+        /// Mint keypair [default: new random keypair]
+        #[arg(long)]
+        mint_keypair: Option<String>,
+        /// Auditor ElGamal pubkey (base64)
+        #[arg(long, conflicts_with = "auditor_keypair")]
+        auditor: Option<String>,
+        /// Auditor ElGamal keypair file, generated if missing
+        #[arg(long)]
+        auditor_keypair: Option<PathBuf>,
+        /// Require the authority to approve new accounts
+        #[arg(long)]
+        no_auto_approve: bool,
+    },
+    /// Create the owner's associated token account
+    CreateAccount {
+        mint: Pubkey,
+        /// Account owner [default: keypair pubkey]
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+    /// Configure the token account for confidential transfers
+    Configure { mint: Pubkey },
+    /// Move public tokens into the pending confidential balance
+    Deposit {
+        mint: Pubkey,
+        amount: u64,
+        /// SPL Memo sent with the deposit
+        #[arg(long)]
+        memo: Option<String>,
+    },
+    /// Apply the pending balance to the available balance
+    Apply { mint: Pubkey },
+    /// Move available confidential tokens to the public balance
+    Withdraw {
+        mint: Pubkey,
+        amount: u64,
+        /// SPL Memo sent with the withdrawal
+        #[arg(long)]
+        memo: Option<String>,
+    },
+    /// Transfer confidentially to another owner's token account
+    Transfer {
+        mint: Pubkey,
+        /// Recipient wallet or destination token account
+        recipient: Pubkey,
+        amount: u64,
+        /// Create the recipient wallet's token account if missing (the
+        /// recipient must still configure it before receiving)
+        #[arg(long)]
+        create_recipient_account: bool,
+        /// Address lookup table for the transfer transaction (fits more
+        /// proofs inline)
+        #[arg(long)]
+        lookup_table: Option<Pubkey>,
+        /// SPL Memo sent with the transfer (required by memo-transfer recipients)
+        #[arg(long)]
+        memo: Option<String>,
+    },
+    /// Decrypt public, pending and available balances
+    Balance { mint: Pubkey },
+    /// Empty and close the token account
+    Close {
+        mint: Pubkey,
+        /// Account receiving the rent [default: keypair pubkey]
+        #[arg(long)]
+        recipient: Option<Pubkey>,
+    },
+    /// Decrypt transfer amounts with the auditor key
+    #[command(group(ArgGroup::new("target").required(true).args(["signature", "mint"])))]
+    Audit {
+        /// Auditor ElGamal keypair file
+        #[arg(long)]
+        auditor_keypair: PathBuf,
+        /// Audit a single transaction
+        #[arg(long)]
+        signature: Option<Signature>,
+        /// Audit every transfer of a mint
+        #[arg(long)]
+        mint: Option<Pubkey>,
+        /// Cache file to resume mint audits
+        #[arg(long, requires = "mint")]
+        cache: Option<PathBuf>,
+    },
+}
+
+/// Connection and keypairs resolved from flags and the CLI config
+struct Context {
+    client: RpcClient,
+    owner: Box<dyn Signer>,
+    /// Separate fee payer, if not the owner
+    fee_payer: Option<Box<dyn Signer>>,
+    dry_run: bool,
+}
+
+impl Context {
+    fn load(cli: &Cli) -> CtResult<Self> {
+        let config = match (&cli.config, CONFIG_FILE.as_ref()) {
+            (Some(path), _) => Config::load(path)
+                .map_err(|e| format!("Failed to load config {}: {}", path, e))?,
+            (None, Some(path)) => Config::load(path).unwrap_or_default(),
+            (None, None) => Config::default(),
+        };
+
+        let url = cli.url.clone().unwrap_or(config.json_rpc_url);
+        let commitment = CommitmentConfig::from_str(&config.commitment)
+            .unwrap_or_else(|_| CommitmentConfig::confirmed());
+
+        let owner = signer::load_signer(cli.keypair.as_deref().unwrap_or(&config.keypair_path))?;
+        let fee_payer = cli.fee_payer.as_deref().map(signer::load_signer).transpose()?;
+
+        Ok(Self {
+            client: RpcClient::new_with_commitment(url, commitment),
+            owner,
+            fee_payer,
+            dry_run: cli.dry_run,
+        })
+    }
+
+    fn fee_payer(&self) -> &dyn Signer {
+        self.fee_payer.as_deref().unwrap_or(self.owner.as_ref())
+    }
+
+    fn token_account(&self, mint: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(&self.owner.pubkey(), mint, &spl_token_2022::id())
+    }
+
+    fn decimals(&self, mint: &Pubkey) -> CtResult<u8> {
+        let mint_data = self.client.get_account(mint)?;
+        Ok(StateWithExtensions::<Mint>::unpack(&mint_data.data)?.base.decimals)
+    }
+
+    fn balances(&self, mint: &Pubkey) -> CtResult<watch::BalanceBreakdown> {
+        let token_account = self.token_account(mint);
+        watch::WatchOnlyAccount::from_provider(token_account, &key_provider::SignerKeyProvider::new(self.owner.as_ref()))?
+            .balances(&self.client)
+    }
+}
+
+/// Result of a dry run: what would have been sent
+fn plan(action: &str, details: Value) -> Value {
+    json!({ "dry_run": true, "action": action, "details": details })
+}
+
+/// Fail a dry run early if the available balance can't cover `amount`
+fn check_available(context: &Context, mint: &Pubkey, amount: u64) -> CtResult<watch::BalanceBreakdown> {
+    let balances = context.balances(mint)?;
+    if balances.available < amount {
+        return Err(format!("Insufficient available balance: have {}, need {}", balances.available, amount).into());
+    }
+    Ok(balances)
+}
+
+async fn run(cli: &Cli, context: &Context) -> CtResult<Value> {
+    let client = &context.client;
+    let payer = context.fee_payer();
+    let owner = context.owner.as_ref();
+    let authority = authority::Authority::Signer(owner);
+    let keys = key_provider::SignerKeyProvider::new(owner);
+
+    match &cli.command {
+        Command::CreateMint { decimals, mint_keypair, auditor, auditor_keypair, no_auto_approve } => {
+            let mint: Box<dyn Signer> = match mint_keypair {
+                Some(source) => signer::load_signer(source)?,
+                None => Box::new(Keypair::new()),
+            };
+            let auditor_pubkey = match (auditor, auditor_keypair) {
+                (Some(pubkey), _) => Some(PodElGamalPubkey::from_str(pubkey)
+                    .map_err(|_| "Invalid auditor ElGamal pubkey")?),
+                (None, Some(path)) if path.exists() => Some((*ElGamalKeypair::read_json_file(path)?.pubkey()).into()),
+                (None, Some(path)) => {
+                    let keypair = ElGamalKeypair::new_rand();
+                    if !context.dry_run {
+                        keypair.write_json_file(path)?;
+                    }
+                    Some((*keypair.pubkey()).into())
+                }
+                (None, None) => None,
+            };
+            let details = json!({
+                "mint": mint.pubkey().to_string(),
+                "decimals": decimals,
+                "auditor_elgamal_pubkey": auditor_pubkey.map(|pubkey| pubkey.to_string()),
+                "auto_approve_new_accounts": !no_auto_approve,
+            });
+            if context.dry_run {
+                return Ok(plan("create_mint", details));
+            }
+            let receipt = accounts::create_confidential_mint(
+                client, payer, mint.as_ref(), &owner.pubkey(), *decimals, auditor_pubkey, !no_auto_approve,
+            ).await?;
+            Ok(json!({ "mint": mint.pubkey().to_string(), "receipt": receipt }))
+        }
+        Command::CreateAccount { mint, owner: account_owner } => {
+            let account_owner = account_owner.unwrap_or(owner.pubkey());
+            let token_account = get_associated_token_address_with_program_id(&account_owner, mint, &spl_token_2022::id());
+            if context.dry_run {
+                return Ok(plan("create_account", json!({
+                    "token_account": token_account.to_string(),
+                    "exists": client.get_account(&token_account).is_ok(),
+                })));
+            }
+            let receipt = accounts::create_token_account(client, payer, &account_owner, mint).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Configure { mint } => {
+            let token_account = context.token_account(mint);
+            if context.dry_run {
+                let account_data = client.get_account(&token_account)?;
+                let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+                return Ok(plan("configure", json!({
+                    "token_account": token_account.to_string(),
+                    "already_configured": account.get_extension::<ConfidentialTransferAccount>().is_ok(),
+                })));
+            }
+            let receipt = configure::configure_account_with_authority(client, payer, &authority, mint, &keys).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Deposit { mint, amount, memo } => {
+            let decimals = context.decimals(mint)?;
+            if context.dry_run {
+                let balances = context.balances(mint)?;
+                if balances.public < *amount {
+                    return Err(format!("Insufficient public balance: have {}, need {}", balances.public, amount).into());
+                }
+                return Ok(plan("deposit", json!({ "token_account": balances.token_account.to_string(), "amount": amount })));
+            }
+            let receipt = deposit::deposit_with_authority(
+                client, payer, &authority, mint, *amount, decimals, &keys, memo.as_deref(),
+            ).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Apply { mint } => {
+            if context.dry_run {
+                let balances = context.balances(mint)?;
+                return Ok(plan("apply", json!({ "token_account": balances.token_account.to_string(), "pending": balances.pending })));
+            }
+            let receipt = apply_pending::apply_pending_balance_with_authority(client, payer, &authority, mint, &keys).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Withdraw { mint, amount, memo } => {
+            let decimals = context.decimals(mint)?;
+            if context.dry_run {
+                let balances = check_available(context, mint, *amount)?;
+                return Ok(plan("withdraw", json!({ "token_account": balances.token_account.to_string(), "amount": amount })));
+            }
+            let receipt = withdraw::withdraw_with_authority(
+                client,
+                payer,
+                &authority,
+                mint,
+                *amount,
+                decimals,
+                &keys,
+                &proof_placement::WithdrawOptions { placements: None, memo: memo.clone() },
+            ).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Transfer { mint, recipient, amount, create_recipient_account, lookup_table, memo } => {
+            if context.dry_run {
+                let balances = check_available(context, mint, *amount)?;
+                let destination = recipient::validate_recipient(client, mint, recipient)?;
+                destination.check_memo(memo.as_deref())?;
+                return Ok(plan("transfer", json!({
+                    "source": balances.token_account.to_string(),
+                    "destination": destination.token_account.to_string(),
+                    "amount": amount,
+                    "memo": memo,
+                })));
+            }
+            if *create_recipient_account {
+                recipient::ensure_recipient_account(client, payer, mint, recipient).await?;
+            }
+            let options = proof_placement::TransferOptions {
+                placements: None,
+                lookup_table: *lookup_table,
+                memo: memo.clone(),
+            };
+            let receipt = transfer::transfer_with_authority(client, payer, &authority, mint, recipient, *amount, &keys, &options).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Balance { mint } => Ok(serde_json::to_value(context.balances(mint)?)?),
+        Command::Close { mint, recipient } => {
+            let recipient = recipient.unwrap_or(owner.pubkey());
+            if context.dry_run {
+                let balances = context.balances(mint)?;
+                if balances.total > 0 {
+                    return Err(format!("Account still holds {} tokens", balances.total).into());
+                }
+                return Ok(plan("close", json!({
+                    "token_account": balances.token_account.to_string(),
+                    "rent_recipient": recipient.to_string(),
+                })));
+            }
+            let receipt = accounts::close_confidential_account(client, payer, owner, mint, &recipient).await?;
+            Ok(serde_json::to_value(receipt)?)
+        }
+        Command::Audit { auditor_keypair, signature, mint, cache } => {
+            let auditor = ElGamalKeypair::read_json_file(auditor_keypair)?;
+            if let Some(signature) = signature {
+                let records = auditor::audit_transaction(client, &auditor, signature)?;
+                return Ok(json!(records.iter().map(|record| json!({
+                    "signature": record.signature.to_string(),
+                    "slot": record.slot,
+                    "source": record.source.to_string(),
+                    "destination": record.destination.to_string(),
+                    "amount": record.amount,
+                })).collect::<Vec<_>>()));
+            }
+            let mut config = audit_report::AuditReportConfig::new(mint.ok_or("--mint is required")?);
+            config.cache_path = cache.clone();
+            let report = audit_report::generate_audit_report(client, &auditor, &config)?;
+            Ok(serde_json::from_str(&report.to_json()?)?)
+        }
+    }
+}
+
+/// Print a JSON value as indented `key: value` lines
+fn print_display(value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::Object(_) | Value::Array(_) => {
+                        println!("{}{}:", pad, key);
+                        print_display(value, indent + 1);
+                    }
+                    _ => println!("{}{}: {}", pad, key, scalar(value)),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::Object(_) | Value::Array(_) => {
+                        println!("{}-", pad);
+                        print_display(item, indent + 1);
+                    }
+                    _ => println!("{}- {}", pad, scalar(item)),
+                }
+            }
+        }
+        _ => println!("{}{}", pad, scalar(value)),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    // JSON output stays machine-readable: progress is only printed for display
+    if cli.output == OutputFormat::Display {
+        events::print_events();
+    }
+
+    let result = match Context::load(&cli) {
+        Ok(context) => run(&cli, &context).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(value) => match cli.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&value).unwrap()),
+            OutputFormat::Display => print_display(&value, 0),
+        },
+        Err(e) => {
+            match cli.output {
+                OutputFormat::Json => {
+                    // Typed details so scripts can tell why an operation wasn't sent
+                    let mut error = json!({ "error": e.to_string() });
+                    if let Some(invalid) = e.downcast_ref::<recipient::InvalidRecipient>() {
+                        error["recipient"] = json!(invalid);
+                    }
+                    if let Some(invalid) = e.downcast_ref::<verification::InvalidProof>() {
+                        error["proof"] = json!(invalid);
+                    }
+                    eprintln!("{}", error);
+                }
+                OutputFormat::Display => eprintln!("❌ {}", e),
+            }
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,6 +1,11 @@
 //! Configure a token account for confidential transfers
 
+use crate::authority::{unique_signers, Authority};
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
 use crate::types::*;
+use crate::verification::verify_configure_proof;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
     signature::Signer,
@@ -10,13 +15,10 @@ use spl_associated_token_account::get_associated_token_address_with_program_id;
 use spl_token_2022::{
     extension::{
         confidential_transfer::instruction::{configure_account, PubkeyValidityProofData},
-        ExtensionType,
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
     },
     instruction::reallocate,
-    solana_zk_sdk::encryption::{
-        auth_encryption::AeKey,
-        elgamal::ElGamalKeypair,
-    },
+    state::Account as TokenAccount,
 };
 use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
 
@@ -24,30 +26,70 @@ use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation
 ///
 /// Steps:
 /// 1. Reallocate account space for ConfidentialTransferAccount extension
+///    (plus ConfidentialTransferFeeAmount for mints with transfer fees)
 /// 2. Derive ElGamal and AES keys from account authority
-/// 3. Generate pubkey validity proof
+/// 3. Generate pubkey validity proof and verify it locally
 /// 4. Configure account with proof
 pub async fn configure_account_for_confidential_transfers(
     client: &RpcClient,
     payer: &dyn Signer,
     authority: &dyn Signer,
     mint: &solana_sdk::pubkey::Pubkey,
+) -> ReceiptResult {
+    configure_account_with_keys(client, payer, authority, mint, &SignerKeyProvider::new(authority)).await
+}
+
+/// Configure a token account for confidential transfers with explicit keys
+///
+/// Same as `configure_account_for_confidential_transfers`, but with the
+/// ElGamal and AES keys supplied by the caller instead of derived from the
+/// authority (e.g. after a key rotation).
+pub async fn configure_account_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    configure_account_with_authority(client, payer, &Authority::Signer(authority), mint, keys).await
+}
+
+/// Configure the token account of a single-signer or multisig authority
+///
+/// Same as `configure_account_with_keys`; a multisig's signers are checked
+/// against the multisig account before sending. The keys must come from
+/// the provider, as a multisig can't derive them.
+pub async fn configure_account_with_authority(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    let signature = send_configure(client, payer, authority, mint, keys)?;
+    let token_account = get_associated_token_address_with_program_id(&authority.pubkey(), mint, &spl_token_2022::id());
+    let sent = SentTransactions::single(TransactionRole::Configure, signature, &[]);
+    Ok(fetch_receipt(client, Operation::Configure, Some(&token_account), &sent, Some(keys)))
+}
+
+#[tracing::instrument(skip_all, fields(authority = %authority.pubkey(), %mint))]
+fn send_configure(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    keys: &dyn KeyProvider,
 ) -> SigResult {
+    authority.check(client)?;
     let token_account = get_associated_token_address_with_program_id(
         &authority.pubkey(),
         mint,
         &spl_token_2022::id(),
     );
 
-    // Derive encryption keys deterministically from authority
-    let elgamal_keypair = ElGamalKeypair::new_from_signer(
-        authority,
-        &token_account.to_bytes(),
-    )?;
-    let aes_key = AeKey::new_from_signer(
-        authority,
-        &token_account.to_bytes(),
-    )?;
+    // Fetch encryption keys from the provider
+    let elgamal_keypair = keys.elgamal_keypair(&token_account)?;
+    let aes_key = keys.aes_key(&token_account)?;
 
     // Maximum pending deposits before apply_pending_balance must be called
     let max_pending_balance_credit_counter = 65536u64;
@@ -58,6 +100,7 @@ pub async fn configure_account_for_confidential_transfers(
     // Generate proof that we control the ElGamal public key
     let proof_data = PubkeyValidityProofData::new(&elgamal_keypair)
         .map_err(|_| "Failed to generate pubkey validity proof")?;
+    verify_configure_proof(&proof_data)?;
 
     // Proof will be in the next instruction (offset 1)
     let proof_location = ProofLocation::InstructionOffset(
@@ -65,7 +108,17 @@ pub async fn configure_account_for_confidential_transfers(
         &proof_data,
     );
 
+    // Accounts of fee-bearing mints also need room for encrypted withheld fees
+    let account_data = client.get_account(&token_account)?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+    let mut extension_types = vec![ExtensionType::ConfidentialTransferAccount];
+    if account.get_extension_types()?.contains(&ExtensionType::TransferFeeAmount) {
+        extension_types.push(ExtensionType::ConfidentialTransferFeeAmount);
+    }
+
     // Build instructions
+    let multisig_signers = authority.multisig_signers();
+    let multisig_signers: Vec<_> = multisig_signers.iter().collect();
     let mut instructions = vec![];
 
     // 1. Reallocate to add ConfidentialTransferAccount extension
@@ -74,8 +127,8 @@ pub async fn configure_account_for_confidential_transfers(
         &token_account,
         &payer.pubkey(),
         &authority.pubkey(),
-        &[&authority.pubkey()],
-        &[ExtensionType::ConfidentialTransferAccount],
+        &multisig_signers,
+        &extension_types,
     )?);
 
     // 2. Configure account (includes proof instruction)
@@ -86,21 +139,23 @@ pub async fn configure_account_for_confidential_transfers(
         &decryptable_balance.into(),
         max_pending_balance_credit_counter,
         &authority.pubkey(),
-        &[],
+        &multisig_signers,
         proof_location,
     )?);
 
     // Send transaction
     let recent_blockhash = client.get_latest_blockhash()?;
+    let mut signers = vec![payer];
+    signers.extend(authority.signers());
     let transaction = Transaction::new_signed_with_payer(
         &instructions,
         Some(&payer.pubkey()),
-        &[authority, payer],
+        &unique_signers(&signers),
         recent_blockhash,
     );
 
-    let signature = client.send_and_confirm_transaction(&transaction)?;
-    println!("✅ Account configured for confidential transfers: {}", signature);
+    let signature = events::send_and_confirm(client, Operation::Configure, &transaction)?;
+    events::completed(Operation::Configure, format!("Account configured for confidential transfers: {}", signature));
 
     Ok(signature)
 }
@@ -0,0 +1,1020 @@
+//! Decoder and pretty-printer for confidential transfer transactions
+//!
+//! Parses the Token-2022 confidential transfer instructions and the ZK ElGamal
+//! proof program instructions of a transaction into typed operations,
+//! including where each proof lives (instruction offset or context state
+//! account), the proof types and the ciphertexts they carry.
+//!
+//! Decoded transactions render as indented text through `Display` and as
+//! JSON through `to_json`. Ciphertexts, commitments and ElGamal pubkeys are
+//! shown base64-encoded.
+
+use crate::auditor::fetch_transaction;
+use crate::types::*;
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::VersionedTransaction,
+};
+use spl_token_2022::{
+    extension::{
+        confidential_mint_burn::instruction::ConfidentialMintBurnInstruction,
+        confidential_transfer::instruction::{
+            ApplyPendingBalanceData, ConfidentialTransferInstruction, ConfigureAccountInstructionData,
+            DepositInstructionData, EmptyAccountInstructionData, TransferInstructionData,
+            TransferWithFeeInstructionData, WithdrawInstructionData,
+        },
+        confidential_transfer_fee::instruction::ConfidentialTransferFeeInstruction,
+    },
+    instruction::{decode_instruction_data, decode_instruction_type, TokenInstruction},
+    solana_zk_sdk::zk_elgamal_proof_program::{
+        self,
+        instruction::ProofInstruction,
+        proof_data::{
+            BatchedGroupedCiphertext2HandlesValidityProofContext,
+            BatchedGroupedCiphertext2HandlesValidityProofData,
+            BatchedGroupedCiphertext3HandlesValidityProofContext,
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofContext,
+            BatchedRangeProofU128Data, BatchedRangeProofU256Data, BatchedRangeProofU64Data,
+            CiphertextCiphertextEqualityProofContext, CiphertextCiphertextEqualityProofData,
+            CiphertextCommitmentEqualityProofContext, CiphertextCommitmentEqualityProofData,
+            GroupedCiphertext2HandlesValidityProofContext, GroupedCiphertext2HandlesValidityProofData,
+            GroupedCiphertext3HandlesValidityProofContext, GroupedCiphertext3HandlesValidityProofData,
+            PercentageWithCapProofContext, PercentageWithCapProofData, PubkeyValidityProofContext,
+            PubkeyValidityProofData, ZeroCiphertextProofContext, ZeroCiphertextProofData,
+        },
+    },
+};
+use std::fmt;
+
+/// Length of proof instruction data that reads the proof from an account
+/// (instruction type byte followed by a `u32` offset into the account)
+const PROOF_FROM_ACCOUNT_DATA_LEN: usize = 5;
+
+/// A decoded transaction
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedTransaction {
+    /// Transaction signature, if decoded from the chain
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub signature: Option<Signature>,
+    /// Slot the transaction landed in, if decoded from the chain
+    pub slot: Option<u64>,
+    /// Transaction error, if it failed
+    pub error: Option<String>,
+    /// Program log messages
+    pub log_messages: Vec<String>,
+    /// Top-level instructions in order
+    pub instructions: Vec<DecodedInstruction>,
+}
+
+/// A decoded top-level instruction
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedInstruction {
+    /// Position of the instruction in the transaction
+    pub index: usize,
+    #[serde_as(as = "DisplayFromStr")]
+    pub program_id: Pubkey,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub accounts: Vec<Pubkey>,
+    pub operation: Operation,
+}
+
+/// What an instruction does
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Token-2022 confidential transfer extension instruction
+    ConfidentialTransfer(ConfidentialTransferOperation),
+    /// ZK ElGamal proof program instruction
+    ZkElGamalProof(ProofOperation),
+    /// Any other Token-2022 instruction
+    Token { instruction: String },
+    /// Instruction of an unrelated program
+    Unknown,
+    /// Instruction of a known program that failed to decode
+    Invalid { error: String },
+}
+
+/// Where a Token-2022 instruction reads one of its proofs from
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "location", rename_all = "snake_case")]
+pub enum ProofReference {
+    /// Proof verified by another instruction of the same transaction
+    InstructionOffset {
+        offset: i8,
+        /// Index of the referenced instruction, if in range
+        instruction_index: Option<usize>,
+    },
+    /// Proof verified earlier and stored in a context state account
+    ContextStateAccount {
+        #[serde_as(as = "DisplayFromStr")]
+        account: Pubkey,
+    },
+}
+
+/// A decoded Token-2022 confidential transfer instruction
+///
+/// Encrypted balances are AE ciphertexts under the owner's AES key; auditor
+/// ciphertexts are ElGamal ciphertexts under the mint's auditor pubkey.
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "instruction", rename_all = "snake_case")]
+pub enum ConfidentialTransferOperation {
+    ConfigureAccount {
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        mint: Pubkey,
+        decryptable_zero_balance: String,
+        maximum_pending_balance_credit_counter: u64,
+        pubkey_validity_proof: ProofReference,
+    },
+    EmptyAccount {
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+        zero_ciphertext_proof: ProofReference,
+    },
+    Deposit {
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        mint: Pubkey,
+        amount: u64,
+        decimals: u8,
+    },
+    Withdraw {
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        mint: Pubkey,
+        amount: u64,
+        decimals: u8,
+        new_decryptable_available_balance: String,
+        equality_proof: ProofReference,
+        range_proof: ProofReference,
+    },
+    Transfer {
+        #[serde_as(as = "DisplayFromStr")]
+        source: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        mint: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        destination: Pubkey,
+        new_source_decryptable_available_balance: String,
+        auditor_ciphertext_lo: String,
+        auditor_ciphertext_hi: String,
+        equality_proof: ProofReference,
+        ciphertext_validity_proof: ProofReference,
+        range_proof: ProofReference,
+    },
+    TransferWithFee {
+        #[serde_as(as = "DisplayFromStr")]
+        source: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        mint: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        destination: Pubkey,
+        new_source_decryptable_available_balance: String,
+        auditor_ciphertext_lo: String,
+        auditor_ciphertext_hi: String,
+        equality_proof: ProofReference,
+        ciphertext_validity_proof: ProofReference,
+        fee_sigma_proof: ProofReference,
+        fee_ciphertext_validity_proof: ProofReference,
+        range_proof: ProofReference,
+    },
+    ApplyPendingBalance {
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+        expected_pending_balance_credit_counter: u64,
+        new_decryptable_available_balance: String,
+    },
+    /// Any other confidential transfer instruction (mint setup, approvals, credit toggles)
+    Other {
+        name: String,
+    },
+}
+
+/// A decoded ZK ElGamal proof program instruction
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "instruction", rename_all = "snake_case")]
+pub enum ProofOperation {
+    /// Verify a proof, optionally storing its context in a context state account
+    Verify {
+        proof_type: String,
+        source: ProofSource,
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        context_state: Option<Pubkey>,
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        context_state_authority: Option<Pubkey>,
+        /// Public context of the proof, when the proof is in the instruction data
+        context: Option<ProofContextData>,
+    },
+    /// Close a context state account and reclaim its rent
+    CloseContextState {
+        #[serde_as(as = "DisplayFromStr")]
+        context_state: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        destination: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        authority: Pubkey,
+    },
+}
+
+/// Where a proof verification instruction reads the proof from
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum ProofSource {
+    /// Proof embedded in the instruction data
+    InstructionData,
+    /// Proof stored in an account (e.g. a record account) at a byte offset
+    Account {
+        #[serde_as(as = "DisplayFromStr")]
+        account: Pubkey,
+        offset: u32,
+    },
+}
+
+/// Public context of a proof
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProofContextData {
+    ZeroCiphertext {
+        pubkey: String,
+        ciphertext: String,
+    },
+    CiphertextCiphertextEquality {
+        first_pubkey: String,
+        second_pubkey: String,
+        first_ciphertext: String,
+        second_ciphertext: String,
+    },
+    CiphertextCommitmentEquality {
+        pubkey: String,
+        ciphertext: String,
+        commitment: String,
+    },
+    PubkeyValidity {
+        pubkey: String,
+    },
+    PercentageWithCap {
+        percentage_commitment: String,
+        delta_commitment: String,
+        claimed_commitment: String,
+        max_value: u64,
+    },
+    /// Range proof over the commitments with a non-zero bit length
+    BatchedRange {
+        commitments: Vec<String>,
+        bit_lengths: Vec<u8>,
+    },
+    /// Validity of grouped ciphertexts (batched proofs carry a lo and hi ciphertext)
+    GroupedCiphertextValidity {
+        pubkeys: Vec<String>,
+        grouped_ciphertexts: Vec<String>,
+    },
+}
+
+impl DecodedTransaction {
+    /// Serialize the decoded transaction as pretty-printed JSON
+    pub fn to_json(&self) -> CtResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Decode a confirmed transaction by signature
+///
+/// Account keys loaded from address lookup tables are resolved.
+pub fn decode_signature(client: &RpcClient, signature: &Signature) -> CtResult<DecodedTransaction> {
+    let fetched = fetch_transaction(client, signature)?;
+
+    let mut decoded = decode_transaction(&fetched.transaction, &fetched.account_keys);
+    decoded.signature = Some(*signature);
+    decoded.slot = Some(fetched.slot);
+    decoded.error = fetched.error;
+    decoded.log_messages = fetched.log_messages;
+    Ok(decoded)
+}
+
+/// Decode a bincode-serialized transaction (legacy or versioned)
+///
+/// Transactions using address lookup tables can't be resolved offline;
+/// decode those by signature instead.
+pub fn decode_transaction_bytes(bytes: &[u8]) -> CtResult<DecodedTransaction> {
+    let transaction: VersionedTransaction = bincode::deserialize(bytes)
+        .map_err(|e| format!("Failed to deserialize transaction: {}", e))?;
+
+    if transaction.message.address_table_lookups().is_some_and(|lookups| !lookups.is_empty()) {
+        return Err("Transaction uses address lookup tables; decode it by signature instead".into());
+    }
+
+    let account_keys = transaction.message.static_account_keys().to_vec();
+    Ok(decode_transaction(&transaction, &account_keys))
+}
+
+/// Decode the top-level instructions of a transaction
+///
+/// `account_keys` must be the transaction's resolved account keys (static
+/// keys followed by any address lookup table keys). Instructions that fail
+/// to decode are reported as `Operation::Invalid` rather than failing the
+/// whole transaction.
+pub fn decode_transaction(
+    transaction: &VersionedTransaction,
+    account_keys: &[Pubkey],
+) -> DecodedTransaction {
+    let instructions = transaction.message.instructions();
+    let instruction_count = instructions.len();
+
+    let instructions = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            let program_id = account_keys.get(instruction.program_id_index as usize)
+                .copied()
+                .unwrap_or_default();
+            let accounts: Option<Vec<Pubkey>> = instruction.accounts
+                .iter()
+                .map(|account| account_keys.get(*account as usize).copied())
+                .collect();
+
+            let operation = match accounts.as_deref() {
+                None => Operation::Invalid { error: "Account index out of range".to_string() },
+                Some(accounts) if program_id == spl_token_2022::id() => {
+                    decode_token_instruction(index, instruction_count, accounts, &instruction.data)
+                }
+                Some(accounts) if program_id == zk_elgamal_proof_program::id() => {
+                    decode_proof_instruction(accounts, &instruction.data)
+                }
+                Some(_) => Operation::Unknown,
+            };
+
+            DecodedInstruction {
+                index,
+                program_id,
+                accounts: accounts.unwrap_or_default(),
+                operation,
+            }
+        })
+        .collect();
+
+    DecodedTransaction {
+        signature: None,
+        slot: None,
+        error: None,
+        log_messages: Vec::new(),
+        instructions,
+    }
+}
+
+/// Decode a Token-2022 instruction
+fn decode_token_instruction(
+    index: usize,
+    instruction_count: usize,
+    accounts: &[Pubkey],
+    data: &[u8],
+) -> Operation {
+    let instruction = match TokenInstruction::unpack(data) {
+        Ok(instruction) => instruction,
+        Err(e) => return Operation::Invalid { error: e.to_string() },
+    };
+
+    let extension_data = &data[1..];
+    match instruction {
+        TokenInstruction::ConfidentialTransferExtension => {
+            match decode_confidential_transfer(index, instruction_count, accounts, extension_data) {
+                Ok(operation) => Operation::ConfidentialTransfer(operation),
+                Err(e) => Operation::Invalid { error: e.to_string() },
+            }
+        }
+        TokenInstruction::ConfidentialTransferFeeExtension => Operation::Token {
+            instruction: match decode_instruction_type::<ConfidentialTransferFeeInstruction>(extension_data) {
+                Ok(fee_instruction) => format!("ConfidentialTransferFee::{:?}", fee_instruction),
+                Err(_) => "ConfidentialTransferFee".to_string(),
+            },
+        },
+        TokenInstruction::ConfidentialMintBurnExtension => Operation::Token {
+            instruction: match decode_instruction_type::<ConfidentialMintBurnInstruction>(extension_data) {
+                Ok(mint_burn_instruction) => format!("ConfidentialMintBurn::{:?}", mint_burn_instruction),
+                Err(_) => "ConfidentialMintBurn".to_string(),
+            },
+        },
+        other => Operation::Token { instruction: format!("{:?}", other) },
+    }
+}
+
+/// Decode a confidential transfer extension instruction (without the extension prefix byte)
+fn decode_confidential_transfer(
+    index: usize,
+    instruction_count: usize,
+    accounts: &[Pubkey],
+    extension_data: &[u8],
+) -> CtResult<ConfidentialTransferOperation> {
+    let account = |position: usize| -> CtResult<Pubkey> {
+        Ok(*accounts.get(position).ok_or("Instruction is missing accounts")?)
+    };
+    let proofs = |first_position: usize, offsets: &[i8]| {
+        proof_references(index, instruction_count, accounts, first_position, offsets)
+    };
+
+    let operation = match decode_instruction_type(extension_data)? {
+        // Accounts: [token_account, mint, (sysvar | context), owner]
+        ConfidentialTransferInstruction::ConfigureAccount => {
+            let data = decode_instruction_data::<ConfigureAccountInstructionData>(extension_data)?;
+            let [pubkey_validity_proof] = proofs(2, &[data.proof_instruction_offset])?.try_into()
+                .map_err(|_| "Unexpected proof count")?;
+            ConfidentialTransferOperation::ConfigureAccount {
+                token_account: account(0)?,
+                mint: account(1)?,
+                decryptable_zero_balance: data.decryptable_zero_balance.to_string(),
+                maximum_pending_balance_credit_counter: data.maximum_pending_balance_credit_counter.into(),
+                pubkey_validity_proof,
+            }
+        }
+        // Accounts: [token_account, (sysvar | context), owner]
+        ConfidentialTransferInstruction::EmptyAccount => {
+            let data = decode_instruction_data::<EmptyAccountInstructionData>(extension_data)?;
+            let [zero_ciphertext_proof] = proofs(1, &[data.proof_instruction_offset])?.try_into()
+                .map_err(|_| "Unexpected proof count")?;
+            ConfidentialTransferOperation::EmptyAccount {
+                token_account: account(0)?,
+                zero_ciphertext_proof,
+            }
+        }
+        // Accounts: [token_account, mint, owner]
+        ConfidentialTransferInstruction::Deposit => {
+            let data = decode_instruction_data::<DepositInstructionData>(extension_data)?;
+            ConfidentialTransferOperation::Deposit {
+                token_account: account(0)?,
+                mint: account(1)?,
+                amount: data.amount.into(),
+                decimals: data.decimals,
+            }
+        }
+        // Accounts: [token_account, mint, (sysvar), (contexts), owner]
+        ConfidentialTransferInstruction::Withdraw => {
+            let data = decode_instruction_data::<WithdrawInstructionData>(extension_data)?;
+            let [equality_proof, range_proof] = proofs(2, &[
+                data.equality_proof_instruction_offset,
+                data.range_proof_instruction_offset,
+            ])?.try_into().map_err(|_| "Unexpected proof count")?;
+            ConfidentialTransferOperation::Withdraw {
+                token_account: account(0)?,
+                mint: account(1)?,
+                amount: data.amount.into(),
+                decimals: data.decimals,
+                new_decryptable_available_balance: data.new_decryptable_available_balance.to_string(),
+                equality_proof,
+                range_proof,
+            }
+        }
+        // Accounts: [source, mint, destination, (sysvar), (contexts), owner]
+        ConfidentialTransferInstruction::Transfer => {
+            let data = decode_instruction_data::<TransferInstructionData>(extension_data)?;
+            let [equality_proof, ciphertext_validity_proof, range_proof] = proofs(3, &[
+                data.equality_proof_instruction_offset,
+                data.ciphertext_validity_proof_instruction_offset,
+                data.range_proof_instruction_offset,
+            ])?.try_into().map_err(|_| "Unexpected proof count")?;
+            ConfidentialTransferOperation::Transfer {
+                source: account(0)?,
+                mint: account(1)?,
+                destination: account(2)?,
+                new_source_decryptable_available_balance: data.new_source_decryptable_available_balance.to_string(),
+                auditor_ciphertext_lo: data.transfer_amount_auditor_ciphertext_lo.to_string(),
+                auditor_ciphertext_hi: data.transfer_amount_auditor_ciphertext_hi.to_string(),
+                equality_proof,
+                ciphertext_validity_proof,
+                range_proof,
+            }
+        }
+        // Accounts: [source, mint, destination, (sysvar), (contexts), owner]
+        ConfidentialTransferInstruction::TransferWithFee => {
+            let data = decode_instruction_data::<TransferWithFeeInstructionData>(extension_data)?;
+            let [equality_proof, ciphertext_validity_proof, fee_sigma_proof, fee_ciphertext_validity_proof, range_proof] =
+                proofs(3, &[
+                    data.equality_proof_instruction_offset,
+                    data.transfer_amount_ciphertext_validity_proof_instruction_offset,
+                    data.fee_sigma_proof_instruction_offset,
+                    data.fee_ciphertext_validity_proof_instruction_offset,
+                    data.range_proof_instruction_offset,
+                ])?.try_into().map_err(|_| "Unexpected proof count")?;
+            ConfidentialTransferOperation::TransferWithFee {
+                source: account(0)?,
+                mint: account(1)?,
+                destination: account(2)?,
+                new_source_decryptable_available_balance: data.new_source_decryptable_available_balance.to_string(),
+                auditor_ciphertext_lo: data.transfer_amount_auditor_ciphertext_lo.to_string(),
+                auditor_ciphertext_hi: data.transfer_amount_auditor_ciphertext_hi.to_string(),
+                equality_proof,
+                ciphertext_validity_proof,
+                fee_sigma_proof,
+                fee_ciphertext_validity_proof,
+                range_proof,
+            }
+        }
+        // Accounts: [token_account, owner]
+        ConfidentialTransferInstruction::ApplyPendingBalance => {
+            let data = decode_instruction_data::<ApplyPendingBalanceData>(extension_data)?;
+            ConfidentialTransferOperation::ApplyPendingBalance {
+                token_account: account(0)?,
+                expected_pending_balance_credit_counter: data.expected_pending_balance_credit_counter.into(),
+                new_decryptable_available_balance: data.new_decryptable_available_balance.to_string(),
+            }
+        }
+        other => ConfidentialTransferOperation::Other {
+            name: format!("{:?}", other),
+        },
+    };
+
+    Ok(operation)
+}
+
+/// Resolve where each proof of a Token-2022 instruction lives
+///
+/// The instructions sysvar follows the fixed accounts when any proof is
+/// referenced by offset, and context state accounts follow in proof order.
+fn proof_references(
+    index: usize,
+    instruction_count: usize,
+    accounts: &[Pubkey],
+    first_position: usize,
+    offsets: &[i8],
+) -> CtResult<Vec<ProofReference>> {
+    let has_sysvar = offsets.iter().any(|offset| *offset != 0);
+    let mut position = first_position + usize::from(has_sysvar);
+
+    offsets
+        .iter()
+        .map(|&offset| {
+            if offset != 0 {
+                let instruction_index = usize::try_from(index as i64 + offset as i64).ok()
+                    .filter(|target| *target < instruction_count);
+                return Ok(ProofReference::InstructionOffset { offset, instruction_index });
+            }
+            let account = *accounts.get(position).ok_or("Instruction is missing proof context accounts")?;
+            position += 1;
+            Ok(ProofReference::ContextStateAccount { account })
+        })
+        .collect()
+}
+
+/// Decode a ZK ElGamal proof program instruction
+///
+/// Accounts:
+/// - Proof in instruction data: [(context_state, context_state_authority)]
+/// - Proof in an account: [proof_account, (context_state, context_state_authority)]
+/// - Close: [context_state, destination, authority]
+fn decode_proof_instruction(accounts: &[Pubkey], data: &[u8]) -> Operation {
+    let Some(proof_instruction) = ProofInstruction::instruction_type(data) else {
+        return Operation::Invalid { error: "Unknown proof instruction".to_string() };
+    };
+
+    if proof_instruction == ProofInstruction::CloseContextState {
+        let [context_state, destination, authority, ..] = *accounts else {
+            return Operation::Invalid { error: "CloseContextState is missing accounts".to_string() };
+        };
+        return Operation::ZkElGamalProof(ProofOperation::CloseContextState {
+            context_state,
+            destination,
+            authority,
+        });
+    }
+
+    let (source, context_accounts) = if data.len() == PROOF_FROM_ACCOUNT_DATA_LEN {
+        let Some((account, context_accounts)) = accounts.split_first() else {
+            return Operation::Invalid { error: "Proof account is missing".to_string() };
+        };
+        let offset = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+        (ProofSource::Account { account: *account, offset }, context_accounts)
+    } else {
+        (ProofSource::InstructionData, accounts)
+    };
+
+    let context = match source {
+        ProofSource::InstructionData => proof_context(proof_instruction, data),
+        ProofSource::Account { .. } => None,
+    };
+
+    Operation::ZkElGamalProof(ProofOperation::Verify {
+        proof_type: format!("{:?}", proof_instruction),
+        source,
+        context_state: context_accounts.first().copied(),
+        context_state_authority: context_accounts.get(1).copied(),
+        context,
+    })
+}
+
+/// Read the public context of a proof embedded in instruction data
+fn proof_context(proof_instruction: ProofInstruction, data: &[u8]) -> Option<ProofContextData> {
+    match proof_instruction {
+        ProofInstruction::VerifyZeroCiphertext => {
+            let proof = ProofInstruction::proof_data::<ZeroCiphertextProofData, ZeroCiphertextProofContext>(data)?;
+            Some(ProofContextData::ZeroCiphertext {
+                pubkey: proof.context.pubkey.to_string(),
+                ciphertext: proof.context.ciphertext.to_string(),
+            })
+        }
+        ProofInstruction::VerifyCiphertextCiphertextEquality => {
+            let proof = ProofInstruction::proof_data::<
+                CiphertextCiphertextEqualityProofData,
+                CiphertextCiphertextEqualityProofContext,
+            >(data)?;
+            Some(ProofContextData::CiphertextCiphertextEquality {
+                first_pubkey: proof.context.first_pubkey.to_string(),
+                second_pubkey: proof.context.second_pubkey.to_string(),
+                first_ciphertext: proof.context.first_ciphertext.to_string(),
+                second_ciphertext: proof.context.second_ciphertext.to_string(),
+            })
+        }
+        ProofInstruction::VerifyCiphertextCommitmentEquality => {
+            let proof = ProofInstruction::proof_data::<
+                CiphertextCommitmentEqualityProofData,
+                CiphertextCommitmentEqualityProofContext,
+            >(data)?;
+            Some(ProofContextData::CiphertextCommitmentEquality {
+                pubkey: proof.context.pubkey.to_string(),
+                ciphertext: proof.context.ciphertext.to_string(),
+                commitment: proof.context.commitment.to_string(),
+            })
+        }
+        ProofInstruction::VerifyPubkeyValidity => {
+            let proof = ProofInstruction::proof_data::<PubkeyValidityProofData, PubkeyValidityProofContext>(data)?;
+            Some(ProofContextData::PubkeyValidity {
+                pubkey: proof.context.pubkey.to_string(),
+            })
+        }
+        ProofInstruction::VerifyPercentageWithCap => {
+            let proof = ProofInstruction::proof_data::<PercentageWithCapProofData, PercentageWithCapProofContext>(data)?;
+            Some(ProofContextData::PercentageWithCap {
+                percentage_commitment: proof.context.percentage_commitment.to_string(),
+                delta_commitment: proof.context.delta_commitment.to_string(),
+                claimed_commitment: proof.context.claimed_commitment.to_string(),
+                max_value: proof.context.max_value.into(),
+            })
+        }
+        ProofInstruction::VerifyBatchedRangeProofU64 => {
+            let proof = ProofInstruction::proof_data::<BatchedRangeProofU64Data, BatchedRangeProofContext>(data)?;
+            Some(batched_range_context(&proof.context))
+        }
+        ProofInstruction::VerifyBatchedRangeProofU128 => {
+            let proof = ProofInstruction::proof_data::<BatchedRangeProofU128Data, BatchedRangeProofContext>(data)?;
+            Some(batched_range_context(&proof.context))
+        }
+        ProofInstruction::VerifyBatchedRangeProofU256 => {
+            let proof = ProofInstruction::proof_data::<BatchedRangeProofU256Data, BatchedRangeProofContext>(data)?;
+            Some(batched_range_context(&proof.context))
+        }
+        ProofInstruction::VerifyGroupedCiphertext2HandlesValidity => {
+            let proof = ProofInstruction::proof_data::<
+                GroupedCiphertext2HandlesValidityProofData,
+                GroupedCiphertext2HandlesValidityProofContext,
+            >(data)?;
+            let context = &proof.context;
+            Some(ProofContextData::GroupedCiphertextValidity {
+                pubkeys: vec![context.first_pubkey.to_string(), context.second_pubkey.to_string()],
+                grouped_ciphertexts: vec![context.grouped_ciphertext.to_string()],
+            })
+        }
+        ProofInstruction::VerifyBatchedGroupedCiphertext2HandlesValidity => {
+            let proof = ProofInstruction::proof_data::<
+                BatchedGroupedCiphertext2HandlesValidityProofData,
+                BatchedGroupedCiphertext2HandlesValidityProofContext,
+            >(data)?;
+            let context = &proof.context;
+            Some(ProofContextData::GroupedCiphertextValidity {
+                pubkeys: vec![context.first_pubkey.to_string(), context.second_pubkey.to_string()],
+                grouped_ciphertexts: vec![
+                    context.grouped_ciphertext_lo.to_string(),
+                    context.grouped_ciphertext_hi.to_string(),
+                ],
+            })
+        }
+        ProofInstruction::VerifyGroupedCiphertext3HandlesValidity => {
+            let proof = ProofInstruction::proof_data::<
+                GroupedCiphertext3HandlesValidityProofData,
+                GroupedCiphertext3HandlesValidityProofContext,
+            >(data)?;
+            let context = &proof.context;
+            Some(ProofContextData::GroupedCiphertextValidity {
+                pubkeys: vec![
+                    context.first_pubkey.to_string(),
+                    context.second_pubkey.to_string(),
+                    context.third_pubkey.to_string(),
+                ],
+                grouped_ciphertexts: vec![context.grouped_ciphertext.to_string()],
+            })
+        }
+        ProofInstruction::VerifyBatchedGroupedCiphertext3HandlesValidity => {
+            let proof = ProofInstruction::proof_data::<
+                BatchedGroupedCiphertext3HandlesValidityProofData,
+                BatchedGroupedCiphertext3HandlesValidityProofContext,
+            >(data)?;
+            let context = &proof.context;
+            Some(ProofContextData::GroupedCiphertextValidity {
+                pubkeys: vec![
+                    context.first_pubkey.to_string(),
+                    context.second_pubkey.to_string(),
+                    context.third_pubkey.to_string(),
+                ],
+                grouped_ciphertexts: vec![
+                    context.grouped_ciphertext_lo.to_string(),
+                    context.grouped_ciphertext_hi.to_string(),
+                ],
+            })
+        }
+        ProofInstruction::CloseContextState => None,
+    }
+}
+
+/// Keep only the commitments a batched range proof actually covers
+fn batched_range_context(context: &BatchedRangeProofContext) -> ProofContextData {
+    let (commitments, bit_lengths) = context.commitments
+        .iter()
+        .zip(context.bit_lengths)
+        .filter(|(_, bit_length)| *bit_length > 0)
+        .map(|(commitment, bit_length)| (commitment.to_string(), bit_length))
+        .unzip();
+    ProofContextData::BatchedRange { commitments, bit_lengths }
+}
+
+impl fmt::Display for DecodedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.signature, self.slot) {
+            (Some(signature), Some(slot)) => writeln!(f, "Transaction {} (slot {})", signature, slot)?,
+            (Some(signature), None) => writeln!(f, "Transaction {}", signature)?,
+            _ => writeln!(f, "Transaction (not submitted)")?,
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "Status: failed: {}", error)?;
+        } else if self.signature.is_some() {
+            writeln!(f, "Status: success")?;
+        }
+
+        for instruction in &self.instructions {
+            write!(f, "\n{}", instruction)?;
+        }
+
+        if !self.log_messages.is_empty() {
+            writeln!(f, "\nLogs:")?;
+            for message in &self.log_messages {
+                writeln!(f, "  {}", message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (title, fields) = match &self.operation {
+            Operation::ConfidentialTransfer(operation) => (
+                format!("Token-2022 Confidential Transfer: {}", operation.name()),
+                operation.fields(),
+            ),
+            Operation::ZkElGamalProof(operation) => (
+                format!("ZK ElGamal Proof: {}", operation.name()),
+                operation.fields(),
+            ),
+            Operation::Token { instruction } => (format!("Token-2022: {}", instruction), Vec::new()),
+            Operation::Unknown => (format!("Program {}", self.program_id), Vec::new()),
+            Operation::Invalid { error } => (
+                format!("Program {}", self.program_id),
+                vec![("decode error".to_string(), error.clone())],
+            ),
+        };
+
+        writeln!(f, "#{} {}", self.index, title)?;
+        for (name, value) in fields {
+            writeln!(f, "    {}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ProofReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProofReference::InstructionOffset { offset, instruction_index: Some(index) } => {
+                write!(f, "instruction #{} (offset {})", index, offset)
+            }
+            ProofReference::InstructionOffset { offset, instruction_index: None } => {
+                write!(f, "instruction offset {} (out of range)", offset)
+            }
+            ProofReference::ContextStateAccount { account } => write!(f, "context state account {}", account),
+        }
+    }
+}
+
+impl ConfidentialTransferOperation {
+    /// Instruction name
+    pub fn name(&self) -> String {
+        match self {
+            Self::ConfigureAccount { .. } => "ConfigureAccount".to_string(),
+            Self::EmptyAccount { .. } => "EmptyAccount".to_string(),
+            Self::Deposit { .. } => "Deposit".to_string(),
+            Self::Withdraw { .. } => "Withdraw".to_string(),
+            Self::Transfer { .. } => "Transfer".to_string(),
+            Self::TransferWithFee { .. } => "TransferWithFee".to_string(),
+            Self::ApplyPendingBalance { .. } => "ApplyPendingBalance".to_string(),
+            Self::Other { name } => name.clone(),
+        }
+    }
+
+    /// Labelled fields for text rendering
+    fn fields(&self) -> Vec<(String, String)> {
+        let field = |name: &str, value: &dyn fmt::Display| (name.to_string(), value.to_string());
+        match self {
+            Self::ConfigureAccount {
+                token_account,
+                mint,
+                decryptable_zero_balance,
+                maximum_pending_balance_credit_counter,
+                pubkey_validity_proof,
+            } => vec![
+                field("token account", token_account),
+                field("mint", mint),
+                field("decryptable zero balance", decryptable_zero_balance),
+                field("max pending credits", maximum_pending_balance_credit_counter),
+                field("pubkey validity proof", pubkey_validity_proof),
+            ],
+            Self::EmptyAccount { token_account, zero_ciphertext_proof } => vec![
+                field("token account", token_account),
+                field("zero ciphertext proof", zero_ciphertext_proof),
+            ],
+            Self::Deposit { token_account, mint, amount, decimals } => vec![
+                field("token account", token_account),
+                field("mint", mint),
+                field("amount", amount),
+                field("decimals", decimals),
+            ],
+            Self::Withdraw {
+                token_account,
+                mint,
+                amount,
+                decimals,
+                new_decryptable_available_balance,
+                equality_proof,
+                range_proof,
+            } => vec![
+                field("token account", token_account),
+                field("mint", mint),
+                field("amount", amount),
+                field("decimals", decimals),
+                field("new decryptable available balance", new_decryptable_available_balance),
+                field("equality proof", equality_proof),
+                field("range proof", range_proof),
+            ],
+            Self::Transfer {
+                source,
+                mint,
+                destination,
+                new_source_decryptable_available_balance,
+                auditor_ciphertext_lo,
+                auditor_ciphertext_hi,
+                equality_proof,
+                ciphertext_validity_proof,
+                range_proof,
+            } => vec![
+                field("source", source),
+                field("mint", mint),
+                field("destination", destination),
+                field("new source decryptable available balance", new_source_decryptable_available_balance),
+                field("auditor ciphertext lo", auditor_ciphertext_lo),
+                field("auditor ciphertext hi", auditor_ciphertext_hi),
+                field("equality proof", equality_proof),
+                field("ciphertext validity proof", ciphertext_validity_proof),
+                field("range proof", range_proof),
+            ],
+            Self::TransferWithFee {
+                source,
+                mint,
+                destination,
+                new_source_decryptable_available_balance,
+                auditor_ciphertext_lo,
+                auditor_ciphertext_hi,
+                equality_proof,
+                ciphertext_validity_proof,
+                fee_sigma_proof,
+                fee_ciphertext_validity_proof,
+                range_proof,
+            } => vec![
+                field("source", source),
+                field("mint", mint),
+                field("destination", destination),
+                field("new source decryptable available balance", new_source_decryptable_available_balance),
+                field("auditor ciphertext lo", auditor_ciphertext_lo),
+                field("auditor ciphertext hi", auditor_ciphertext_hi),
+                field("equality proof", equality_proof),
+                field("ciphertext validity proof", ciphertext_validity_proof),
+                field("fee sigma proof", fee_sigma_proof),
+                field("fee ciphertext validity proof", fee_ciphertext_validity_proof),
+                field("range proof", range_proof),
+            ],
+            Self::ApplyPendingBalance {
+                token_account,
+                expected_pending_balance_credit_counter,
+                new_decryptable_available_balance,
+            } => vec![
+                field("token account", token_account),
+                field("expected pending credits", expected_pending_balance_credit_counter),
+                field("new decryptable available balance", new_decryptable_available_balance),
+            ],
+            Self::Other { .. } => Vec::new(),
+        }
+    }
+}
+
+impl ProofOperation {
+    /// Instruction name
+    pub fn name(&self) -> String {
+        match self {
+            Self::Verify { proof_type, .. } => proof_type.clone(),
+            Self::CloseContextState { .. } => "CloseContextState".to_string(),
+        }
+    }
+
+    /// Labelled fields for text rendering
+    fn fields(&self) -> Vec<(String, String)> {
+        let field = |name: &str, value: &dyn fmt::Display| (name.to_string(), value.to_string());
+        match self {
+            Self::Verify { source, context_state, context_state_authority, context, .. } => {
+                let mut fields = vec![match source {
+                    ProofSource::InstructionData => field("proof", &"instruction data"),
+                    ProofSource::Account { account, offset } => {
+                        field("proof", &format!("account {} at offset {}", account, offset))
+                    }
+                }];
+                if let Some(context_state) = context_state {
+                    fields.push(field("context state", context_state));
+                }
+                if let Some(authority) = context_state_authority {
+                    fields.push(field("context state authority", authority));
+                }
+                if let Some(context) = context {
+                    fields.extend(context.fields());
+                }
+                fields
+            }
+            Self::CloseContextState { context_state, destination, authority } => vec![
+                field("context state", context_state),
+                field("destination", destination),
+                field("authority", authority),
+            ],
+        }
+    }
+}
+
+impl ProofContextData {
+    /// Labelled fields for text rendering
+    fn fields(&self) -> Vec<(String, String)> {
+        let field = |name: &str, value: &dyn fmt::Display| (name.to_string(), value.to_string());
+        match self {
+            Self::ZeroCiphertext { pubkey, ciphertext } => vec![
+                field("pubkey", pubkey),
+                field("ciphertext", ciphertext),
+            ],
+            Self::CiphertextCiphertextEquality { first_pubkey, second_pubkey, first_ciphertext, second_ciphertext } => vec![
+                field("first pubkey", first_pubkey),
+                field("second pubkey", second_pubkey),
+                field("first ciphertext", first_ciphertext),
+                field("second ciphertext", second_ciphertext),
+            ],
+            Self::CiphertextCommitmentEquality { pubkey, ciphertext, commitment } => vec![
+                field("pubkey", pubkey),
+                field("ciphertext", ciphertext),
+                field("commitment", commitment),
+            ],
+            Self::PubkeyValidity { pubkey } => vec![field("pubkey", pubkey)],
+            Self::PercentageWithCap { percentage_commitment, delta_commitment, claimed_commitment, max_value } => vec![
+                field("percentage commitment", percentage_commitment),
+                field("delta commitment", delta_commitment),
+                field("claimed commitment", claimed_commitment),
+                field("max value", max_value),
+            ],
+            Self::BatchedRange { commitments, bit_lengths } => commitments
+                .iter()
+                .zip(bit_lengths)
+                .enumerate()
+                .map(|(i, (commitment, bit_length))| {
+                    field(&format!("commitment {} ({} bits)", i, bit_length), commitment)
+                })
+                .collect(),
+            Self::GroupedCiphertextValidity { pubkeys, grouped_ciphertexts } => pubkeys
+                .iter()
+                .enumerate()
+                .map(|(i, pubkey)| field(&format!("pubkey {}", i), pubkey))
+                .chain(grouped_ciphertexts.iter().enumerate().map(|(i, ciphertext)| {
+                    field(&format!("grouped ciphertext {}", i), ciphertext)
+                }))
+                .collect(),
+        }
+    }
+}
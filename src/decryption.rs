@@ -0,0 +1,348 @@
+//! Fast ElGamal decryption with a precomputed discrete-log table
+//!
+//! Decrypting an ElGamal ciphertext means solving a discrete log: finding
+//! `x` with `x * G = target`. `ElGamalCiphertext::decrypt_u32` walks up to
+//! 2^16 points on every call. `DecryptionEngine` splits the 32-bit search
+//! into a precomputed table of `2^table_bits` giant steps and a short online
+//! walk of `2^(32 - table_bits)` baby steps, run in parallel on rayon.
+//!
+//! The table is the same for every key, so it is built once per machine
+//! and cached in a file (8 bytes per entry, 8 MiB at the default size).
+//! `engine()` returns the process-wide engine every operation and the
+//! balance API decrypt with.
+
+use crate::types::*;
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT as G,
+    ristretto::RistrettoPoint,
+    scalar::Scalar,
+    traits::Identity,
+};
+use rayon::prelude::*;
+use spl_token_2022::{
+    extension::confidential_transfer::ConfidentialTransferAccount,
+    solana_zk_sdk::encryption::{
+        elgamal::{ElGamalCiphertext, ElGamalSecretKey},
+        pod::elgamal::PodElGamalCiphertext,
+    },
+};
+use spl_token_confidential_transfer_proof_generation::TRANSFER_AMOUNT_LO_BITS;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Table size of the shared engine: 2^20 entries, 2^12 online steps
+pub const DEFAULT_TABLE_BITS: u32 = 20;
+
+/// Environment variable overriding the cached table file
+pub const TABLE_PATH_ENV: &str = "CONF_BALANCES_DLOG_TABLE";
+
+/// Amounts are decrypted as 32-bit values, like `decrypt_u32`
+const AMOUNT_BITS: u32 = 32;
+
+/// Points compressed per batch, both building and searching
+const BATCH_SIZE: usize = 256;
+
+/// Table file header: magic, then the table bits as little-endian u32
+const TABLE_MAGIC: &[u8; 8] = b"CBDLOG01";
+
+/// Discrete-log solver for 32-bit ElGamal amounts
+///
+/// An amount `x` is split as `x_hi * 2^online_bits + x_lo`. The table maps
+/// every `x_hi * 2^online_bits * G` to `x_hi`; decryption walks
+/// `target - x_lo * G` until a point is in the table. Table keys are the
+/// first 8 bytes of the compressed doubled point (as in
+/// `solana_zk_sdk`'s own table); hits are confirmed by recomputing `x * G`.
+pub struct DecryptionEngine {
+    table_bits: u32,
+    table: HashMap<u64, u32>,
+}
+
+impl DecryptionEngine {
+    /// Build a table of `2^table_bits` entries in memory
+    ///
+    /// Larger tables make decryption faster at the cost of build time and
+    /// memory. `table_bits` must be between 1 and 31.
+    pub fn build(table_bits: u32) -> CtResult<Self> {
+        let giant_step = giant_step(table_bits)?;
+        let start = Instant::now();
+
+        // Entry 0 is the identity, handled separately during the search
+        let keys: Vec<u64> = (1..1u64 << table_bits)
+            .collect::<Vec<_>>()
+            .par_chunks(BATCH_SIZE)
+            .flat_map_iter(|chunk| {
+                let mut point = Scalar::from(chunk[0]) * giant_step;
+                let points: Vec<RistrettoPoint> = chunk.iter()
+                    .map(|_| {
+                        let current = point;
+                        point += giant_step;
+                        current
+                    })
+                    .collect();
+                RistrettoPoint::double_and_compress_batch(&points)
+                    .into_iter()
+                    .map(|compressed| table_key(compressed.as_bytes()))
+            })
+            .collect();
+
+        tracing::info!(table_bits, elapsed_ms = start.elapsed().as_millis() as u64, "Built discrete-log table");
+        Ok(Self::from_keys(table_bits, keys))
+    }
+
+    /// Load a cached table
+    ///
+    /// A few entries are recomputed to reject corrupt or foreign files.
+    pub fn load(path: &Path) -> CtResult<Self> {
+        let bytes = fs::read(path)?;
+        let header_len = TABLE_MAGIC.len() + 4;
+        if bytes.len() < header_len || &bytes[..TABLE_MAGIC.len()] != TABLE_MAGIC {
+            return Err(format!("{} is not a discrete-log table", path.display()).into());
+        }
+        let table_bits = u32::from_le_bytes(bytes[TABLE_MAGIC.len()..header_len].try_into()?);
+        let giant_step = giant_step(table_bits)?;
+
+        let entries = &bytes[header_len..];
+        let expected_entries = (1usize << table_bits) - 1;
+        if entries.len() != expected_entries * 8 {
+            return Err(format!(
+                "Discrete-log table {} is truncated: {} of {} entries",
+                path.display(),
+                entries.len() / 8,
+                expected_entries
+            ).into());
+        }
+        let keys: Vec<u64> = entries.chunks_exact(8)
+            .map(|entry| u64::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        for x_hi in [1, expected_entries.div_ceil(2), expected_entries] {
+            let point = Scalar::from(x_hi as u64) * giant_step;
+            let compressed = RistrettoPoint::double_and_compress_batch(&[point]);
+            if keys[x_hi - 1] != table_key(compressed[0].as_bytes()) {
+                return Err(format!("Discrete-log table {} is corrupt", path.display()).into());
+            }
+        }
+
+        Ok(Self::from_keys(table_bits, keys))
+    }
+
+    /// Write the table to `path`, creating parent directories
+    pub fn save(&self, path: &Path) -> CtResult<()> {
+        let mut keys = vec![0u64; (1usize << self.table_bits) - 1];
+        for (&key, &x_hi) in &self.table {
+            keys[x_hi as usize - 1] = key;
+        }
+
+        let mut bytes = Vec::with_capacity(TABLE_MAGIC.len() + 4 + keys.len() * 8);
+        bytes.extend_from_slice(TABLE_MAGIC);
+        bytes.extend_from_slice(&self.table_bits.to_le_bytes());
+        for key in keys {
+            bytes.extend_from_slice(&key.to_le_bytes());
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write then rename so concurrent processes never read half a table
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Load the cached table at `path`, or build and cache it
+    ///
+    /// A table of another size is rebuilt. Failing to write the cache only
+    /// costs the next process a rebuild, so it is logged, not returned.
+    pub fn load_or_build(path: &Path, table_bits: u32) -> CtResult<Self> {
+        match Self::load(path) {
+            Ok(engine) if engine.table_bits == table_bits => return Ok(engine),
+            Ok(_) => {}
+            Err(e) if path.exists() => tracing::warn!("Rebuilding discrete-log table: {}", e),
+            Err(_) => {}
+        }
+
+        let engine = Self::build(table_bits)?;
+        if let Err(e) = engine.save(path) {
+            tracing::warn!("Failed to cache discrete-log table at {}: {}", path.display(), e);
+        }
+        Ok(engine)
+    }
+
+    pub fn table_bits(&self) -> u32 {
+        self.table_bits
+    }
+
+    /// Decrypt a ciphertext of a 32-bit amount
+    ///
+    /// Same result as `ElGamalCiphertext::decrypt_u32`: `None` when the
+    /// amount doesn't fit in 32 bits or the key doesn't match.
+    pub fn decrypt_u32(&self, secret: &ElGamalSecretKey, ciphertext: &ElGamalCiphertext) -> Option<u64> {
+        let target = ciphertext.decrypt(secret).target;
+        let online_steps = 1u64 << (AMOUNT_BITS - self.table_bits);
+
+        (0..online_steps.div_ceil(BATCH_SIZE as u64))
+            .into_par_iter()
+            .find_map_any(|batch| {
+                let first = batch * BATCH_SIZE as u64;
+                let last = (first + BATCH_SIZE as u64).min(online_steps);
+
+                let mut point = target - Scalar::from(first) * G;
+                let mut points = Vec::with_capacity(BATCH_SIZE);
+                let mut x_los = Vec::with_capacity(BATCH_SIZE);
+                for x_lo in first..last {
+                    // The identity can't be compressed in a batch; it means x_hi = 0
+                    if point == RistrettoPoint::identity() {
+                        return Some(x_lo);
+                    }
+                    points.push(point);
+                    x_los.push(x_lo);
+                    point -= G;
+                }
+
+                RistrettoPoint::double_and_compress_batch(&points)
+                    .iter()
+                    .zip(x_los)
+                    .find_map(|(compressed, x_lo)| {
+                        let x_hi = *self.table.get(&table_key(compressed.as_bytes()))?;
+                        let amount = ((x_hi as u64) << (AMOUNT_BITS - self.table_bits)) + x_lo;
+                        (Scalar::from(amount) * G == target).then_some(amount)
+                    })
+            })
+    }
+
+    /// Decrypt several ciphertexts in parallel, in input order
+    pub fn decrypt_many(&self, secret: &ElGamalSecretKey, ciphertexts: &[ElGamalCiphertext]) -> Vec<Option<u64>> {
+        ciphertexts.par_iter()
+            .map(|ciphertext| self.decrypt_u32(secret, ciphertext))
+            .collect()
+    }
+
+    /// Decrypt a lo/hi ciphertext pair in parallel and recombine the amount
+    pub fn decrypt_lo_hi(
+        &self,
+        secret: &ElGamalSecretKey,
+        ciphertext_lo: &PodElGamalCiphertext,
+        ciphertext_hi: &PodElGamalCiphertext,
+    ) -> Option<u64> {
+        let ciphertext_lo: ElGamalCiphertext = (*ciphertext_lo).try_into().ok()?;
+        let ciphertext_hi: ElGamalCiphertext = (*ciphertext_hi).try_into().ok()?;
+
+        let (amount_lo, amount_hi) = rayon::join(
+            || self.decrypt_u32(secret, &ciphertext_lo),
+            || self.decrypt_u32(secret, &ciphertext_hi),
+        );
+
+        Some(amount_lo? + (amount_hi? << TRANSFER_AMOUNT_LO_BITS))
+    }
+
+    /// Decrypt an account's pending balance
+    pub fn decrypt_pending_balance(
+        &self,
+        secret: &ElGamalSecretKey,
+        ct_extension: &ConfidentialTransferAccount,
+    ) -> CtResult<u64> {
+        let [pending_lo, pending_hi] = self.decrypt_fields(secret, [
+            (ct_extension.pending_balance_lo, "pending_balance_lo"),
+            (ct_extension.pending_balance_hi, "pending_balance_hi"),
+        ])?;
+        Ok(pending_lo + (pending_hi << TRANSFER_AMOUNT_LO_BITS))
+    }
+
+    /// Decrypt an account's pending and available balances
+    ///
+    /// The pending lo/hi and available ciphertexts are decrypted in parallel.
+    pub fn decrypt_balances(
+        &self,
+        secret: &ElGamalSecretKey,
+        ct_extension: &ConfidentialTransferAccount,
+    ) -> CtResult<(u64, u64)> {
+        let [pending_lo, pending_hi, available] = self.decrypt_fields(secret, [
+            (ct_extension.pending_balance_lo, "pending_balance_lo"),
+            (ct_extension.pending_balance_hi, "pending_balance_hi"),
+            (ct_extension.available_balance, "available_balance"),
+        ])?;
+        Ok((pending_lo + (pending_hi << TRANSFER_AMOUNT_LO_BITS), available))
+    }
+
+    /// Decrypt named account fields in parallel
+    fn decrypt_fields<const N: usize>(
+        &self,
+        secret: &ElGamalSecretKey,
+        fields: [(PodElGamalCiphertext, &str); N],
+    ) -> CtResult<[u64; N]> {
+        let mut ciphertexts = Vec::with_capacity(N);
+        for (ciphertext, name) in fields {
+            ciphertexts.push(ElGamalCiphertext::try_from(ciphertext)
+                .map_err(|_| format!("Failed to convert {}", name))?);
+        }
+
+        let mut amounts = [0; N];
+        for ((amount, decrypted), (_, name)) in amounts.iter_mut()
+            .zip(self.decrypt_many(secret, &ciphertexts))
+            .zip(fields)
+        {
+            *amount = decrypted.ok_or_else(|| format!("Failed to decrypt {}", name))?;
+        }
+        Ok(amounts)
+    }
+
+    fn from_keys(table_bits: u32, keys: Vec<u64>) -> Self {
+        let table = keys.into_iter()
+            .zip(1u32..)
+            .collect();
+        Self { table_bits, table }
+    }
+}
+
+static ENGINE: OnceLock<DecryptionEngine> = OnceLock::new();
+
+/// Shared engine used by every operation
+///
+/// The first call loads the cached table (see `default_table_path`) or
+/// builds it, which takes a few seconds once per machine.
+pub fn engine() -> &'static DecryptionEngine {
+    ENGINE.get_or_init(|| {
+        let engine = match default_table_path() {
+            Some(path) => DecryptionEngine::load_or_build(&path, DEFAULT_TABLE_BITS),
+            None => DecryptionEngine::build(DEFAULT_TABLE_BITS),
+        };
+        engine.expect("default table size is valid")
+    })
+}
+
+/// Use `engine` as the shared engine (e.g. a different size or cache path)
+///
+/// Fails once the shared engine has been used or installed.
+pub fn install(engine: DecryptionEngine) -> CtResult<()> {
+    ENGINE.set(engine)
+        .map_err(|_| "The shared decryption engine is already initialized".into())
+}
+
+/// Cache file of the shared table
+///
+/// `$CONF_BALANCES_DLOG_TABLE` if set, otherwise under `$XDG_CACHE_HOME` or
+/// `~/.cache`.
+pub fn default_table_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(TABLE_PATH_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("conf-balances").join(format!("dlog-u32-{}.bin", DEFAULT_TABLE_BITS)))
+}
+
+fn giant_step(table_bits: u32) -> CtResult<RistrettoPoint> {
+    if !(1..AMOUNT_BITS).contains(&table_bits) {
+        return Err(format!("Table bits must be between 1 and {}, got {}", AMOUNT_BITS - 1, table_bits).into());
+    }
+    Ok(Scalar::from(1u64 << (AMOUNT_BITS - table_bits)) * G)
+}
+
+fn table_key(compressed: &[u8; 32]) -> u64 {
+    u64::from_le_bytes(compressed[..8].try_into().unwrap())
+}
@@ -1,5 +1,10 @@
 //! Deposit tokens into confidential balance
 
+use crate::authority::{unique_signers, Authority};
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::memo::with_memo;
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
 use crate::types::*;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
@@ -27,13 +32,55 @@ pub async fn deposit_to_confidential(
     mint: &solana_sdk::pubkey::Pubkey,
     amount: u64,
     decimals: u8,
+) -> ReceiptResult {
+    deposit_with_authority(
+        client, payer, &Authority::Signer(authority), mint, amount, decimals, &SignerKeyProvider::new(authority), None,
+    ).await
+}
+
+/// Deposit for a single-signer or multisig authority
+///
+/// Same as `deposit_to_confidential`; a multisig's signers are checked
+/// against the multisig account before sending. `keys` only decrypt the
+/// resulting balances for the returned receipt; `memo` is sent in an SPL
+/// Memo instruction right before the deposit.
+#[allow(clippy::too_many_arguments)]
+pub async fn deposit_with_authority(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    decimals: u8,
+    keys: &dyn KeyProvider,
+    memo: Option<&str>,
+) -> ReceiptResult {
+    let signature = send_deposit(client, payer, authority, mint, amount, decimals, memo)?;
+    let token_account = get_associated_token_address_with_program_id(&authority.pubkey(), mint, &spl_token_2022::id());
+    let sent = SentTransactions::single(TransactionRole::Deposit, signature, &[]);
+    Ok(fetch_receipt(client, Operation::Deposit, Some(&token_account), &sent, Some(keys)))
+}
+
+#[tracing::instrument(skip_all, fields(authority = %authority.pubkey(), %mint, amount))]
+fn send_deposit(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    decimals: u8,
+    memo: Option<&str>,
 ) -> SigResult {
+    authority.check(client)?;
     let token_account = get_associated_token_address_with_program_id(
         &authority.pubkey(),
         mint,
         &spl_token_2022::id(),
     );
 
+    let multisig_signers = authority.multisig_signers();
+    let multisig_signers: Vec<_> = multisig_signers.iter().collect();
+
     // Create deposit instruction
     let deposit_ix = deposit(
         &spl_token_2022::id(),
@@ -42,20 +89,22 @@ pub async fn deposit_to_confidential(
         amount,
         decimals,
         &authority.pubkey(),
-        &[&authority.pubkey()],
+        &multisig_signers,
     )?;
 
     // Send transaction
     let recent_blockhash = client.get_latest_blockhash()?;
+    let mut signers = vec![payer];
+    signers.extend(authority.signers());
     let transaction = Transaction::new_signed_with_payer(
-        &[deposit_ix],
+        &with_memo(memo, vec![deposit_ix]),
         Some(&payer.pubkey()),
-        &[payer, authority],
+        &unique_signers(&signers),
         recent_blockhash,
     );
 
-    let signature = client.send_and_confirm_transaction(&transaction)?;
-    println!("✅ Deposited {} tokens to pending balance: {}", amount, signature);
+    let signature = events::send_and_confirm(client, Operation::Deposit, &transaction)?;
+    events::completed(Operation::Deposit, format!("Deposited {} tokens to pending balance: {}", amount, signature));
 
     Ok(signature)
 }
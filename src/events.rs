@@ -0,0 +1,307 @@
+//! Structured progress events for confidential operations
+//!
+//! Operations don't print; they emit `OperationEvent`s and `tracing`
+//! events instead. Services subscribe with `add_listener` or `subscribe`
+//! (a channel), UIs render them, and the examples and CLI call
+//! `print_events` to get the familiar emoji status lines on stdout.
+//!
+//! Listeners are process-wide: every operation in the process reports to
+//! every listener. `add_listener` returns a `ListenerId` so a component can
+//! remove its own listener with `remove_listener` without touching others.
+
+use crate::proofs::ProofTimings;
+use crate::types::*;
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::rpc_client::{RpcClient, SerializableTransaction};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often `send_and_confirm` checks whether a sent transaction landed
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Operation an event belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    CreateMint,
+    CreateAccount,
+    Configure,
+    Deposit,
+    ApplyPendingBalance,
+    Withdraw,
+    Transfer,
+    BatchTransfer,
+    HarvestWithheldFees,
+    WithdrawWithheldFees,
+    ConfidentialMint,
+    ConfidentialBurn,
+    ApplyPendingBurn,
+    RotateSupplyKey,
+    CloseAccount,
+    KeyRotation,
+    History,
+    KeyExport,
+    WatchPendingBalance,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::CreateMint => "create mint",
+            Self::CreateAccount => "create account",
+            Self::Configure => "configure",
+            Self::Deposit => "deposit",
+            Self::ApplyPendingBalance => "apply pending balance",
+            Self::Withdraw => "withdraw",
+            Self::Transfer => "transfer",
+            Self::BatchTransfer => "batch transfer",
+            Self::HarvestWithheldFees => "harvest withheld fees",
+            Self::WithdrawWithheldFees => "withdraw withheld fees",
+            Self::ConfidentialMint => "confidential mint",
+            Self::ConfidentialBurn => "confidential burn",
+            Self::ApplyPendingBurn => "apply pending burn",
+            Self::RotateSupplyKey => "rotate supply key",
+            Self::CloseAccount => "close account",
+            Self::KeyRotation => "key rotation",
+            Self::History => "history",
+            Self::KeyExport => "key export",
+            Self::WatchPendingBalance => "watch pending balance",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Progress of a confidential operation
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OperationEvent {
+    /// Zero-knowledge proof data generated for `amount` tokens
+    ProofGenerated {
+        operation: Operation,
+        amount: u64,
+        /// Generation timings, for proofs generated by `proofs`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timings: Option<ProofTimings>,
+    },
+    /// Proof context state account created
+    ContextAccountCreated {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        signature: Signature,
+    },
+    /// Proof context state account closed and rent reclaimed
+    ContextAccountClosed {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        signature: Signature,
+    },
+    /// Record account created to hold proof data (first chunk written)
+    RecordAccountCreated {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        signature: Signature,
+    },
+    /// Proof record account closed and rent reclaimed
+    RecordAccountClosed {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        account: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        signature: Signature,
+    },
+    /// Transaction submitted, not yet confirmed
+    TransactionSent {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        signature: Signature,
+    },
+    /// Transaction confirmed
+    Confirmed {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        signature: Signature,
+    },
+    /// Incoming credits detected in a watched account's pending balance
+    PendingBalanceCredited {
+        operation: Operation,
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+        pending: u64,
+        pending_balance_credit_counter: u64,
+    },
+    /// Intermediate step of a long-running operation
+    Progress {
+        operation: Operation,
+        message: String,
+    },
+    /// Operation finished
+    Completed {
+        operation: Operation,
+        summary: String,
+    },
+}
+
+impl fmt::Display for OperationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProofGenerated { operation, amount, timings: None } => {
+                write!(f, "🔐 Generated {} proofs for {} tokens", operation, amount)
+            }
+            Self::ProofGenerated { operation, amount, timings: Some(timings) } => write!(
+                f,
+                "🔐 Generated {} proofs for {} tokens in {} ms",
+                operation,
+                amount,
+                timings.total.as_millis()
+            ),
+            Self::ContextAccountCreated { account, signature, .. } => {
+                write!(f, "📦 Created proof context account {}: {}", account, signature)
+            }
+            Self::ContextAccountClosed { account, signature, .. } => {
+                write!(f, "🧹 Closed proof context account {}: {}", account, signature)
+            }
+            Self::RecordAccountCreated { account, signature, .. } => {
+                write!(f, "📝 Created proof record account {}: {}", account, signature)
+            }
+            Self::RecordAccountClosed { account, signature, .. } => {
+                write!(f, "🧹 Closed proof record account {}: {}", account, signature)
+            }
+            Self::TransactionSent { operation, signature } => {
+                write!(f, "📤 Sent {} transaction: {}", operation, signature)
+            }
+            Self::Confirmed { operation, signature } => {
+                write!(f, "✔️  Confirmed {} transaction: {}", operation, signature)
+            }
+            Self::PendingBalanceCredited { token_account, pending, pending_balance_credit_counter, .. } => write!(
+                f,
+                "📥 {} pending tokens in {} ({} credits)",
+                pending, token_account, pending_balance_credit_counter
+            ),
+            Self::Progress { message, .. } => write!(f, "⏳ {}", message),
+            Self::Completed { summary, .. } => write!(f, "✅ {}", summary),
+        }
+    }
+}
+
+/// Listener called with every event; returns `false` to be removed
+type Listener = Arc<dyn Fn(&OperationEvent) -> bool + Send + Sync>;
+
+/// Handle on a listener registered with `add_listener`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(u64);
+
+static LISTENERS: Mutex<Vec<(ListenerId, Listener)>> = Mutex::new(Vec::new());
+static NEXT_LISTENER_ID: AtomicU64 = AtomicU64::new(0);
+static PRINTING: AtomicBool = AtomicBool::new(false);
+
+fn register(listener: Listener) -> ListenerId {
+    let id = ListenerId(NEXT_LISTENER_ID.fetch_add(1, Ordering::SeqCst));
+    LISTENERS.lock().unwrap().push((id, listener));
+    id
+}
+
+/// Call `listener` for every event emitted in the process
+///
+/// The listener stays registered until `remove_listener` is called with
+/// the returned id.
+pub fn add_listener(listener: impl Fn(&OperationEvent) + Send + Sync + 'static) -> ListenerId {
+    register(Arc::new(move |event| {
+        listener(event);
+        true
+    }))
+}
+
+/// Remove a listener added with `add_listener`
+///
+/// Other listeners are left alone. Returns `false` if it was already removed.
+pub fn remove_listener(id: ListenerId) -> bool {
+    let mut listeners = LISTENERS.lock().unwrap();
+    let before = listeners.len();
+    listeners.retain(|(listener_id, _)| *listener_id != id);
+    listeners.len() != before
+}
+
+/// Receive every event emitted in the process on a channel
+pub fn subscribe() -> Receiver<OperationEvent> {
+    let (sender, receiver) = channel();
+    // Removed once the receiver is dropped
+    register(Arc::new(move |event| sender.send(event.clone()).is_ok()));
+    receiver
+}
+
+/// Print events as status lines on stdout (examples and CLI)
+///
+/// Calling it more than once doesn't duplicate the output.
+pub fn print_events() {
+    if !PRINTING.swap(true, Ordering::SeqCst) {
+        let _ = add_listener(|event| println!("{}", event));
+    }
+}
+
+/// Report an event to `tracing` and every listener
+///
+/// Listeners are called without holding the lock, so they may emit events,
+/// add listeners or start operations themselves.
+pub(crate) fn emit(event: OperationEvent) {
+    tracing::info!(event = ?event, "{}", event);
+    let listeners = LISTENERS.lock().unwrap().clone();
+    let finished: Vec<_> = listeners.into_iter()
+        .filter(|(_, listener)| !listener(&event))
+        .map(|(id, _)| id)
+        .collect();
+    if !finished.is_empty() {
+        LISTENERS.lock().unwrap().retain(|(id, _)| !finished.contains(id));
+    }
+}
+
+/// Report the final status line of an operation
+pub(crate) fn completed(operation: Operation, summary: impl Into<String>) {
+    emit(OperationEvent::Completed { operation, summary: summary.into() });
+}
+
+/// Report an intermediate step of an operation
+pub(crate) fn progress(operation: Operation, message: impl Into<String>) {
+    emit(OperationEvent::Progress { operation, message: message.into() });
+}
+
+/// Send a transaction and wait for confirmation, reporting both steps
+///
+/// `TransactionSent` is reported once the RPC node accepted the
+/// transaction. Confirmation is awaited like
+/// `RpcClient::send_and_confirm_transaction`: until the transaction lands
+/// or its blockhash expires.
+pub(crate) fn send_and_confirm(
+    client: &RpcClient,
+    operation: Operation,
+    transaction: &impl SerializableTransaction,
+) -> SigResult {
+    let signature = client.send_transaction(transaction)?;
+    emit(OperationEvent::TransactionSent { operation, signature });
+
+    loop {
+        match client.get_signature_status(&signature)? {
+            Some(Ok(())) => break,
+            Some(Err(e)) => return Err(e.into()),
+            None if !client.is_blockhash_valid(transaction.get_recent_blockhash(), CommitmentConfig::processed())? => {
+                return Err(format!("Transaction {} expired before it was confirmed", signature).into());
+            }
+            None => std::thread::sleep(CONFIRM_POLL_INTERVAL),
+        }
+    }
+    emit(OperationEvent::Confirmed { operation, signature });
+
+    Ok(signature)
+}
@@ -0,0 +1,373 @@
+//! Harvest and withdraw withheld confidential transfer fees
+//!
+//! For mints with the `ConfidentialTransferFeeConfig` extension, every
+//! confidential transfer withholds an encrypted fee in the destination
+//! account's `ConfidentialTransferFeeAmount` extension. The fees are encrypted
+//! under the mint's withdraw-withheld authority ElGamal pubkey and can be:
+//! - Harvested from token accounts into the mint (permissionless)
+//! - Withdrawn from the mint to a token account (withdraw-withheld authority)
+//! - Withdrawn directly from token accounts (withdraw-withheld authority)
+
+use crate::decryption;
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
+use crate::types::*;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        confidential_transfer_fee::{
+            account_info::WithheldTokensInfo,
+            instruction::{
+                harvest_withheld_tokens_to_mint as harvest_withheld_tokens_to_mint_instruction,
+                withdraw_withheld_tokens_from_accounts as withdraw_withheld_tokens_from_accounts_instruction,
+                withdraw_withheld_tokens_from_mint as withdraw_withheld_tokens_from_mint_instruction,
+            },
+            ConfidentialTransferFeeAmount, ConfidentialTransferFeeConfig,
+        },
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeCiphertext,
+        elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+
+/// Withheld fees decrypted with the withdraw-withheld authority ElGamal key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithheldFees {
+    /// Fees already harvested to the mint
+    pub mint: u64,
+    /// Fees still withheld in the given token accounts
+    pub accounts: u64,
+    /// Sum of mint and account fees
+    pub total: u64,
+}
+
+/// Harvest withheld fees from token accounts into the mint
+///
+/// This instruction is permissionless: anyone can move withheld fees from
+/// token accounts into the mint. Accounts that fail to harvest (e.g. wrong
+/// mint) are skipped by the program rather than failing the transaction.
+#[tracing::instrument(skip_all, fields(%mint, sources = sources.len()))]
+pub async fn harvest_withheld_tokens_to_mint(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    mint: &Pubkey,
+    sources: &[Pubkey],
+) -> ReceiptResult {
+    let source_refs: Vec<&Pubkey> = sources.iter().collect();
+
+    let harvest_ix = harvest_withheld_tokens_to_mint_instruction(
+        &spl_token_2022::id(),
+        mint,
+        &source_refs,
+    )?;
+
+    // Send transaction
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[harvest_ix],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::HarvestWithheldFees, &transaction)?;
+    events::completed(
+        Operation::HarvestWithheldFees,
+        format!("Harvested withheld fees from {} accounts to mint: {}", sources.len(), signature),
+    );
+
+    let sent = SentTransactions::single(TransactionRole::HarvestWithheldFees, signature, &[]);
+    Ok(fetch_receipt(client, Operation::HarvestWithheldFees, None, &sent, None))
+}
+
+/// Withdraw withheld fees from the mint to the authority's token account
+///
+/// The withheld amount in the mint is re-encrypted under the destination
+/// account's ElGamal pubkey and credited directly to its available balance.
+///
+/// Requires a ciphertext-ciphertext equality proof showing the mint
+/// ciphertext (under the withdraw-withheld authority key) and the new
+/// destination ciphertext encrypt the same amount.
+///
+/// # Arguments
+/// * `authority` - Withdraw-withheld authority and owner of the destination account
+/// * `withdraw_withheld_elgamal_keypair` - ElGamal keypair matching the mint's
+///   `withdraw_withheld_authority_elgamal_pubkey`
+pub async fn withdraw_withheld_tokens_from_mint(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    withdraw_withheld_elgamal_keypair: &ElGamalKeypair,
+    mint: &Pubkey,
+) -> ReceiptResult {
+    withdraw_withheld_tokens_from_mint_with_keys(
+        client, payer, authority, withdraw_withheld_elgamal_keypair, mint,
+        &SignerKeyProvider::new(authority),
+    ).await
+}
+
+/// Withdraw withheld fees from the mint with the destination's keys taken from a key provider
+///
+/// Same as `withdraw_withheld_tokens_from_mint`, for destinations whose AE
+/// key isn't derived from the authority.
+#[tracing::instrument(skip_all, fields(%mint))]
+pub async fn withdraw_withheld_tokens_from_mint_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    withdraw_withheld_elgamal_keypair: &ElGamalKeypair,
+    mint: &Pubkey,
+    destination_keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    let destination = get_associated_token_address_with_program_id(
+        &authority.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    // Fetch withheld amount from the mint
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let fee_config = mint_account.get_extension::<ConfidentialTransferFeeConfig>()?;
+
+    let withheld_amount: ElGamalCiphertext = fee_config.withheld_amount.try_into()
+        .map_err(|_| "Failed to convert mint withheld_amount")?;
+
+    let withheld_info = WithheldTokensInfo::new(&fee_config.withheld_amount);
+
+    let (destination_elgamal_pubkey, new_decryptable_balance, withdrawn) = credit_destination(
+        client,
+        destination_keys,
+        &destination,
+        withdraw_withheld_elgamal_keypair,
+        &withheld_amount,
+    )?;
+
+    // Generate ciphertext-ciphertext equality proof
+    let proof_data = withheld_info.generate_proof_data(
+        withdraw_withheld_elgamal_keypair,
+        &destination_elgamal_pubkey,
+    )?;
+
+    // Proof will be in the next instruction (offset 1)
+    let withdraw_instructions = withdraw_withheld_tokens_from_mint_instruction(
+        &spl_token_2022::id(),
+        mint,
+        &destination,
+        &new_decryptable_balance.into(),
+        &authority.pubkey(),
+        &[],
+        ProofLocation::InstructionOffset(1.try_into().unwrap(), &proof_data),
+    )?;
+
+    // Send transaction
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &withdraw_instructions,
+        Some(&payer.pubkey()),
+        &[payer, authority],
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::WithdrawWithheldFees, &transaction)?;
+    events::completed(
+        Operation::WithdrawWithheldFees,
+        format!("Withdrew {} withheld fee tokens from mint: {}", withdrawn, signature),
+    );
+
+    let sent = SentTransactions::single(TransactionRole::WithdrawWithheldFees, signature, &[]);
+    Ok(fetch_receipt(client, Operation::WithdrawWithheldFees, Some(&destination), &sent, Some(destination_keys)))
+}
+
+/// Withdraw withheld fees directly from token accounts to the authority's token account
+///
+/// The withheld amounts of all sources are aggregated homomorphically, so a
+/// single equality proof covers every source account in the instruction.
+///
+/// # Arguments
+/// * `authority` - Withdraw-withheld authority and owner of the destination account
+/// * `withdraw_withheld_elgamal_keypair` - ElGamal keypair matching the mint's
+///   `withdraw_withheld_authority_elgamal_pubkey`
+/// * `sources` - Token accounts holding withheld fees
+pub async fn withdraw_withheld_tokens_from_accounts(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    withdraw_withheld_elgamal_keypair: &ElGamalKeypair,
+    mint: &Pubkey,
+    sources: &[Pubkey],
+) -> ReceiptResult {
+    withdraw_withheld_tokens_from_accounts_with_keys(
+        client, payer, authority, withdraw_withheld_elgamal_keypair, mint, sources,
+        &SignerKeyProvider::new(authority),
+    ).await
+}
+
+/// Withdraw withheld fees from token accounts with the destination's keys taken from a key provider
+///
+/// Same as `withdraw_withheld_tokens_from_accounts`, for destinations whose
+/// AE key isn't derived from the authority.
+#[tracing::instrument(skip_all, fields(%mint, sources = sources.len()))]
+pub async fn withdraw_withheld_tokens_from_accounts_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    withdraw_withheld_elgamal_keypair: &ElGamalKeypair,
+    mint: &Pubkey,
+    sources: &[Pubkey],
+    destination_keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    let destination = get_associated_token_address_with_program_id(
+        &authority.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    // Aggregate withheld amounts across all sources
+    let aggregate_withheld_amount = aggregate_account_withheld_amounts(client, sources)?;
+    let withheld_info = WithheldTokensInfo::new(&aggregate_withheld_amount.into());
+
+    let (destination_elgamal_pubkey, new_decryptable_balance, withdrawn) = credit_destination(
+        client,
+        destination_keys,
+        &destination,
+        withdraw_withheld_elgamal_keypair,
+        &aggregate_withheld_amount,
+    )?;
+
+    // Generate ciphertext-ciphertext equality proof over the aggregate
+    let proof_data = withheld_info.generate_proof_data(
+        withdraw_withheld_elgamal_keypair,
+        &destination_elgamal_pubkey,
+    )?;
+
+    let source_refs: Vec<&Pubkey> = sources.iter().collect();
+    let withdraw_instructions = withdraw_withheld_tokens_from_accounts_instruction(
+        &spl_token_2022::id(),
+        mint,
+        &destination,
+        &new_decryptable_balance.into(),
+        &authority.pubkey(),
+        &[],
+        &source_refs,
+        ProofLocation::InstructionOffset(1.try_into().unwrap(), &proof_data),
+    )?;
+
+    // Send transaction
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &withdraw_instructions,
+        Some(&payer.pubkey()),
+        &[payer, authority],
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::WithdrawWithheldFees, &transaction)?;
+    events::completed(
+        Operation::WithdrawWithheldFees,
+        format!("Withdrew {} withheld fee tokens from {} accounts: {}", withdrawn, sources.len(), signature),
+    );
+
+    let sent = SentTransactions::single(TransactionRole::WithdrawWithheldFees, signature, &[]);
+    Ok(fetch_receipt(client, Operation::WithdrawWithheldFees, Some(&destination), &sent, Some(destination_keys)))
+}
+
+/// Decrypt the withheld fees in the mint and the given token accounts
+///
+/// Uses the withdraw-withheld authority ElGamal keypair, which is the only
+/// key able to decrypt withheld fee ciphertexts.
+pub fn get_withheld_fees(
+    client: &RpcClient,
+    withdraw_withheld_elgamal_keypair: &ElGamalKeypair,
+    mint: &Pubkey,
+    sources: &[Pubkey],
+) -> CtResult<WithheldFees> {
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let fee_config = mint_account.get_extension::<ConfidentialTransferFeeConfig>()?;
+
+    let mint_withheld: ElGamalCiphertext = fee_config.withheld_amount.try_into()
+        .map_err(|_| "Failed to convert mint withheld_amount")?;
+    let mint_fees = decryption::engine().decrypt_u32(withdraw_withheld_elgamal_keypair.secret(), &mint_withheld)
+        .ok_or("Failed to decrypt mint withheld_amount")?;
+
+    let account_fees = if sources.is_empty() {
+        0
+    } else {
+        let aggregate_withheld_amount = aggregate_account_withheld_amounts(client, sources)?;
+        decryption::engine()
+            .decrypt_u32(withdraw_withheld_elgamal_keypair.secret(), &aggregate_withheld_amount)
+            .ok_or("Failed to decrypt account withheld amounts")?
+    };
+
+    Ok(WithheldFees {
+        mint: mint_fees,
+        accounts: account_fees,
+        total: mint_fees + account_fees,
+    })
+}
+
+/// Sum the encrypted withheld amounts of the given token accounts
+fn aggregate_account_withheld_amounts(
+    client: &RpcClient,
+    sources: &[Pubkey],
+) -> CtResult<ElGamalCiphertext> {
+    let mut aggregate_withheld_amount = ElGamalCiphertext::default();
+    for source in sources {
+        let account_data = client.get_account(source)?;
+        let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+        let fee_amount = account.get_extension::<ConfidentialTransferFeeAmount>()?;
+        let withheld_amount: ElGamalCiphertext = fee_amount.withheld_amount.try_into()
+            .map_err(|_| format!("Failed to convert withheld_amount of {}", source))?;
+        aggregate_withheld_amount = aggregate_withheld_amount + withheld_amount;
+    }
+    Ok(aggregate_withheld_amount)
+}
+
+/// Compute the destination's ElGamal pubkey and new decryptable available balance
+///
+/// Returns the destination pubkey, the AES-encrypted balance after the
+/// withheld amount is credited, and the decrypted withheld amount.
+fn credit_destination(
+    client: &RpcClient,
+    destination_keys: &dyn KeyProvider,
+    destination: &Pubkey,
+    withdraw_withheld_elgamal_keypair: &ElGamalKeypair,
+    withheld_amount: &ElGamalCiphertext,
+) -> CtResult<(ElGamalPubkey, AeCiphertext, u64)> {
+    let withdrawn = decryption::engine().decrypt_u32(withdraw_withheld_elgamal_keypair.secret(), withheld_amount)
+        .ok_or("Failed to decrypt withheld amount")?;
+
+    // Destination AES key to update its decryptable balance
+    let aes_key = destination_keys.aes_key(destination)?;
+
+    let account_data = client.get_account(destination)?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+    let ct_extension = account.get_extension::<ConfidentialTransferAccount>()?;
+
+    let destination_elgamal_pubkey: ElGamalPubkey = ct_extension.elgamal_pubkey.try_into()
+        .map_err(|_| "Failed to convert destination ElGamal pubkey")?;
+
+    let decryptable_balance: AeCiphertext = ct_extension.decryptable_available_balance.try_into()
+        .map_err(|_| "Failed to convert decryptable_available_balance")?;
+    let current_available = aes_key.decrypt(&decryptable_balance)
+        .ok_or("Failed to decrypt decryptable_available_balance")?;
+
+    let new_available = current_available.checked_add(withdrawn)
+        .ok_or_else(|| format!("Available balance of {} overflows u64", destination))?;
+    let new_decryptable_balance = aes_key.encrypt(new_available);
+
+    Ok((destination_elgamal_pubkey, new_decryptable_balance, withdrawn))
+}
@@ -0,0 +1,490 @@
+//! Transaction history reconstruction for a confidential token account
+//!
+//! Walks every transaction that touched the owner's token account, decodes
+//! the Token-2022 confidential instructions and rebuilds a ledger with the
+//! running pending and available balances.
+//!
+//! Amounts are recovered from what each instruction exposes to the owner:
+//! - Deposits and withdrawals carry the amount in plaintext
+//! - Apply, withdraw and outgoing transfers carry the new available balance
+//!   encrypted under the owner's AE key
+//! - Transfers carry the amount encrypted under the source and destination
+//!   ElGamal keys in the ciphertext validity proof, which lives either in the
+//!   same transaction or in a proof context state account
+
+use crate::auditor::{decrypt_lo_hi, fetch_transaction};
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::types::*;
+use solana_client::{
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::{
+    message::compiled_instruction::CompiledInstruction,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::confidential_transfer::{
+        instruction::{
+            ApplyPendingBalanceData, ConfidentialTransferInstruction, DepositInstructionData,
+            TransferInstructionData, TransferWithFeeInstructionData, WithdrawInstructionData,
+        },
+        DecryptableBalance,
+    },
+    instruction::{decode_instruction_data, decode_instruction_type, TokenInstruction},
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::ElGamalKeypair,
+        },
+        zk_elgamal_proof_program::{
+            self,
+            instruction::ProofInstruction,
+            proof_data::{
+                BatchedGroupedCiphertext3HandlesValidityProofContext,
+                BatchedGroupedCiphertext3HandlesValidityProofData,
+            },
+            state::ProofContextState,
+        },
+    },
+};
+
+/// Maximum number of signatures returned by a single RPC page
+const SIGNATURES_PAGE_SIZE: usize = 1000;
+
+/// Handle index of the source in a transfer's grouped ciphertexts
+const SOURCE_HANDLE: usize = 0;
+
+/// Handle index of the destination in a transfer's grouped ciphertexts
+const DESTINATION_HANDLE: usize = 1;
+
+/// Kind of balance change recorded in the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryEvent {
+    /// Public balance moved into the pending balance
+    Deposit,
+    /// Pending balance moved into the available balance
+    ApplyPendingBalance,
+    /// Available balance moved back into the public balance
+    Withdraw,
+    /// Confidential transfer sent from this account
+    TransferOut { destination: Pubkey },
+    /// Confidential transfer received by this account
+    TransferIn { source: Pubkey },
+}
+
+/// A single entry of the reconstructed ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Transaction containing the instruction
+    pub signature: Signature,
+    /// Slot the transaction landed in
+    pub slot: u64,
+    /// Block time of the transaction, if known
+    pub block_time: Option<i64>,
+    /// What happened to the balance
+    pub event: HistoryEvent,
+    /// Amount moved (in base units), or `None` if it couldn't be decrypted
+    pub amount: Option<u64>,
+    /// Pending balance after this entry, or `None` if it can't be known
+    /// until the next apply (e.g. after an undecryptable or fee-bearing transfer)
+    pub pending_balance: Option<u64>,
+    /// Available balance after this entry
+    pub available_balance: u64,
+}
+
+/// Ledger of a confidential token account, oldest entry first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountHistory {
+    /// Token account the ledger belongs to
+    pub token_account: Pubkey,
+    /// Ledger entries in chain order
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl AccountHistory {
+    /// Pending balance after the last entry
+    pub fn pending_balance(&self) -> Option<u64> {
+        self.entries.last().map_or(Some(0), |entry| entry.pending_balance)
+    }
+
+    /// Available balance after the last entry
+    pub fn available_balance(&self) -> u64 {
+        self.entries.last().map_or(0, |entry| entry.available_balance)
+    }
+}
+
+/// Running balances while replaying the ledger
+struct Balances {
+    pending: Option<u64>,
+    available: u64,
+}
+
+/// Reconstruct the ledger of the owner's associated token account
+///
+/// Derives the owner's ElGamal and AE keys from the signer, the same way
+/// the other operations in this crate do.
+pub fn get_transaction_history(
+    client: &RpcClient,
+    owner: &dyn Signer,
+    mint: &Pubkey,
+) -> CtResult<AccountHistory> {
+    let token_account = get_associated_token_address_with_program_id(
+        &owner.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    get_account_history(client, &SignerKeyProvider::new(owner), &token_account)
+}
+
+/// Reconstruct the ledger of a confidential token account
+///
+/// Steps:
+/// 1. Fetch every signature for the token account, oldest first
+/// 2. Decode the confidential instructions touching the account
+/// 3. Decrypt amounts and replay them into running balances
+///
+/// Failed transactions are skipped.
+#[tracing::instrument(skip_all, fields(%token_account))]
+pub fn get_account_history(
+    client: &RpcClient,
+    keys: &dyn KeyProvider,
+    token_account: &Pubkey,
+) -> CtResult<AccountHistory> {
+    let elgamal_keypair = keys.elgamal_keypair(token_account)?;
+    let aes_key = keys.aes_key(token_account)?;
+    let mut balances = Balances { pending: Some(0), available: 0 };
+    let mut entries = Vec::new();
+
+    for status in account_signatures(client, token_account)? {
+        if status.err.is_some() {
+            continue;
+        }
+        let signature: Signature = status.signature.parse()
+            .map_err(|_| format!("Invalid signature {}", status.signature))?;
+        let fetched = fetch_transaction(client, &signature)?;
+
+        let instructions = fetched.transaction.message.instructions();
+        for (index, instruction) in instructions.iter().enumerate() {
+            let Some(&program_id) = fetched.account_keys.get(instruction.program_id_index as usize) else {
+                continue;
+            };
+            if program_id != spl_token_2022::id() {
+                continue;
+            }
+
+            let context = InstructionContext {
+                client,
+                instructions,
+                index,
+                account_keys: &fetched.account_keys,
+                token_account,
+            };
+            for (event, amount) in decode_events(&context, &elgamal_keypair, &aes_key, &mut balances)? {
+                entries.push(HistoryEntry {
+                    signature,
+                    slot: fetched.slot,
+                    block_time: status.block_time,
+                    event,
+                    amount,
+                    pending_balance: balances.pending,
+                    available_balance: balances.available,
+                });
+            }
+        }
+    }
+
+    events::completed(
+        Operation::History,
+        format!("Reconstructed {} history entries for {}", entries.len(), token_account),
+    );
+
+    Ok(AccountHistory {
+        token_account: *token_account,
+        entries,
+    })
+}
+
+/// A Token-2022 instruction together with its enclosing transaction
+struct InstructionContext<'a> {
+    client: &'a RpcClient,
+    instructions: &'a [CompiledInstruction],
+    index: usize,
+    account_keys: &'a [Pubkey],
+    token_account: &'a Pubkey,
+}
+
+impl InstructionContext<'_> {
+    fn instruction(&self) -> &CompiledInstruction {
+        &self.instructions[self.index]
+    }
+
+    /// Resolve the instruction account at `position`
+    fn account(&self, position: usize) -> CtResult<Pubkey> {
+        let index = *self.instruction().accounts.get(position)
+            .ok_or("Instruction is missing accounts")?;
+        Ok(*self.account_keys.get(index as usize)
+            .ok_or("Instruction account index out of range")?)
+    }
+}
+
+/// Decode one instruction and apply it to the running balances
+///
+/// Returns the ledger events it produced for this token account (a
+/// self-transfer produces both an outgoing and an incoming entry).
+fn decode_events(
+    context: &InstructionContext,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    balances: &mut Balances,
+) -> CtResult<Vec<(HistoryEvent, Option<u64>)>> {
+    let data = &context.instruction().data;
+    if !matches!(TokenInstruction::unpack(data), Ok(TokenInstruction::ConfidentialTransferExtension)) {
+        return Ok(Vec::new());
+    }
+    let extension_data = &data[1..];
+    let Ok(instruction_type) = decode_instruction_type(extension_data) else {
+        return Ok(Vec::new());
+    };
+
+    match instruction_type {
+        // Accounts: [token_account, mint, authority]
+        ConfidentialTransferInstruction::Deposit => {
+            if context.account(0)? != *context.token_account {
+                return Ok(Vec::new());
+            }
+            let data = decode_instruction_data::<DepositInstructionData>(extension_data)?;
+            let amount = u64::from(data.amount);
+            balances.pending = balances.pending.and_then(|pending| pending.checked_add(amount));
+            Ok(vec![(HistoryEvent::Deposit, Some(amount))])
+        }
+        // Accounts: [token_account, authority]
+        ConfidentialTransferInstruction::ApplyPendingBalance => {
+            if context.account(0)? != *context.token_account {
+                return Ok(Vec::new());
+            }
+            let data = decode_instruction_data::<ApplyPendingBalanceData>(extension_data)?;
+            let new_available = decrypt_balance(aes_key, &data.new_decryptable_available_balance)?;
+            let amount = new_available.checked_sub(balances.available);
+            balances.pending = Some(0);
+            balances.available = new_available;
+            Ok(vec![(HistoryEvent::ApplyPendingBalance, amount)])
+        }
+        // Accounts: [token_account, mint, ...]
+        ConfidentialTransferInstruction::Withdraw => {
+            if context.account(0)? != *context.token_account {
+                return Ok(Vec::new());
+            }
+            let data = decode_instruction_data::<WithdrawInstructionData>(extension_data)?;
+            balances.available = decrypt_balance(aes_key, &data.new_decryptable_available_balance)?;
+            Ok(vec![(HistoryEvent::Withdraw, Some(u64::from(data.amount)))])
+        }
+        ConfidentialTransferInstruction::Transfer => {
+            let data = decode_instruction_data::<TransferInstructionData>(extension_data)?;
+            let offsets = [
+                data.equality_proof_instruction_offset,
+                data.ciphertext_validity_proof_instruction_offset,
+                data.range_proof_instruction_offset,
+            ];
+            decode_transfer(
+                context,
+                elgamal_keypair,
+                aes_key,
+                balances,
+                &data.new_source_decryptable_available_balance,
+                &offsets,
+                false,
+            )
+        }
+        ConfidentialTransferInstruction::TransferWithFee => {
+            let data = decode_instruction_data::<TransferWithFeeInstructionData>(extension_data)?;
+            let offsets = [
+                data.equality_proof_instruction_offset,
+                data.transfer_amount_ciphertext_validity_proof_instruction_offset,
+                data.fee_sigma_proof_instruction_offset,
+                data.fee_ciphertext_validity_proof_instruction_offset,
+                data.range_proof_instruction_offset,
+            ];
+            decode_transfer(
+                context,
+                elgamal_keypair,
+                aes_key,
+                balances,
+                &data.new_source_decryptable_available_balance,
+                &offsets,
+                true,
+            )
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Decode a `Transfer` or `TransferWithFee` instruction
+///
+/// `proof_offsets` are the instruction's proof offsets in account order,
+/// with the transfer amount ciphertext validity proof second. For fee
+/// transfers the destination's pending balance becomes unknown, since the
+/// withheld fee is only encrypted under the destination and fee authority.
+///
+/// Accounts: [source, mint, destination, (instructions sysvar), proof context accounts..., authority]
+fn decode_transfer(
+    context: &InstructionContext,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    balances: &mut Balances,
+    new_source_decryptable_available_balance: &DecryptableBalance,
+    proof_offsets: &[i8],
+    with_fee: bool,
+) -> CtResult<Vec<(HistoryEvent, Option<u64>)>> {
+    let source = context.account(0)?;
+    let destination = context.account(2)?;
+    let is_source = source == *context.token_account;
+    let is_destination = destination == *context.token_account;
+    if !is_source && !is_destination {
+        return Ok(Vec::new());
+    }
+
+    let validity_proof = transfer_validity_proof(context, proof_offsets)?;
+    let decrypt_handle = |handle: usize| -> Option<u64> {
+        let proof = validity_proof.as_ref()?;
+        let ciphertext_lo = proof.grouped_ciphertext_lo.try_extract_ciphertext(handle).ok()?;
+        let ciphertext_hi = proof.grouped_ciphertext_hi.try_extract_ciphertext(handle).ok()?;
+        decrypt_lo_hi(elgamal_keypair, &ciphertext_lo, &ciphertext_hi)
+    };
+
+    let mut events = Vec::new();
+
+    if is_source {
+        let new_available = decrypt_balance(aes_key, new_source_decryptable_available_balance)?;
+        let amount = decrypt_handle(SOURCE_HANDLE)
+            .or_else(|| balances.available.checked_sub(new_available));
+        balances.available = new_available;
+        events.push((HistoryEvent::TransferOut { destination }, amount));
+    }
+
+    if is_destination {
+        let amount = decrypt_handle(DESTINATION_HANDLE);
+        balances.pending = match (balances.pending, amount) {
+            (Some(pending), Some(amount)) if !with_fee => pending.checked_add(amount),
+            _ => None,
+        };
+        events.push((HistoryEvent::TransferIn { source }, amount));
+    }
+
+    Ok(events)
+}
+
+/// Locate the transfer amount ciphertext validity proof of a transfer
+///
+/// The proof is either verified by an instruction in the same transaction
+/// (non-zero offset) or stored in a context state account. Context state
+/// accounts are usually closed right after the transfer, in which case the
+/// proof is read back from the transaction that created the account.
+fn transfer_validity_proof(
+    context: &InstructionContext,
+    proof_offsets: &[i8],
+) -> CtResult<Option<BatchedGroupedCiphertext3HandlesValidityProofContext>> {
+    let validity_offset = proof_offsets[1];
+
+    if validity_offset != 0 {
+        let index = context.index as i64 + validity_offset as i64;
+        let proof = usize::try_from(index).ok()
+            .and_then(|index| context.instructions.get(index))
+            .and_then(|instruction| validity_proof_data(&instruction.data));
+        return Ok(proof);
+    }
+
+    // Context accounts follow the sysvar (if any proof is inline), in proof order
+    let has_sysvar = proof_offsets.iter().any(|offset| *offset != 0);
+    let position = 3 + usize::from(has_sysvar) + usize::from(proof_offsets[0] == 0);
+    let context_account = context.account(position)?;
+
+    if let Ok(account) = context.client.get_account(&context_account) {
+        if let Ok(state) = ProofContextState::<BatchedGroupedCiphertext3HandlesValidityProofContext>::try_from_bytes(&account.data) {
+            return Ok(Some(state.proof_context));
+        }
+    }
+
+    for status in account_signatures(context.client, &context_account)? {
+        if status.err.is_some() {
+            continue;
+        }
+        let signature: Signature = status.signature.parse()
+            .map_err(|_| format!("Invalid signature {}", status.signature))?;
+        let fetched = fetch_transaction(context.client, &signature)?;
+
+        for instruction in fetched.transaction.message.instructions() {
+            let program_id = fetched.account_keys.get(instruction.program_id_index as usize);
+            let creates_context = instruction.accounts.first()
+                .and_then(|index| fetched.account_keys.get(*index as usize))
+                == Some(&context_account);
+            if program_id != Some(&zk_elgamal_proof_program::id()) || !creates_context {
+                continue;
+            }
+            if let Some(proof) = validity_proof_data(&instruction.data) {
+                return Ok(Some(proof));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read a batched 3-handle validity proof context from proof instruction data
+fn validity_proof_data(data: &[u8]) -> Option<BatchedGroupedCiphertext3HandlesValidityProofContext> {
+    if ProofInstruction::instruction_type(data)? != ProofInstruction::VerifyBatchedGroupedCiphertext3HandlesValidity {
+        return None;
+    }
+    ProofInstruction::proof_data::<
+        BatchedGroupedCiphertext3HandlesValidityProofData,
+        BatchedGroupedCiphertext3HandlesValidityProofContext,
+    >(data)
+    .map(|proof| proof.context)
+}
+
+/// Decrypt an AE-encrypted decryptable balance
+fn decrypt_balance(aes_key: &AeKey, balance: &DecryptableBalance) -> CtResult<u64> {
+    let ciphertext: AeCiphertext = (*balance).try_into()
+        .map_err(|_| "Failed to convert decryptable balance")?;
+    Ok(aes_key.decrypt(&ciphertext)
+        .ok_or("Failed to decrypt decryptable balance with AES key")?)
+}
+
+/// Fetch every signature for an address, oldest first
+fn account_signatures(
+    client: &RpcClient,
+    address: &Pubkey,
+) -> CtResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+    let mut signatures = Vec::new();
+    let mut before = None;
+
+    loop {
+        let page = client.get_signatures_for_address_with_config(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until: None,
+                limit: Some(SIGNATURES_PAGE_SIZE),
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )?;
+        let last_page = page.len() < SIGNATURES_PAGE_SIZE;
+        before = match page.last() {
+            Some(status) => Some(status.signature.parse()
+                .map_err(|_| format!("Invalid signature {}", status.signature))?),
+            None => None,
+        };
+        signatures.extend(page);
+        if last_page || before.is_none() {
+            break;
+        }
+    }
+
+    signatures.reverse();
+    Ok(signatures)
+}
@@ -0,0 +1,491 @@
+//! Pluggable sources of ElGamal and AE keys
+//!
+//! Operations used to derive encryption keys from the owner's signer on
+//! every call. A `KeyProvider` decouples where keys come from (signer
+//! derivation, explicit key material, an encrypted file, the environment or
+//! a remote key service) from the operations that use them, and
+//! `CachingKeyProvider` makes sure each key is only derived once per session.
+
+use crate::types::*;
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit},
+    Aes256GcmSiv, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+use spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalSecretKey},
+};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Environment variable holding the hex-encoded ElGamal secret key
+pub const ELGAMAL_SECRET_ENV: &str = "CT_ELGAMAL_SECRET";
+
+/// Environment variable holding the hex-encoded AE key
+pub const AE_KEY_ENV: &str = "CT_AE_KEY";
+
+/// Default scrypt cost (log2 of N) for encrypted key files
+pub const DEFAULT_SCRYPT_LOG_N: u8 = 15;
+
+/// Highest scrypt cost accepted, so a crafted key file can't demand an
+/// arbitrarily expensive derivation (2^20 * 8 * 128 bytes is already 1 GiB)
+pub const MAX_SCRYPT_LOG_N: u8 = 20;
+
+/// scrypt block size and parallelism of every key file; others are refused,
+/// as large values cost as much memory or time as a large `scrypt_log_n`
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+const ELGAMAL_SECRET_LEN: usize = 32;
+const AE_KEY_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Source of the ElGamal and AE keys of confidential token accounts
+pub trait KeyProvider {
+    /// ElGamal keypair of a token account
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair>;
+
+    /// AE key of a token account
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey>;
+}
+
+/// Derives keys from a signer, the default used throughout this crate
+///
+/// Each call asks the signer for a signature; wrap it in a
+/// `CachingKeyProvider` to derive once per session.
+pub struct SignerKeyProvider<'a> {
+    signer: &'a dyn Signer,
+}
+
+impl<'a> SignerKeyProvider<'a> {
+    pub fn new(signer: &'a dyn Signer) -> Self {
+        Self { signer }
+    }
+
+    /// Signer-derived keys, cached after the first derivation
+    pub fn cached(signer: &'a dyn Signer) -> CachingKeyProvider<Self> {
+        CachingKeyProvider::new(Self::new(signer))
+    }
+}
+
+impl KeyProvider for SignerKeyProvider<'_> {
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        ElGamalKeypair::new_from_signer(self.signer, &token_account.to_bytes())
+    }
+
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey> {
+        AeKey::new_from_signer(self.signer, &token_account.to_bytes())
+    }
+}
+
+/// Fixed key material, used for every token account
+pub struct ExplicitKeyProvider {
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+}
+
+impl ExplicitKeyProvider {
+    pub fn new(elgamal_keypair: ElGamalKeypair, aes_key: AeKey) -> Self {
+        Self { elgamal_keypair, aes_key }
+    }
+
+    /// Build from raw ElGamal secret key and AE key bytes
+    pub fn from_bytes(elgamal_secret: &[u8], aes_key: &[u8]) -> CtResult<Self> {
+        let secret = ElGamalSecretKey::try_from(elgamal_secret)
+            .map_err(|_| "Invalid ElGamal secret key")?;
+        let aes_key = AeKey::try_from(aes_key)
+            .map_err(|_| "Invalid AE key")?;
+        Ok(Self::new(ElGamalKeypair::new(secret), aes_key))
+    }
+}
+
+impl KeyProvider for ExplicitKeyProvider {
+    fn elgamal_keypair(&self, _token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        Ok(self.elgamal_keypair.clone())
+    }
+
+    fn aes_key(&self, _token_account: &Pubkey) -> CtResult<AeKey> {
+        Ok(self.aes_key.clone())
+    }
+}
+
+/// Reads hex-encoded keys from environment variables on each call
+pub struct EnvKeyProvider {
+    elgamal_var: String,
+    aes_var: String,
+}
+
+impl EnvKeyProvider {
+    /// Read `CT_ELGAMAL_SECRET` and `CT_AE_KEY`
+    pub fn new() -> Self {
+        Self::with_vars(ELGAMAL_SECRET_ENV, AE_KEY_ENV)
+    }
+
+    /// Read keys from custom variable names
+    pub fn with_vars(elgamal_var: &str, aes_var: &str) -> Self {
+        Self {
+            elgamal_var: elgamal_var.to_string(),
+            aes_var: aes_var.to_string(),
+        }
+    }
+
+    fn read(&self) -> CtResult<ExplicitKeyProvider> {
+        let read_hex = |var: &str| -> CtResult<Vec<u8>> {
+            let value = env::var(var).map_err(|_| format!("{} is not set", var))?;
+            Ok(hex::decode(value.trim()).map_err(|_| format!("{} is not valid hex", var))?)
+        };
+        ExplicitKeyProvider::from_bytes(&read_hex(&self.elgamal_var)?, &read_hex(&self.aes_var)?)
+    }
+}
+
+impl Default for EnvKeyProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        self.read()?.elgamal_keypair(token_account)
+    }
+
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey> {
+        self.read()?.aes_key(token_account)
+    }
+}
+
+/// Password-encrypted ElGamal secret and AE key
+///
+/// The key is stretched with scrypt and the key material sealed with
+/// AES-256-GCM-SIV. The token account and ElGamal pubkey are stored in the
+/// clear so files can be identified without the password.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedKeyFile {
+    /// Token account the keys belong to, if bound to one
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub token_account: Option<Pubkey>,
+    /// ElGamal pubkey of the stored keypair
+    pub elgamal_pubkey: String,
+    /// scrypt cost parameters
+    pub scrypt_log_n: u8,
+    pub scrypt_r: u32,
+    pub scrypt_p: u32,
+    /// Hex-encoded scrypt salt
+    pub salt: String,
+    /// Hex-encoded AES-GCM-SIV nonce
+    pub nonce: String,
+    /// Hex-encoded sealed ElGamal secret key followed by AE key
+    pub ciphertext: String,
+}
+
+impl EncryptedKeyFile {
+    /// Seal key material under a password
+    pub fn seal(
+        token_account: Option<Pubkey>,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+        password: &str,
+        scrypt_log_n: u8,
+    ) -> CtResult<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = file_cipher(password, &salt, scrypt_log_n, SCRYPT_R, SCRYPT_P)?;
+
+        let mut plaintext = elgamal_keypair.secret().as_bytes().to_vec();
+        plaintext.extend_from_slice(&<[u8; AE_KEY_LEN]>::from(aes_key.clone()));
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| "Failed to encrypt key material")?;
+
+        Ok(Self {
+            token_account,
+            elgamal_pubkey: elgamal_keypair.pubkey().to_string(),
+            scrypt_log_n,
+            scrypt_r: SCRYPT_R,
+            scrypt_p: SCRYPT_P,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Decrypt the key material with a password
+    pub fn open(&self, password: &str) -> CtResult<ExplicitKeyProvider> {
+        let salt = hex::decode(&self.salt).map_err(|_| "Invalid salt")?;
+        let nonce = hex::decode(&self.nonce).map_err(|_| "Invalid nonce")?;
+        let ciphertext = hex::decode(&self.ciphertext).map_err(|_| "Invalid ciphertext")?;
+        if nonce.len() != NONCE_LEN {
+            return Err("Invalid nonce length".into());
+        }
+
+        let cipher = file_cipher(password, &salt, self.scrypt_log_n, self.scrypt_r, self.scrypt_p)?;
+        let plaintext = cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "Wrong password or corrupted key file")?;
+        if plaintext.len() != ELGAMAL_SECRET_LEN + AE_KEY_LEN {
+            return Err("Unexpected key material length".into());
+        }
+
+        ExplicitKeyProvider::from_bytes(&plaintext[..ELGAMAL_SECRET_LEN], &plaintext[ELGAMAL_SECRET_LEN..])
+    }
+
+    pub fn load(path: &Path) -> CtResult<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Write a new key file readable by its owner only
+    ///
+    /// The file is created with its final permissions, so the encrypted keys
+    /// are never readable by others, and never replaces an existing file.
+    pub fn save(&self, path: &Path) -> CtResult<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Derive the file encryption key from a password
+fn file_cipher(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> CtResult<Aes256GcmSiv> {
+    if log_n > MAX_SCRYPT_LOG_N {
+        return Err(format!("scrypt cost 2^{} exceeds the maximum of 2^{}", log_n, MAX_SCRYPT_LOG_N).into());
+    }
+    if (r, p) != (SCRYPT_R, SCRYPT_P) {
+        return Err(format!("Unsupported scrypt parameters r={} p={} (expected r={} p={})", r, p, SCRYPT_R, SCRYPT_P).into());
+    }
+    let params = scrypt::Params::new(log_n, r, p, 32)
+        .map_err(|_| "Invalid scrypt parameters")?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| "Failed to derive key file encryption key")?;
+    Ok(Aes256GcmSiv::new_from_slice(&key).map_err(|_| "Invalid key file encryption key")?)
+}
+
+/// Reads keys from a password-encrypted key file
+///
+/// The file is decrypted on each call (scrypt is deliberately slow), so
+/// wrap it in a `CachingKeyProvider` for repeated use.
+pub struct EncryptedFileKeyProvider {
+    path: PathBuf,
+    password: String,
+}
+
+impl EncryptedFileKeyProvider {
+    pub fn new(path: impl Into<PathBuf>, password: &str) -> Self {
+        Self {
+            path: path.into(),
+            password: password.to_string(),
+        }
+    }
+
+    fn open(&self, token_account: &Pubkey) -> CtResult<ExplicitKeyProvider> {
+        let file = EncryptedKeyFile::load(&self.path)?;
+        if file.token_account.is_some_and(|bound| bound != *token_account) {
+            return Err(format!(
+                "Key file {} belongs to another token account",
+                self.path.display()
+            ).into());
+        }
+        file.open(&self.password)
+    }
+}
+
+impl KeyProvider for EncryptedFileKeyProvider {
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        self.open(token_account)?.elgamal_keypair(token_account)
+    }
+
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey> {
+        self.open(token_account)?.aes_key(token_account)
+    }
+}
+
+/// Request sent to a remote key service
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+struct RemoteKeyRequest {
+    /// Shared secret the service was started with
+    auth_token: String,
+    #[serde_as(as = "DisplayFromStr")]
+    token_account: Pubkey,
+}
+
+/// Response of a remote key service
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RemoteKeyResponse {
+    elgamal_secret: Option<String>,
+    aes_key: Option<String>,
+    error: Option<String>,
+}
+
+/// Fetches keys from a remote key service
+///
+/// Stand-in for a real KMS/HSM integration: speaks one JSON line per
+/// request over plain TCP, authenticated with a shared token. Use it
+/// against `serve_key_requests` on localhost only, since key material
+/// travels unencrypted.
+pub struct RemoteKeyProvider {
+    address: String,
+    auth_token: String,
+}
+
+impl RemoteKeyProvider {
+    pub fn new(address: impl ToSocketAddrs + ToString, auth_token: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            auth_token: auth_token.to_string(),
+        }
+    }
+
+    fn fetch(&self, token_account: &Pubkey) -> CtResult<ExplicitKeyProvider> {
+        let mut stream = TcpStream::connect(&self.address)
+            .map_err(|e| format!("Failed to reach key service at {}: {}", self.address, e))?;
+        let request = serde_json::to_string(&RemoteKeyRequest {
+            auth_token: self.auth_token.clone(),
+            token_account: *token_account,
+        })?;
+        writeln!(stream, "{}", request)?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        let response: RemoteKeyResponse = serde_json::from_str(&line)?;
+        if let Some(error) = response.error {
+            return Err(format!("Key service error: {}", error).into());
+        }
+
+        let elgamal_secret = hex::decode(response.elgamal_secret.ok_or("Key service sent no ElGamal secret")?)
+            .map_err(|_| "Key service sent invalid ElGamal secret")?;
+        let aes_key = hex::decode(response.aes_key.ok_or("Key service sent no AE key")?)
+            .map_err(|_| "Key service sent invalid AE key")?;
+        ExplicitKeyProvider::from_bytes(&elgamal_secret, &aes_key)
+    }
+}
+
+impl KeyProvider for RemoteKeyProvider {
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        self.fetch(token_account)?.elgamal_keypair(token_account)
+    }
+
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey> {
+        self.fetch(token_account)?.aes_key(token_account)
+    }
+}
+
+/// Answer remote key requests from another provider (local stand-in service)
+///
+/// Only requests carrying `auth_token` get keys. Bind the listener to a
+/// loopback address: anyone holding the token can read the keys. Serves
+/// `max_requests` connections, or forever if `None`; a failing connection
+/// is logged and doesn't stop the service.
+pub fn serve_key_requests(
+    listener: TcpListener,
+    provider: &dyn KeyProvider,
+    auth_token: &str,
+    max_requests: Option<usize>,
+) -> CtResult<()> {
+    if auth_token.is_empty() {
+        return Err("Key service needs a non-empty auth token".into());
+    }
+    let mut served = 0;
+    while max_requests.is_none_or(|max| served < max) {
+        let (stream, peer) = listener.accept()?;
+        served += 1;
+        if let Err(e) = answer_key_request(stream, provider, auth_token) {
+            tracing::warn!("Failed to answer key request from {}: {}", peer, e);
+        }
+    }
+    Ok(())
+}
+
+/// Answer the single request of one key service connection
+fn answer_key_request(mut stream: TcpStream, provider: &dyn KeyProvider, auth_token: &str) -> CtResult<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match serde_json::from_str::<RemoteKeyRequest>(&line) {
+        Ok(request) if !constant_time_eq(request.auth_token.as_bytes(), auth_token.as_bytes()) => {
+            RemoteKeyResponse { error: Some("Unauthorized".to_string()), ..Default::default() }
+        }
+        Ok(request) => {
+            let keys = provider.elgamal_keypair(&request.token_account).and_then(|elgamal_keypair| {
+                Ok((elgamal_keypair, provider.aes_key(&request.token_account)?))
+            });
+            match keys {
+                Ok((elgamal_keypair, aes_key)) => RemoteKeyResponse {
+                    elgamal_secret: Some(hex::encode(elgamal_keypair.secret().as_bytes())),
+                    aes_key: Some(hex::encode(<[u8; AE_KEY_LEN]>::from(aes_key))),
+                    error: None,
+                },
+                Err(e) => RemoteKeyResponse { error: Some(e.to_string()), ..Default::default() },
+            }
+        }
+        Err(e) => RemoteKeyResponse { error: Some(format!("Invalid request: {}", e)), ..Default::default() },
+    };
+
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+/// Compare secrets without leaking where they differ through timing
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Caches the keys of another provider per token account
+pub struct CachingKeyProvider<P: KeyProvider> {
+    inner: P,
+    elgamal_keypairs: Mutex<HashMap<Pubkey, ElGamalKeypair>>,
+    aes_keys: Mutex<HashMap<Pubkey, AeKey>>,
+}
+
+impl<P: KeyProvider> CachingKeyProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            elgamal_keypairs: Mutex::new(HashMap::new()),
+            aes_keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget all cached keys
+    pub fn clear(&self) {
+        self.elgamal_keypairs.lock().unwrap().clear();
+        self.aes_keys.lock().unwrap().clear();
+    }
+}
+
+impl<P: KeyProvider> KeyProvider for CachingKeyProvider<P> {
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        if let Some(keypair) = self.elgamal_keypairs.lock().unwrap().get(token_account) {
+            return Ok(keypair.clone());
+        }
+        let keypair = self.inner.elgamal_keypair(token_account)?;
+        self.elgamal_keypairs.lock().unwrap().insert(*token_account, keypair.clone());
+        Ok(keypair)
+    }
+
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey> {
+        if let Some(key) = self.aes_keys.lock().unwrap().get(token_account) {
+            return Ok(key.clone());
+        }
+        let key = self.inner.aes_key(token_account)?;
+        self.aes_keys.lock().unwrap().insert(*token_account, key.clone());
+        Ok(key)
+    }
+}
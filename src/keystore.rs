@@ -0,0 +1,179 @@
+//! Encrypted local keystore for ElGamal and AE keys
+//!
+//! Services that only need to read confidential balances shouldn't hold the
+//! owner's wallet keypair. The keystore exports the keys of a token account
+//! once (e.g. derived from the owner's signer) into a password-encrypted
+//! file, and later serves them as a `KeyProvider` without the spending key.
+//!
+//! Each token account gets one `<token account>.json` file in the keystore
+//! directory, in the `EncryptedKeyFile` format (scrypt + AES-256-GCM-SIV).
+//! Note that the keys only decrypt; spending still needs the owner's signature.
+
+use crate::events::{self, Operation};
+use crate::key_provider::{
+    CachingKeyProvider, EncryptedKeyFile, ExplicitKeyProvider, KeyProvider, SignerKeyProvider,
+    DEFAULT_SCRYPT_LOG_N,
+};
+use crate::types::*;
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Account stored in a keystore
+#[derive(Debug, Clone)]
+pub struct KeystoreEntry {
+    pub token_account: Pubkey,
+    pub elgamal_pubkey: String,
+    pub path: PathBuf,
+}
+
+/// Directory of password-encrypted key files, one per token account
+pub struct Keystore {
+    dir: PathBuf,
+    scrypt_log_n: u8,
+}
+
+impl Keystore {
+    /// Open a keystore directory, creating it if needed
+    pub fn open(dir: impl Into<PathBuf>) -> CtResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, scrypt_log_n: DEFAULT_SCRYPT_LOG_N })
+    }
+
+    /// Use a different scrypt cost for newly exported files
+    pub fn with_scrypt_log_n(mut self, scrypt_log_n: u8) -> Self {
+        self.scrypt_log_n = scrypt_log_n;
+        self
+    }
+
+    /// Path of the key file for a token account
+    pub fn path_for(&self, token_account: &Pubkey) -> PathBuf {
+        self.dir.join(format!("{}.json", token_account))
+    }
+
+    /// Export the keys of a token account from any key provider
+    ///
+    /// Refuses to overwrite an existing file; `remove` it first to replace
+    /// keys (e.g. after a rotation).
+    pub fn export(
+        &self,
+        keys: &dyn KeyProvider,
+        token_account: &Pubkey,
+        password: &str,
+    ) -> CtResult<PathBuf> {
+        let path = self.path_for(token_account);
+        if path.exists() {
+            return Err(format!("Keystore already holds keys for {}", token_account).into());
+        }
+
+        let elgamal_keypair = keys.elgamal_keypair(token_account)?;
+        let aes_key = keys.aes_key(token_account)?;
+        let file = EncryptedKeyFile::seal(Some(*token_account), &elgamal_keypair, &aes_key, password, self.scrypt_log_n)?;
+        file.save(&path)?;
+
+        events::completed(Operation::KeyExport, format!("Exported keys of {} to {}", token_account, path.display()));
+
+        Ok(path)
+    }
+
+    /// Export the signer-derived keys of the owner's associated token account
+    pub fn export_from_signer(
+        &self,
+        owner: &dyn Signer,
+        mint: &Pubkey,
+        password: &str,
+    ) -> CtResult<PathBuf> {
+        let token_account = get_associated_token_address_with_program_id(
+            &owner.pubkey(),
+            mint,
+            &spl_token_2022::id(),
+        );
+        self.export(&SignerKeyProvider::new(owner), &token_account, password)
+    }
+
+    /// Decrypt the stored keys of a token account
+    pub fn import(&self, token_account: &Pubkey, password: &str) -> CtResult<ExplicitKeyProvider> {
+        let path = self.path_for(token_account);
+        if !path.exists() {
+            return Err(format!("No keys stored for {}", token_account).into());
+        }
+        EncryptedKeyFile::load(&path)?.open(password)
+    }
+
+    /// Copy an exported key file (e.g. from another machine) into the keystore
+    ///
+    /// The password is checked before the file is stored. Returns the token
+    /// account the file belongs to.
+    pub fn import_file(&self, source: &Path, password: &str) -> CtResult<Pubkey> {
+        let file = EncryptedKeyFile::load(source)?;
+        let token_account = file.token_account
+            .ok_or("Key file isn't bound to a token account")?;
+        file.open(password)?;
+
+        let path = self.path_for(&token_account);
+        if path.exists() {
+            return Err(format!("Keystore already holds keys for {}", token_account).into());
+        }
+        file.save(&path)?;
+
+        Ok(token_account)
+    }
+
+    /// List stored accounts without decrypting anything
+    pub fn list(&self) -> CtResult<Vec<KeystoreEntry>> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let Ok(file) = EncryptedKeyFile::load(&path) else {
+                continue;
+            };
+            if let Some(token_account) = file.token_account {
+                entries.push(KeystoreEntry {
+                    token_account,
+                    elgamal_pubkey: file.elgamal_pubkey,
+                    path,
+                });
+            }
+        }
+        entries.sort_by_key(|entry| entry.token_account.to_string());
+        Ok(entries)
+    }
+
+    /// Delete the stored keys of a token account
+    pub fn remove(&self, token_account: &Pubkey) -> CtResult<()> {
+        fs::remove_file(self.path_for(token_account))?;
+        Ok(())
+    }
+
+    /// Key provider over every account in the keystore
+    ///
+    /// Files are decrypted on first use and cached for the session.
+    pub fn unlock(&self, password: &str) -> CachingKeyProvider<KeystoreKeyProvider<'_>> {
+        CachingKeyProvider::new(KeystoreKeyProvider {
+            keystore: self,
+            password: password.to_string(),
+        })
+    }
+}
+
+/// Serves keys from a keystore, see `Keystore::unlock`
+pub struct KeystoreKeyProvider<'a> {
+    keystore: &'a Keystore,
+    password: String,
+}
+
+impl KeyProvider for KeystoreKeyProvider<'_> {
+    fn elgamal_keypair(&self, token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        self.keystore.import(token_account, &self.password)?.elgamal_keypair(token_account)
+    }
+
+    fn aes_key(&self, token_account: &Pubkey) -> CtResult<AeKey> {
+        self.keystore.import(token_account, &self.password)?.aes_key(token_account)
+    }
+}
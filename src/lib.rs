@@ -4,11 +4,35 @@
 //! using the latest API versions specified in docs/reference/rust-deps.md
 
 pub mod types;
+pub mod events;
+pub mod signer;
+pub mod key_provider;
+pub mod keystore;
+pub mod authority;
+pub mod receipt;
+pub mod accounts;
 pub mod configure;
 pub mod deposit;
 pub mod apply_pending;
 pub mod withdraw;
+pub mod decryption;
+pub mod proofs;
+pub mod proof_placement;
+pub mod proof_accounts;
+pub mod memo;
+pub mod verification;
+pub mod recipient;
 pub mod transfer;
+pub mod payouts;
+pub mod fees;
+pub mod mint_burn;
+pub mod auditor;
+pub mod audit_report;
+pub mod history;
+pub mod decoder;
+pub mod rotation;
+pub mod watch;
+pub mod pending_watcher;
 
 // Re-export common types
 pub use types::*;
@@ -0,0 +1,24 @@
+//! SPL Memo instructions for memo-required token accounts
+//!
+//! Token accounts with the `MemoTransfer` extension requiring incoming
+//! memos reject a transfer unless the instruction right before it is an
+//! SPL Memo. Operations taking a memo put it there; deposits and
+//! withdrawals accept one too, to annotate them.
+
+use solana_sdk::instruction::Instruction;
+
+/// SPL Memo (v3) instruction carrying `memo`, without signers
+pub fn memo_instruction(memo: &str) -> Instruction {
+    spl_memo_interface::instruction::build_memo(&spl_memo_interface::v3::id(), memo.as_bytes(), &[])
+}
+
+/// `instructions` preceded by a memo instruction, when there's a memo
+///
+/// The Token-2022 instruction must come first in `instructions` (inline
+/// proof instructions follow it), so the memo lands immediately before it.
+pub fn with_memo(memo: Option<&str>, instructions: Vec<Instruction>) -> Vec<Instruction> {
+    match memo {
+        Some(memo) => std::iter::once(memo_instruction(memo)).chain(instructions).collect(),
+        None => instructions,
+    }
+}
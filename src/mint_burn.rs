@@ -0,0 +1,515 @@
+//! Confidential mint and burn
+//!
+//! Mints with the `ConfidentialMintBurn` extension keep an encrypted total
+//! supply. The mint authority mints directly into a recipient's pending
+//! confidential balance and holders burn from their available balance, so
+//! amounts never touch a public balance.
+//!
+//! The supply is encrypted under a dedicated supply ElGamal keypair and the
+//! mint stores an AES-encrypted "decryptable supply" for the mint authority.
+//! Minting and burning use proof context state accounts because the
+//! equality, 3-handle ciphertext validity and U128 range proofs do not fit
+//! in a single transaction.
+
+use crate::authority::unique_signers;
+use crate::decryption;
+use crate::events::{self, Operation, OperationEvent};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::proof_accounts::{close_context_state_accounts, create_proof_account};
+use crate::proof_placement::{with_record_fallback, ProofPlacement};
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
+use crate::types::*;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_mint_burn::{
+            account_info::{BurnAccountInfo, SupplyAccountInfo},
+            instruction::{
+                apply_pending_burn as apply_pending_burn_instruction,
+                confidential_burn_with_split_proofs, confidential_mint_with_split_proofs,
+                rotate_supply_elgamal_pubkey as rotate_supply_elgamal_pubkey_instruction,
+            },
+            ConfidentialMintBurn,
+        },
+        confidential_transfer::{ConfidentialTransferAccount, ConfidentialTransferMint},
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalKeypair, ElGamalPubkey},
+            pod::elgamal::PodElGamalPubkey,
+        },
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU128Data,
+            CiphertextCommitmentEqualityProofData,
+        },
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+use spl_token_confidential_transfer_proof_generation::{
+    burn::BurnProofData,
+    mint::MintProofData,
+};
+
+/// Mint tokens directly into a recipient's pending confidential balance
+///
+/// This implementation:
+/// 1. Fetches the recipient's and auditor's ElGamal public keys
+/// 2. Decrypts the current supply with the supply keys and generates proofs
+/// 3. Creates proof context state accounts for the equality, ciphertext
+///    validity and range proofs
+/// 4. Executes the confidential mint and updates the decryptable supply
+/// 5. Closes the proof accounts to reclaim rent, even if the mint fails
+///
+/// # Arguments
+/// * `payer` - Pays the fees and the proof accounts' rent, and gets the rent back
+/// * `mint_authority` - Mint authority
+/// * `supply_elgamal_keypair` - Keypair matching the mint's `supply_elgamal_pubkey`
+/// * `supply_aes_key` - AES key used for the mint's `decryptable_supply`
+/// * `recipient` - Recipient wallet (its ATA must be configured)
+///
+/// Returns the receipt of all transactions (proof creation + mint + cleanup)
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(%mint, %recipient, amount))]
+pub async fn confidential_mint_to(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    mint_authority: &dyn Signer,
+    supply_elgamal_keypair: &ElGamalKeypair,
+    supply_aes_key: &AeKey,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+) -> ReceiptResult {
+    let recipient_token_account = get_associated_token_address_with_program_id(
+        recipient,
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    // Fetch recipient's ElGamal public key from their account
+    let recipient_account_data = client.get_account(&recipient_token_account)?;
+    let recipient_account = StateWithExtensions::<TokenAccount>::unpack(&recipient_account_data.data)?;
+    let recipient_ct_extension = recipient_account.get_extension::<ConfidentialTransferAccount>()?;
+    let recipient_elgamal_pubkey: ElGamalPubkey = recipient_ct_extension.elgamal_pubkey.try_into()
+        .map_err(|_| "Failed to convert recipient ElGamal pubkey")?;
+
+    // Fetch supply state and auditor from the mint
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let auditor_elgamal_pubkey = auditor_elgamal_pubkey(&mint_account)?;
+    let supply_info = SupplyAccountInfo::new(mint_account.get_extension::<ConfidentialMintBurn>()?);
+
+    let MintProofData {
+        equality_proof_data,
+        ciphertext_validity_proof_data_with_ciphertext,
+        range_proof_data,
+    } = supply_info.generate_split_mint_proof_data(
+        amount,
+        supply_elgamal_keypair,
+        supply_aes_key,
+        &recipient_elgamal_pubkey,
+        auditor_elgamal_pubkey.as_ref(),
+    )?;
+    events::emit(OperationEvent::ProofGenerated { operation: Operation::ConfidentialMint, amount, timings: None });
+
+    let new_decryptable_supply = supply_info
+        .new_decryptable_supply(amount, supply_elgamal_keypair, supply_aes_key)?
+        .into();
+
+    let equality_proof_account = Keypair::new();
+    let ciphertext_validity_proof_account = Keypair::new();
+    let range_proof_account = Keypair::new();
+    let proof_accounts = [&equality_proof_account, &ciphertext_validity_proof_account, &range_proof_account];
+
+    let [equality_proof_pubkey, ciphertext_validity_proof_pubkey, range_proof_pubkey] =
+        proof_accounts.map(|proof_account| proof_account.pubkey());
+
+    let mut sent = SentTransactions::default();
+    let mut created = Vec::new();
+    let minted = create_proof_accounts(
+        client,
+        Operation::ConfidentialMint,
+        payer,
+        proof_accounts,
+        (
+            &equality_proof_data,
+            &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            &range_proof_data,
+        ),
+        &mut sent,
+        &mut created,
+    ).and_then(|()| {
+        let mint_instructions = confidential_mint_with_split_proofs(
+            &spl_token_2022::id(),
+            &recipient_token_account,
+            mint,
+            &ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+            &ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+            &mint_authority.pubkey(),
+            &[],
+            ProofLocation::ContextStateAccount(&equality_proof_pubkey),
+            ProofLocation::ContextStateAccount(&ciphertext_validity_proof_pubkey),
+            ProofLocation::ContextStateAccount(&range_proof_pubkey),
+            &new_decryptable_supply,
+        )?;
+        send(client, Operation::ConfidentialMint, payer, mint_authority, &mint_instructions)
+    });
+    if let Ok(signature) = minted {
+        sent.push(TransactionRole::ConfidentialMint, signature);
+    }
+
+    // Reclaim the rent whether or not the mint went through
+    close_context_state_accounts(client, Operation::ConfidentialMint, payer, &created, &mut sent);
+    minted?;
+
+    events::completed(
+        Operation::ConfidentialMint,
+        format!("Confidential mint complete with {} transactions", sent.transactions.len()),
+    );
+
+    Ok(fetch_receipt(client, Operation::ConfidentialMint, Some(&recipient_token_account), &sent, None))
+}
+
+/// Burn tokens from the owner's available confidential balance
+///
+/// The burn amount is encrypted under the supply ElGamal pubkey and recorded
+/// as a pending burn on the mint. The mint authority folds pending burns into
+/// the confidential supply with [`apply_pending_burn`].
+///
+/// `payer` pays the fees and the proof accounts' rent, and gets the rent
+/// back; the proof accounts are closed even if the burn fails.
+///
+/// Returns the receipt of all transactions (proof creation + burn +
+/// cleanup) with the owner's remaining balances
+pub async fn confidential_burn(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    owner: &dyn Signer,
+    mint: &Pubkey,
+    amount: u64,
+) -> ReceiptResult {
+    confidential_burn_with_keys(client, payer, owner, mint, amount, &SignerKeyProvider::new(owner)).await
+}
+
+/// Burn with the owner's keys taken from a key provider
+///
+/// Same as `confidential_burn`, for owners whose keys aren't derived from
+/// their keypair.
+#[tracing::instrument(skip_all, fields(owner = %owner.pubkey(), %mint, amount))]
+pub async fn confidential_burn_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    owner: &dyn Signer,
+    mint: &Pubkey,
+    amount: u64,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    let token_account = get_associated_token_address_with_program_id(
+        &owner.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    // Fetch supply pubkey and auditor from the mint
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let auditor_elgamal_pubkey = auditor_elgamal_pubkey(&mint_account)?;
+    let mint_burn_extension = mint_account.get_extension::<ConfidentialMintBurn>()?;
+    let supply_elgamal_pubkey: ElGamalPubkey = mint_burn_extension.supply_elgamal_pubkey.try_into()
+        .map_err(|_| "Failed to convert supply ElGamal pubkey")?;
+
+    // Fetch owner's encryption keys
+    let elgamal_keypair = keys.elgamal_keypair(&token_account)?;
+    let aes_key = keys.aes_key(&token_account)?;
+
+    // Fetch owner's account state
+    let account_data = client.get_account(&token_account)?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+    let ct_extension = account.get_extension::<ConfidentialTransferAccount>()?;
+    let burn_info = BurnAccountInfo::new(ct_extension);
+
+    // Verify sufficient balance
+    let available_balance: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext =
+        ct_extension.available_balance.try_into()
+            .map_err(|_| "Failed to convert available_balance")?;
+
+    let current_available = decryption::engine().decrypt_u32(elgamal_keypair.secret(), &available_balance)
+        .ok_or("Failed to decrypt available balance")?;
+
+    if current_available < amount {
+        return Err(format!(
+            "Insufficient balance: have {}, need {}",
+            current_available, amount
+        ).into());
+    }
+
+    let BurnProofData {
+        equality_proof_data,
+        ciphertext_validity_proof_data_with_ciphertext,
+        range_proof_data,
+    } = burn_info.generate_split_burn_proof_data(
+        amount,
+        &elgamal_keypair,
+        &aes_key,
+        &supply_elgamal_pubkey,
+        auditor_elgamal_pubkey.as_ref(),
+    )?;
+    events::emit(OperationEvent::ProofGenerated { operation: Operation::ConfidentialBurn, amount, timings: None });
+
+    let new_decryptable_balance = burn_info.new_decryptable_balance(amount, &aes_key)?.into();
+
+    let equality_proof_account = Keypair::new();
+    let ciphertext_validity_proof_account = Keypair::new();
+    let range_proof_account = Keypair::new();
+    let proof_accounts = [&equality_proof_account, &ciphertext_validity_proof_account, &range_proof_account];
+
+    let [equality_proof_pubkey, ciphertext_validity_proof_pubkey, range_proof_pubkey] =
+        proof_accounts.map(|proof_account| proof_account.pubkey());
+
+    let mut sent = SentTransactions::default();
+    let mut created = Vec::new();
+    let burned = create_proof_accounts(
+        client,
+        Operation::ConfidentialBurn,
+        payer,
+        proof_accounts,
+        (
+            &equality_proof_data,
+            &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            &range_proof_data,
+        ),
+        &mut sent,
+        &mut created,
+    ).and_then(|()| {
+        let burn_instructions = confidential_burn_with_split_proofs(
+            &spl_token_2022::id(),
+            &token_account,
+            mint,
+            &new_decryptable_balance,
+            &ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+            &ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+            &owner.pubkey(),
+            &[],
+            ProofLocation::ContextStateAccount(&equality_proof_pubkey),
+            ProofLocation::ContextStateAccount(&ciphertext_validity_proof_pubkey),
+            ProofLocation::ContextStateAccount(&range_proof_pubkey),
+        )?;
+        send(client, Operation::ConfidentialBurn, payer, owner, &burn_instructions)
+    });
+    if let Ok(signature) = burned {
+        sent.push(TransactionRole::ConfidentialBurn, signature);
+    }
+
+    // Reclaim the rent whether or not the burn went through
+    close_context_state_accounts(client, Operation::ConfidentialBurn, payer, &created, &mut sent);
+    burned?;
+
+    events::completed(
+        Operation::ConfidentialBurn,
+        format!("Confidential burn complete with {} transactions", sent.transactions.len()),
+    );
+
+    Ok(fetch_receipt(client, Operation::ConfidentialBurn, Some(&token_account), &sent, Some(keys)))
+}
+
+/// Fold pending burns into the mint's confidential supply
+///
+/// Burns are accumulated in the mint's `pending_burn` ciphertext so holders
+/// don't contend on the supply. The mint authority must apply them before
+/// the supply (and decryptable supply) reflect the burned amounts.
+#[tracing::instrument(skip_all, fields(%mint))]
+pub async fn apply_pending_burn(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    mint_authority: &dyn Signer,
+    mint: &Pubkey,
+) -> ReceiptResult {
+    let apply_ix = apply_pending_burn_instruction(
+        &spl_token_2022::id(),
+        mint,
+        &mint_authority.pubkey(),
+        &[],
+    )?;
+
+    // Send transaction
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[apply_ix],
+        Some(&payer.pubkey()),
+        &unique_signers(&[payer, mint_authority]),
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::ApplyPendingBurn, &transaction)?;
+    events::completed(Operation::ApplyPendingBurn, format!("Applied pending burn to confidential supply: {}", signature));
+
+    let sent = SentTransactions::single(TransactionRole::ApplyPendingBurn, signature, &[]);
+    Ok(fetch_receipt(client, Operation::ApplyPendingBurn, None, &sent, None))
+}
+
+/// Decrypt the mint's confidential supply
+///
+/// Combines the AES-encrypted decryptable supply with the difference to the
+/// ElGamal supply ciphertext, so only a small discrete log has to be solved.
+pub fn get_confidential_supply(
+    client: &RpcClient,
+    supply_elgamal_keypair: &ElGamalKeypair,
+    supply_aes_key: &AeKey,
+    mint: &Pubkey,
+) -> CtResult<u64> {
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let supply_info = SupplyAccountInfo::new(mint_account.get_extension::<ConfidentialMintBurn>()?);
+
+    let supply = supply_info.decrypted_current_supply(supply_aes_key, supply_elgamal_keypair)?;
+
+    Ok(supply)
+}
+
+/// Rotate the supply ElGamal pubkey to a new keypair
+///
+/// The current supply is re-encrypted under the new pubkey and a
+/// ciphertext-ciphertext equality proof (inline, offset 1) shows both
+/// ciphertexts hold the same amount. The decryptable supply is unchanged
+/// because it is encrypted with the AES key, not the ElGamal key.
+#[tracing::instrument(skip_all, fields(%mint))]
+pub async fn rotate_supply_elgamal_pubkey(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    mint_authority: &dyn Signer,
+    current_supply_elgamal_keypair: &ElGamalKeypair,
+    new_supply_elgamal_keypair: &ElGamalKeypair,
+    supply_aes_key: &AeKey,
+    mint: &Pubkey,
+) -> ReceiptResult {
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let supply_info = SupplyAccountInfo::new(mint_account.get_extension::<ConfidentialMintBurn>()?);
+
+    let proof_data = supply_info.generate_rotate_supply_elgamal_pubkey_proof(
+        current_supply_elgamal_keypair,
+        new_supply_elgamal_keypair.pubkey(),
+        supply_aes_key,
+    )?;
+
+    let new_supply_elgamal_pubkey: PodElGamalPubkey = (*new_supply_elgamal_keypair.pubkey()).into();
+
+    let rotate_instructions = rotate_supply_elgamal_pubkey_instruction(
+        &spl_token_2022::id(),
+        mint,
+        &mint_authority.pubkey(),
+        &[],
+        &new_supply_elgamal_pubkey,
+        ProofLocation::InstructionOffset(1.try_into().unwrap(), &proof_data),
+    )?;
+
+    // Send transaction
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &rotate_instructions,
+        Some(&payer.pubkey()),
+        &unique_signers(&[payer, mint_authority]),
+        recent_blockhash,
+    );
+
+    let signature = events::send_and_confirm(client, Operation::RotateSupplyKey, &transaction)?;
+    events::completed(Operation::RotateSupplyKey, format!("Rotated supply ElGamal pubkey: {}", signature));
+
+    let sent = SentTransactions::single(TransactionRole::RotateSupplyKey, signature, &[]);
+    Ok(fetch_receipt(client, Operation::RotateSupplyKey, None, &sent, None))
+}
+
+/// Read the optional auditor ElGamal pubkey from the mint
+pub(crate) fn auditor_elgamal_pubkey(
+    mint_account: &StateWithExtensions<Mint>,
+) -> CtResult<Option<ElGamalPubkey>> {
+    let mint_ct_extension = mint_account.get_extension::<ConfidentialTransferMint>()?;
+    let auditor_elgamal_pubkey = Option::<PodElGamalPubkey>::from(mint_ct_extension.auditor_elgamal_pubkey)
+        .map(|pk| pk.try_into())
+        .transpose()
+        .map_err(|_| "Failed to convert auditor ElGamal pubkey")?;
+    Ok(auditor_elgamal_pubkey)
+}
+
+/// Verify the equality, ciphertext validity and range proofs into context state accounts
+///
+/// `payer` pays for the accounts and may close them. A proof whose
+/// verification doesn't fit in one transaction is written to a record first.
+/// Each account is added to `created` (with the role of its close) as soon
+/// as it exists, so the caller can close those made before a failure.
+fn create_proof_accounts(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    [equality_proof_account, ciphertext_validity_proof_account, range_proof_account]: [&Keypair; 3],
+    (equality_proof_data, ciphertext_validity_proof_data, range_proof_data): (
+        &CiphertextCommitmentEqualityProofData,
+        &BatchedGroupedCiphertext3HandlesValidityProofData,
+        &BatchedRangeProofU128Data,
+    ),
+    sent: &mut SentTransactions,
+    created: &mut Vec<(TransactionRole, Pubkey)>,
+) -> CtResult<()> {
+    let payer_pubkey = payer.pubkey();
+    create_proof_account(
+        client,
+        operation,
+        payer,
+        with_record_fallback(ProofPlacement::ContextState, &payer_pubkey, &payer_pubkey, equality_proof_data)?,
+        (TransactionRole::EqualityProofCreate, TransactionRole::EqualityProofClose),
+        equality_proof_account,
+        equality_proof_data,
+        sent,
+        created,
+    )?;
+    create_proof_account(
+        client,
+        operation,
+        payer,
+        with_record_fallback(ProofPlacement::ContextState, &payer_pubkey, &payer_pubkey, ciphertext_validity_proof_data)?,
+        (TransactionRole::CiphertextValidityProofCreate, TransactionRole::CiphertextValidityProofClose),
+        ciphertext_validity_proof_account,
+        ciphertext_validity_proof_data,
+        sent,
+        created,
+    )?;
+    create_proof_account(
+        client,
+        operation,
+        payer,
+        with_record_fallback(ProofPlacement::ContextState, &payer_pubkey, &payer_pubkey, range_proof_data)?,
+        (TransactionRole::RangeProofCreate, TransactionRole::RangeProofClose),
+        range_proof_account,
+        range_proof_data,
+        sent,
+        created,
+    )
+}
+
+/// Send a transaction signed by the payer and authority
+fn send(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    instructions: &[Instruction],
+) -> SigResult {
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        &unique_signers(&[payer, authority]),
+        recent_blockhash,
+    );
+    events::send_and_confirm(client, operation, &transaction)
+}
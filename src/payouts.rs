@@ -0,0 +1,504 @@
+//! Batch confidential payouts from one treasury account
+//!
+//! Paying many recipients from the same account is a chain: every transfer
+//! spends from the available balance left by the previous one. Instead of
+//! refetching the account (and waiting for confirmation) before each proof,
+//! the new available balance ciphertext is taken from the previous
+//! transfer's equality proof, which is exactly what the program computes
+//! on-chain. That lets proofs be generated ahead on a blocking thread while
+//! earlier transfers are still being submitted.
+
+use crate::events::{self, Operation, OperationEvent};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::mint_burn::auditor_elgamal_pubkey;
+use crate::proofs::transfer_proofs;
+use crate::proof_accounts::{close_context_state_accounts, create_proof_account};
+use crate::proof_placement::{
+    proof_location, select_placements, send_versioned, transaction_size, with_record_fallback, TransferPlacements,
+};
+use crate::receipt::{fetch_receipt, Receipt, SentTransactions, TransactionRole};
+use crate::recipient::{check_recipient_account, InvalidRecipient};
+use crate::types::*;
+use crate::verification::verify_transfer_proofs;
+use crate::watch::MAX_MULTIPLE_ACCOUNTS;
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::{
+            account_info::TransferAccountInfo,
+            instruction::transfer,
+            ConfidentialTransferAccount,
+        },
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::{ElGamalKeypair, ElGamalPubkey},
+        },
+        zk_elgamal_proof_program::proof_data::ZkProofData,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_confidential_transfer_proof_generation::transfer::TransferProofData;
+use std::fmt;
+use tokio::sync::mpsc;
+
+/// How many payouts proof generation may run ahead of submission
+const PROOF_PIPELINE_DEPTH: usize = 4;
+
+/// One recipient of a batch payout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Payout {
+    /// Recipient wallet (owner of the associated token account)
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+/// What happened to a payout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutStatus {
+    /// Transfer confirmed
+    Sent,
+    /// Transfer attempted and failed
+    Failed,
+    /// Not attempted because an earlier payout failed
+    Skipped,
+}
+
+/// Result of a single payout
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct PayoutResult {
+    #[serde_as(as = "DisplayFromStr")]
+    pub recipient: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub token_account: Pubkey,
+    pub amount: u64,
+    pub status: PayoutStatus,
+    /// Proof account, transfer and cleanup signatures, including those of a
+    /// failed attempt
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub signatures: Vec<Signature>,
+    /// Receipt of a sent payout, with the sender's balances after it
+    pub receipt: Option<Receipt>,
+    pub error: Option<String>,
+}
+
+/// Batch payout rejected because some recipients can't receive it
+///
+/// Returned (boxed) by the batch payout functions before anything is sent;
+/// downcast the error to read each recipient wallet's typed reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPayoutRecipients {
+    pub recipients: Vec<(Pubkey, InvalidRecipient)>,
+}
+
+impl fmt::Display for InvalidPayoutRecipients {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problems: Vec<String> = self.recipients.iter()
+            .map(|(recipient, invalid)| format!("{}: {}", recipient, invalid.reason))
+            .collect();
+        write!(f, "Recipients can't receive confidential transfers: {}", problems.join("; "))
+    }
+}
+
+impl std::error::Error for InvalidPayoutRecipients {}
+
+/// Per-recipient results of a batch payout
+#[derive(Debug, Clone, Serialize)]
+pub struct PayoutReport {
+    pub results: Vec<PayoutResult>,
+    /// Total amount of the confirmed payouts
+    pub total_sent: u64,
+}
+
+impl PayoutReport {
+    /// Whether every payout was sent
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(|result| result.status == PayoutStatus::Sent)
+    }
+}
+
+/// Pay many recipients confidentially from the sender's available balance
+///
+/// Steps:
+/// 1. Validate every recipient account (see `InvalidPayoutRecipients`) and
+///    that the available balance covers the total
+/// 2. Generate proofs on a blocking thread, chaining the available balance locally
+/// 3. Submit the transfers one by one
+///
+/// Nothing is sent if validation fails. A failed transfer breaks the chain,
+/// so the payouts after it are reported as skipped; the report is returned
+/// either way.
+pub async fn batch_transfer_confidential(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    sender: &dyn Signer,
+    mint: &Pubkey,
+    payouts: &[Payout],
+) -> CtResult<PayoutReport> {
+    batch_transfer_confidential_with_keys(client, payer, sender, mint, payouts, &SignerKeyProvider::new(sender)).await
+}
+
+/// Batch payout with the sender's keys taken from a key provider
+///
+/// Same as `batch_transfer_confidential`, for senders whose keys aren't
+/// derived from their keypair.
+#[tracing::instrument(skip_all, fields(sender = %sender.pubkey(), %mint, payouts = payouts.len()))]
+pub async fn batch_transfer_confidential_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    sender: &dyn Signer,
+    mint: &Pubkey,
+    payouts: &[Payout],
+    keys: &dyn KeyProvider,
+) -> CtResult<PayoutReport> {
+    if payouts.is_empty() {
+        return Err("No payouts given".into());
+    }
+    if let Some(payout) = payouts.iter().find(|payout| payout.amount == 0) {
+        return Err(format!("Payout to {} has a zero amount", payout.recipient).into());
+    }
+    let total = payouts.iter()
+        .try_fold(0u64, |total, payout| total.checked_add(payout.amount))
+        .ok_or("Payout total overflows u64")?;
+
+    let sender_token_account = get_associated_token_address_with_program_id(
+        &sender.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+    let sender_elgamal = keys.elgamal_keypair(&sender_token_account)?;
+    let sender_aes = keys.aes_key(&sender_token_account)?;
+
+    let mint_account_data = client.get_account(mint)?;
+    let mint_account = StateWithExtensions::<Mint>::unpack(&mint_account_data.data)?;
+    let auditor_elgamal_pubkey = auditor_elgamal_pubkey(&mint_account)?;
+
+    let recipients = recipient_elgamal_pubkeys(client, mint, payouts)?;
+
+    // The whole batch must be covered before anything is sent
+    let sender_account_data = client.get_account(&sender_token_account)?;
+    let sender_account = StateWithExtensions::<TokenAccount>::unpack(&sender_account_data.data)?;
+    let account_info = TransferAccountInfo::new(sender_account.get_extension::<ConfidentialTransferAccount>()?);
+    let decryptable_balance: AeCiphertext = account_info.decryptable_available_balance.try_into()
+        .map_err(|_| "Failed to convert decryptable_available_balance")?;
+    let available = sender_aes.decrypt(&decryptable_balance)
+        .ok_or("Failed to decrypt available balance")?;
+    if available < total {
+        return Err(format!("Insufficient balance: have {}, need {}", available, total).into());
+    }
+
+    // Proof generation runs ahead of submission on a blocking thread
+    let (proof_sender, mut proof_receiver) = mpsc::channel(PROOF_PIPELINE_DEPTH);
+    let generator = {
+        let amounts: Vec<(u64, ElGamalPubkey)> = payouts.iter()
+            .zip(&recipients)
+            .map(|(payout, (_, elgamal_pubkey))| (payout.amount, *elgamal_pubkey))
+            .collect();
+        let sender_elgamal = sender_elgamal.clone();
+        let sender_aes = sender_aes.clone();
+        tokio::task::spawn_blocking(move || {
+            generate_chained_proofs(
+                account_info, available, &amounts, &sender_elgamal, &sender_aes, auditor_elgamal_pubkey, proof_sender,
+            )
+        })
+    };
+
+    let submitter = PayoutSubmitter {
+        client,
+        mint: *mint,
+        payer,
+        sender,
+        sender_token_account,
+        sender_aes,
+    };
+
+    let mut results = Vec::with_capacity(payouts.len());
+    let mut total_sent = 0;
+    let mut failed = false;
+    for (payout, (token_account, _)) in payouts.iter().zip(&recipients) {
+        let mut result = PayoutResult {
+            recipient: payout.recipient,
+            token_account: *token_account,
+            amount: payout.amount,
+            status: PayoutStatus::Skipped,
+            signatures: Vec::new(),
+            receipt: None,
+            error: None,
+        };
+
+        if !failed {
+            let mut sent = SentTransactions::default();
+            let transferred = match proof_receiver.recv().await {
+                Some(Ok((account_info, proofs))) => {
+                    submitter.send(token_account, payout.amount, account_info, proofs, &mut sent).await
+                }
+                Some(Err(e)) => Err(e.into()),
+                None => Err("Proof generation stopped".into()),
+            };
+            result.signatures = sent.transactions.iter().map(|(_, signature)| *signature).collect();
+            match transferred {
+                Ok(()) => {
+                    result.status = PayoutStatus::Sent;
+                    result.receipt = Some(fetch_receipt(client, Operation::BatchTransfer, Some(&sender_token_account), &sent, Some(keys)));
+                    total_sent += payout.amount;
+                    events::progress(
+                        Operation::BatchTransfer,
+                        format!("Paid {}/{}: {} tokens to {}", results.len() + 1, payouts.len(), payout.amount, payout.recipient),
+                    );
+                }
+                Err(e) => {
+                    result.status = PayoutStatus::Failed;
+                    result.error = Some(e.to_string());
+                    failed = true;
+                }
+            }
+        }
+        results.push(result);
+    }
+
+    // Stop generating proofs nobody will submit
+    drop(proof_receiver);
+    let _ = generator.await;
+
+    let report = PayoutReport { results, total_sent };
+    events::completed(
+        Operation::BatchTransfer,
+        format!(
+            "Batch payout sent {} of {} transfers ({} tokens)",
+            report.results.iter().filter(|result| result.status == PayoutStatus::Sent).count(),
+            payouts.len(),
+            total_sent
+        ),
+    );
+
+    Ok(report)
+}
+
+/// Recipient token accounts and ElGamal pubkeys, in payout order
+///
+/// Fails with `InvalidPayoutRecipients` listing every recipient that can't
+/// receive confidential transfers. Payouts carry no memo, so recipients
+/// requiring one are rejected with `RecipientReason::MemoRequired`.
+fn recipient_elgamal_pubkeys(
+    client: &RpcClient,
+    mint: &Pubkey,
+    payouts: &[Payout],
+) -> CtResult<Vec<(Pubkey, ElGamalPubkey)>> {
+    let token_accounts: Vec<Pubkey> = payouts.iter()
+        .map(|payout| get_associated_token_address_with_program_id(&payout.recipient, mint, &spl_token_2022::id()))
+        .collect();
+
+    let mut recipients = Vec::with_capacity(payouts.len());
+    let mut invalid_recipients = Vec::new();
+    for (chunk_index, chunk) in token_accounts.chunks(MAX_MULTIPLE_ACCOUNTS).enumerate() {
+        let fetched = client.get_multiple_accounts(chunk)?;
+        for (offset, (token_account, account_data)) in chunk.iter().zip(fetched).enumerate() {
+            let recipient = payouts[chunk_index * MAX_MULTIPLE_ACCOUNTS + offset].recipient;
+            let checked = check_recipient_account(mint, token_account, account_data.as_ref())
+                .and_then(|valid| valid.check_memo(None).map(|()| valid));
+            match checked {
+                Ok(valid) => recipients.push((*token_account, valid.elgamal_pubkey)),
+                Err(invalid) => invalid_recipients.push((recipient, invalid)),
+            }
+        }
+    }
+
+    if !invalid_recipients.is_empty() {
+        return Err(InvalidPayoutRecipients { recipients: invalid_recipients }.into());
+    }
+    Ok(recipients)
+}
+
+/// Generate the proofs of every payout in order
+///
+/// Each payout gets the account state it will be applied to: the available
+/// balance ciphertext from the previous equality proof and a decryptable
+/// balance of what's left. Stops at the first failure or when the
+/// receiving side hangs up.
+fn generate_chained_proofs(
+    mut account_info: TransferAccountInfo,
+    mut available: u64,
+    amounts: &[(u64, ElGamalPubkey)],
+    sender_elgamal: &ElGamalKeypair,
+    sender_aes: &AeKey,
+    auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+    proof_sender: mpsc::Sender<Result<(TransferAccountInfo, TransferProofData), String>>,
+) {
+    for (amount, recipient_elgamal_pubkey) in amounts {
+        let (proofs, timings) = match transfer_proofs(
+            &account_info,
+            *amount,
+            sender_elgamal,
+            sender_aes,
+            recipient_elgamal_pubkey,
+            auditor_elgamal_pubkey.as_ref(),
+        ).and_then(|(proofs, timings)| {
+            // Don't pay for proof accounts the program would reject
+            verify_transfer_proofs(Operation::BatchTransfer, &proofs)?;
+            Ok((proofs, timings))
+        }) {
+            Ok(generated) => generated,
+            Err(e) => {
+                let _ = proof_sender.blocking_send(Err(e.to_string()));
+                return;
+            }
+        };
+        events::emit(OperationEvent::ProofGenerated {
+            operation: Operation::BatchTransfer,
+            amount: *amount,
+            timings: Some(timings),
+        });
+
+        let current = account_info;
+        available -= amount;
+        account_info.available_balance = proofs.equality_proof_data.context_data().ciphertext;
+        account_info.decryptable_available_balance = sender_aes.encrypt(available).into();
+
+        if proof_sender.blocking_send(Ok((current, proofs))).is_err() {
+            return;
+        }
+    }
+}
+
+/// Submits prepared payouts of one sender
+struct PayoutSubmitter<'a> {
+    client: &'a RpcClient,
+    mint: Pubkey,
+    payer: &'a dyn Signer,
+    sender: &'a dyn Signer,
+    sender_token_account: Pubkey,
+    sender_aes: AeKey,
+}
+
+impl PayoutSubmitter<'_> {
+    /// Create proof accounts, transfer, then close the proof accounts
+    ///
+    /// Proofs are placed like `transfer::transfer_with_options` does by
+    /// default: as many inline as fit, the others in context state accounts,
+    /// through a record when their verification doesn't fit alone. The payer
+    /// pays the fees and the proof accounts' rent, and gets the rent back.
+    /// Proof accounts are closed even if the transfer fails; a failed close
+    /// is logged and never replaces the transfer's outcome. Every
+    /// transaction sent is recorded in `sent`, whatever the outcome.
+    async fn send(
+        &self,
+        recipient_token_account: &Pubkey,
+        amount: u64,
+        account_info: TransferAccountInfo,
+        proofs: TransferProofData,
+        sent: &mut SentTransactions,
+    ) -> CtResult<()> {
+        let TransferProofData {
+            equality_proof_data,
+            ciphertext_validity_proof_data_with_ciphertext,
+            range_proof_data,
+        } = proofs;
+
+        // The chained account info stands in for the not yet updated on-chain state
+        let new_decryptable_balance = account_info.new_decryptable_available_balance(amount, &self.sender_aes)?.into();
+        let equality_proof_account = Keypair::new();
+        let ciphertext_validity_proof_account = Keypair::new();
+        let range_proof_account = Keypair::new();
+        let transfer_instructions = |placements: TransferPlacements| -> CtResult<Vec<Instruction>> {
+            let mut next_offset = 1;
+            Ok(transfer(
+                &spl_token_2022::id(),
+                &self.sender_token_account,
+                &self.mint,
+                recipient_token_account,
+                &new_decryptable_balance,
+                &ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+                &ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+                &self.sender.pubkey(),
+                &[],
+                proof_location(placements.equality, &mut next_offset, &equality_proof_account.pubkey(), &equality_proof_data)?,
+                proof_location(
+                    placements.ciphertext_validity,
+                    &mut next_offset,
+                    &ciphertext_validity_proof_account.pubkey(),
+                    &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+                )?,
+                proof_location(placements.range, &mut next_offset, &range_proof_account.pubkey(), &range_proof_data)?,
+            )?)
+        };
+
+        let payer = self.payer.pubkey();
+        let placements: TransferPlacements = select_placements(|placements| {
+            transaction_size(&payer, &transfer_instructions(placements)?, &[])
+        })?;
+        let placements = TransferPlacements {
+            equality: with_record_fallback(placements.equality, &payer, &payer, &equality_proof_data)?,
+            ciphertext_validity: with_record_fallback(
+                placements.ciphertext_validity,
+                &payer,
+                &payer,
+                &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            )?,
+            range: with_record_fallback(placements.range, &payer, &payer, &range_proof_data)?,
+        };
+
+        let mut context_accounts = Vec::new();
+        let transferred = (|| -> SigResult {
+            create_proof_account(
+                self.client,
+                Operation::BatchTransfer,
+                self.payer,
+                placements.equality,
+                (TransactionRole::EqualityProofCreate, TransactionRole::EqualityProofClose),
+                &equality_proof_account,
+                &equality_proof_data,
+                sent,
+                &mut context_accounts,
+            )?;
+            create_proof_account(
+                self.client,
+                Operation::BatchTransfer,
+                self.payer,
+                placements.ciphertext_validity,
+                (TransactionRole::CiphertextValidityProofCreate, TransactionRole::CiphertextValidityProofClose),
+                &ciphertext_validity_proof_account,
+                &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+                sent,
+                &mut context_accounts,
+            )?;
+            create_proof_account(
+                self.client,
+                Operation::BatchTransfer,
+                self.payer,
+                placements.range,
+                (TransactionRole::RangeProofCreate, TransactionRole::RangeProofClose),
+                &range_proof_account,
+                &range_proof_data,
+                sent,
+                &mut context_accounts,
+            )?;
+            send_versioned(
+                self.client,
+                Operation::BatchTransfer,
+                self.payer,
+                &[self.sender],
+                &transfer_instructions(placements)?,
+                &[],
+            )
+        })();
+        if let Ok(signature) = transferred {
+            sent.push(TransactionRole::Transfer, signature);
+        }
+
+        close_context_state_accounts(self.client, Operation::BatchTransfer, self.payer, &context_accounts, sent);
+        transferred?;
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! Watch a token account and auto-apply incoming confidential funds
+//!
+//! Incoming transfers and deposits land in the pending balance and only
+//! become spendable once the owner applies them. Every credit bumps the
+//! account's `pending_balance_credit_counter`; once it reaches the
+//! maximum, further credits are rejected until the owner applies.
+//!
+//! `PendingBalanceWatcher` follows the account with a websocket
+//! `accountSubscribe` (falling back to polling when the websocket is
+//! unavailable or drops, and subscribing again every
+//! `RESUBSCRIBE_INTERVAL`), reports new credits as
+//! `OperationEvent::PendingBalanceCredited` and applies the pending
+//! balance whenever the `ApplyPolicy` says so.
+
+use crate::apply_pending::apply_pending_balance_with_keys;
+use crate::events::{self, Operation, OperationEvent};
+use crate::key_provider::KeyProvider;
+use crate::types::*;
+use crate::watch::WatchOnlyAccount;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_cli_config::Config;
+use solana_client::{
+    pubsub_client::{AccountSubscription, PubsubClient},
+    rpc_client::RpcClient,
+    rpc_config::RpcAccountInfoConfig,
+};
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Signer};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    state::Account as TokenAccount,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Apply once the credit counter is this close to its maximum
+pub const DEFAULT_COUNTER_HEADROOM: u64 = 2;
+
+/// How often the account is polled without a websocket
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a polling watcher tries to subscribe to the websocket again
+pub const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When to apply the pending balance
+///
+/// Nothing is applied while the credit counter is zero. Otherwise the
+/// pending balance is applied as soon as any configured condition holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyPolicy {
+    /// Apply once the pending balance reaches this many tokens
+    pub min_pending_amount: Option<u64>,
+    /// Apply once the credit counter is within this many credits of its maximum
+    pub counter_headroom: Option<u64>,
+    /// Apply whatever is pending at least this often
+    pub interval: Option<Duration>,
+}
+
+impl Default for ApplyPolicy {
+    /// Apply only when the counter is about to block further credits
+    fn default() -> Self {
+        Self {
+            min_pending_amount: None,
+            counter_headroom: Some(DEFAULT_COUNTER_HEADROOM),
+            interval: None,
+        }
+    }
+}
+
+impl ApplyPolicy {
+    /// Apply every credit as soon as it arrives
+    pub fn immediate() -> Self {
+        Self { min_pending_amount: Some(0), ..Self::default() }
+    }
+
+    /// Whether the pending balance should be applied now
+    pub fn should_apply(
+        &self,
+        pending: u64,
+        credit_counter: u64,
+        maximum_credit_counter: u64,
+        since_last_apply: Duration,
+    ) -> bool {
+        if credit_counter == 0 {
+            return false;
+        }
+        self.min_pending_amount.is_some_and(|min| pending >= min)
+            || self.counter_headroom.is_some_and(|headroom| {
+                maximum_credit_counter.saturating_sub(credit_counter) <= headroom
+            })
+            || self.interval.is_some_and(|interval| since_last_apply >= interval)
+    }
+}
+
+/// Long-running watcher applying the authority's pending balance
+pub struct PendingBalanceWatcher<'a> {
+    client: &'a RpcClient,
+    payer: &'a dyn Signer,
+    authority: &'a dyn Signer,
+    mint: Pubkey,
+    token_account: Pubkey,
+    keys: &'a dyn KeyProvider,
+    policy: ApplyPolicy,
+    websocket_url: Option<String>,
+    poll_interval: Duration,
+}
+
+impl<'a> PendingBalanceWatcher<'a> {
+    /// Watch the authority's associated token account for `mint`
+    ///
+    /// The websocket URL is derived from the RPC URL the way the Solana CLI
+    /// does (`http` → `ws`, port 8899 → 8900).
+    pub fn new(
+        client: &'a RpcClient,
+        payer: &'a dyn Signer,
+        authority: &'a dyn Signer,
+        mint: &Pubkey,
+        keys: &'a dyn KeyProvider,
+        policy: ApplyPolicy,
+    ) -> Self {
+        let token_account = get_associated_token_address_with_program_id(
+            &authority.pubkey(),
+            mint,
+            &spl_token_2022::id(),
+        );
+        let websocket_url = Some(Config::compute_websocket_url(&client.url()))
+            .filter(|url| !url.is_empty());
+
+        Self {
+            client,
+            payer,
+            authority,
+            mint: *mint,
+            token_account,
+            keys,
+            policy,
+            websocket_url,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Subscribe through this websocket URL instead of the derived one
+    pub fn with_websocket_url(mut self, websocket_url: impl Into<String>) -> Self {
+        self.websocket_url = Some(websocket_url.into());
+        self
+    }
+
+    /// Never use a websocket, only poll
+    pub fn polling_only(mut self) -> Self {
+        self.websocket_url = None;
+        self
+    }
+
+    /// Poll interval, also the longest wait between websocket notifications
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Token account being watched
+    pub fn token_account(&self) -> &Pubkey {
+        &self.token_account
+    }
+
+    /// Watch until `stop` is set, returning the apply signatures
+    ///
+    /// Steps (on every account update, or every poll interval):
+    /// 1. Decrypt the pending balance and report new credits
+    /// 2. Ask the policy whether to apply
+    /// 3. Apply the pending balance
+    ///
+    /// RPC and apply failures are reported through `tracing` and retried
+    /// on the next update; `stop` is checked at least every poll interval.
+    #[tracing::instrument(skip_all, fields(token_account = %self.token_account))]
+    pub async fn run(&self, stop: &AtomicBool) -> MultiSigResult {
+        let watched = WatchOnlyAccount::from_provider(self.token_account, self.keys)?;
+        let mut subscription = self.subscribe().await;
+        let mut last_subscribe = Instant::now();
+        let mut last_counter = 0;
+        let mut last_apply = Instant::now();
+        let mut signatures = Vec::new();
+
+        events::progress(
+            Operation::WatchPendingBalance,
+            format!(
+                "Watching {} ({})",
+                self.token_account,
+                if subscription.is_some() { "websocket" } else { "polling" }
+            ),
+        );
+
+        // Start from the current state rather than waiting for the first update
+        let mut next = self.client.get_account(&self.token_account).map_err(Into::into);
+
+        while !stop.load(Ordering::SeqCst) {
+            match next.and_then(|account| self.credit_state(&watched, &account)) {
+                Ok((pending, counter, maximum_counter)) => {
+                    if counter > last_counter {
+                        events::emit(OperationEvent::PendingBalanceCredited {
+                            operation: Operation::WatchPendingBalance,
+                            token_account: self.token_account,
+                            pending,
+                            pending_balance_credit_counter: counter,
+                        });
+                    }
+                    last_counter = counter;
+
+                    if self.policy.should_apply(pending, counter, maximum_counter, last_apply.elapsed()) {
+                        match apply_pending_balance_with_keys(
+                            self.client, self.payer, self.authority, &self.mint, self.keys,
+                        ).await {
+                            Ok(receipt) => {
+                                signatures.extend(receipt.signatures());
+                                last_counter = 0;
+                                last_apply = Instant::now();
+                            }
+                            Err(e) => tracing::warn!("Failed to apply pending balance: {}", e),
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to read {}: {}", self.token_account, e),
+            }
+
+            next = self.next_account(&mut subscription, &mut last_subscribe).await;
+        }
+
+        events::completed(
+            Operation::WatchPendingBalance,
+            format!("Stopped watching {} after {} applies", self.token_account, signatures.len()),
+        );
+
+        Ok(signatures)
+    }
+
+    /// Subscribe to account updates, or `None` to poll
+    ///
+    /// Connecting blocks, so it runs off the async runtime.
+    async fn subscribe(&self) -> Option<AccountSubscription> {
+        let websocket_url = self.websocket_url.clone()?;
+        let token_account = self.token_account;
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(self.client.commitment()),
+            ..Default::default()
+        };
+        let subscribed = tokio::task::spawn_blocking(move || {
+            PubsubClient::account_subscribe(websocket_url.as_str(), &token_account, Some(config))
+                .map_err(|e| format!("Websocket {} unavailable, polling instead: {}", websocket_url, e))
+        })
+        .await;
+        match subscribed {
+            Ok(Ok(subscription)) => Some(subscription),
+            Ok(Err(e)) => {
+                tracing::warn!("{}", e);
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Websocket subscription failed, polling instead: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Wait for the next account update
+    ///
+    /// Without a notification within the poll interval the account is
+    /// fetched anyway, so interval policies fire and missed notifications
+    /// are caught up. The wait on the websocket runs off the async runtime.
+    /// While polling, the websocket is subscribed to again every
+    /// `RESUBSCRIBE_INTERVAL`.
+    async fn next_account(
+        &self,
+        subscription: &mut Option<AccountSubscription>,
+        last_subscribe: &mut Instant,
+    ) -> CtResult<Account> {
+        if let Some((_, receiver)) = subscription {
+            let receiver = receiver.clone();
+            let poll_interval = self.poll_interval;
+            match tokio::task::spawn_blocking(move || receiver.recv_timeout(poll_interval)).await? {
+                Ok(response) => {
+                    return response.value.decode::<Account>()
+                        .ok_or_else(|| "Failed to decode account notification".into());
+                }
+                Err(e) if e.is_disconnected() => {
+                    events::progress(
+                        Operation::WatchPendingBalance,
+                        format!("Websocket closed, polling {}", self.token_account),
+                    );
+                    // Unsubscribing writes to the socket
+                    if let Some(closed) = subscription.take() {
+                        tokio::task::spawn_blocking(move || drop(closed));
+                    }
+                    *last_subscribe = Instant::now();
+                }
+                Err(_) => {}
+            }
+        } else {
+            tokio::time::sleep(self.poll_interval).await;
+            if self.websocket_url.is_some() && last_subscribe.elapsed() >= RESUBSCRIBE_INTERVAL {
+                *subscription = self.subscribe().await;
+                *last_subscribe = Instant::now();
+                if subscription.is_some() {
+                    events::progress(
+                        Operation::WatchPendingBalance,
+                        format!("Websocket reconnected for {}", self.token_account),
+                    );
+                }
+            }
+        }
+
+        Ok(self.client.get_account(&self.token_account)?)
+    }
+
+    /// Pending balance, credit counter and maximum credit counter
+    fn credit_state(&self, watched: &WatchOnlyAccount, account: &Account) -> CtResult<(u64, u64, u64)> {
+        let balances = watched.decrypt(account)?;
+        let state = StateWithExtensions::<TokenAccount>::unpack(&account.data)?;
+        let ct_extension = state.get_extension::<ConfidentialTransferAccount>()?;
+
+        Ok((
+            balances.pending,
+            balances.pending_balance_credit_counter,
+            ct_extension.maximum_pending_balance_credit_counter.into(),
+        ))
+    }
+}
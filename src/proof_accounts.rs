@@ -0,0 +1,388 @@
+//! Proof context state and record accounts
+//!
+//! Proofs that aren't verified inline are verified beforehand into a
+//! context state account, which the Token-2022 instruction references.
+//! `create_context_state_account` verifies the proof data sent in the
+//! instruction itself, splitting account creation into its own
+//! transaction when both don't fit in one.
+//!
+//! A proof too large for one transaction, even alone, is written into an
+//! spl-record account in chunks, one transaction each, then verified by
+//! the ZK ElGamal proof program straight from that account into a context
+//! state account (`create_context_state_from_record`). The record is
+//! closed right after verification; the context state is referenced and
+//! closed like any other.
+//!
+//! Everything here is built from the record, system and proof program
+//! instructions directly, without `spl_token_client::Token`.
+
+use crate::authority::unique_signers;
+use crate::events::{self, Operation, OperationEvent};
+use crate::proof_placement::ProofPlacement;
+use crate::receipt::{SentTransactions, TransactionRole};
+use crate::types::*;
+use bytemuck::{bytes_of, Pod};
+use solana_client::rpc_client::RpcClient;
+use solana_packet::PACKET_DATA_SIZE;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use solana_system_interface::instruction as system_instruction;
+use spl_record::state::RecordData;
+use spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::{
+    self,
+    instruction::{close_context_state, ContextStateInfo},
+    proof_data::ZkProofData,
+    state::ProofContextState,
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::zk_proof_type_to_instruction;
+use std::mem::size_of;
+
+/// Offset of the proof data in a record account (after the record header)
+pub const RECORD_PROOF_OFFSET: u32 = RecordData::WRITABLE_START_INDEX as u32;
+
+/// Create a context state account holding the verified `proof_data`
+///
+/// Creating the account and verifying the proof go in one transaction when
+/// they fit, two otherwise. `payer` pays the rent; `context_authority` may
+/// close the account. Fails if even the verification alone doesn't fit
+/// (see `context_state_fits`; use `create_context_state_from_record`).
+pub fn create_context_state_account<T: Pod + ZkProofData<U>, U: Pod>(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    context_account: &Keypair,
+    context_authority: &Pubkey,
+    proof_data: &T,
+) -> MultiSigResult {
+    let space = size_of::<ProofContextState<U>>();
+    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+    let create_account = system_instruction::create_account(
+        &payer.pubkey(),
+        &context_account.pubkey(),
+        rent,
+        space as u64,
+        &zk_elgamal_proof_program::id(),
+    );
+    let verify_proof = verify_proof_instruction(&context_account.pubkey(), context_authority, proof_data)?;
+
+    let mut signatures = Vec::new();
+    let together = [create_account.clone(), verify_proof.clone()];
+    if legacy_transaction_size(&payer.pubkey(), &together)? <= PACKET_DATA_SIZE {
+        signatures.push(send(client, operation, payer, &[payer, context_account], &together)?);
+    } else {
+        if legacy_transaction_size(&payer.pubkey(), std::slice::from_ref(&verify_proof))? > PACKET_DATA_SIZE {
+            return Err("Proof verification exceeds the transaction size limit; verify it from a record account".into());
+        }
+        signatures.push(send(client, operation, payer, &[payer, context_account], &[create_account])?);
+        signatures.push(send(client, operation, payer, &[payer], &[verify_proof])?);
+    }
+    events::emit(OperationEvent::ContextAccountCreated {
+        operation,
+        account: context_account.pubkey(),
+        signature: *signatures.last().unwrap(),
+    });
+    Ok(signatures)
+}
+
+/// Whether verifying `proof_data` into a context state fits in one transaction
+///
+/// Measures the verification alone, as sent by `create_context_state_account`
+/// (or the Token client's split creation) with `payer` paying the fee.
+pub fn context_state_fits<T: Pod + ZkProofData<U>, U: Pod>(
+    payer: &Pubkey,
+    context_authority: &Pubkey,
+    proof_data: &T,
+) -> CtResult<bool> {
+    let verify_proof = verify_proof_instruction(&Pubkey::new_unique(), context_authority, proof_data)?;
+    Ok(legacy_transaction_size(payer, &[verify_proof])? <= PACKET_DATA_SIZE)
+}
+
+/// Create a record account holding `proof_data`, writing it in chunks
+///
+/// Steps:
+/// 1. Create and initialize the record and write the first chunk
+/// 2. Write each remaining chunk in its own transaction
+///
+/// Chunks are as large as the packet size allows. `authority` owns the
+/// record and must sign every write; `payer` pays the rent.
+pub fn create_proof_record<T: Pod>(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    record: &Keypair,
+    proof_data: &T,
+) -> MultiSigResult {
+    let proof_bytes = bytes_of(proof_data);
+    let space = RecordData::WRITABLE_START_INDEX + proof_bytes.len();
+    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+
+    let record_instructions = |first: bool, offset: usize, chunk: &[u8]| {
+        let mut instructions = Vec::new();
+        if first {
+            instructions.push(system_instruction::create_account(
+                &payer.pubkey(),
+                &record.pubkey(),
+                rent,
+                space as u64,
+                &spl_record::id(),
+            ));
+            instructions.push(spl_record::instruction::initialize(&record.pubkey(), &authority.pubkey()));
+        }
+        instructions.push(spl_record::instruction::write(&record.pubkey(), &authority.pubkey(), offset as u64, chunk));
+        instructions
+    };
+
+    let mut signatures = Vec::new();
+    let mut offset = 0;
+    while offset < proof_bytes.len() {
+        let first = offset == 0;
+        let chunk_size = max_chunk_size(&payer.pubkey(), &record_instructions(first, offset, &[]))?;
+        let chunk = &proof_bytes[offset..proof_bytes.len().min(offset + chunk_size)];
+
+        let signers: Vec<&dyn Signer> = if first { vec![payer, authority, record] } else { vec![payer, authority] };
+        let signature = send(client, operation, payer, &signers, &record_instructions(first, offset, chunk))?;
+        if first {
+            events::emit(OperationEvent::RecordAccountCreated { operation, account: record.pubkey(), signature });
+        }
+        signatures.push(signature);
+        offset += chunk.len();
+    }
+
+    Ok(signatures)
+}
+
+/// Verify the proof in a record account into a new context state account
+///
+/// The context state account is created in the same transaction, owned by
+/// the proof program, with `context_authority` allowed to close it.
+pub fn verify_proof_from_record<T: Pod + ZkProofData<U>, U: Pod>(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    record: &Pubkey,
+    context_account: &Keypair,
+    context_authority: &Pubkey,
+) -> SigResult {
+    let space = size_of::<ProofContextState<U>>();
+    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+    let context_state_info = ContextStateInfo {
+        context_state_account: &context_account.pubkey(),
+        context_state_authority: context_authority,
+    };
+
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &context_account.pubkey(),
+            rent,
+            space as u64,
+            &zk_elgamal_proof_program::id(),
+        ),
+        zk_proof_type_to_instruction(T::PROOF_TYPE)?
+            .encode_verify_proof_from_account(Some(context_state_info), record, RECORD_PROOF_OFFSET),
+    ];
+    let signature = send(client, operation, payer, &[payer, context_account], &instructions)?;
+    events::emit(OperationEvent::ContextAccountCreated { operation, account: context_account.pubkey(), signature });
+    Ok(signature)
+}
+
+/// Close a record account, returning its rent to `destination`
+pub fn close_proof_record(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    record: &Pubkey,
+    destination: &Pubkey,
+) -> SigResult {
+    let instruction = spl_record::instruction::close_account(record, &authority.pubkey(), destination);
+    let signature = send(client, operation, payer, &[payer, authority], &[instruction])?;
+    events::emit(OperationEvent::RecordAccountClosed { operation, account: *record, signature });
+    Ok(signature)
+}
+
+/// Write a proof to a record, verify it into a context state, close the record
+///
+/// `record` is a fresh keypair for the temporary record account. Returns the
+/// signatures of every transaction, in order. If writing or verification
+/// fails, a record that was already created is closed before returning
+/// the error.
+pub fn create_context_state_from_record<T: Pod + ZkProofData<U>, U: Pod>(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    record: &Keypair,
+    context_account: &Keypair,
+    proof_data: &T,
+) -> MultiSigResult {
+    let mut signatures = Vec::new();
+    let verified = create_proof_record(client, operation, payer, authority, record, proof_data).and_then(|written| {
+        signatures.extend(written);
+        verify_proof_from_record::<T, U>(
+            client,
+            operation,
+            payer,
+            &record.pubkey(),
+            context_account,
+            &authority.pubkey(),
+        )
+    });
+    if let Err(e) = verified {
+        if client.get_account(&record.pubkey()).is_ok() {
+            if let Err(close_error) = close_proof_record(client, operation, payer, authority, &record.pubkey(), &payer.pubkey()) {
+                tracing::warn!("Failed to close proof record {}: {}", record.pubkey(), close_error);
+            }
+        }
+        return Err(e);
+    }
+    signatures.extend(verified);
+    signatures.push(close_proof_record(client, operation, payer, authority, &record.pubkey(), &payer.pubkey())?);
+    Ok(signatures)
+}
+
+/// Set up the context state account of a proof that isn't verified inline
+///
+/// Verifies `proof_data` into `context_account` for
+/// `ProofPlacement::ContextState`, through a temporary record for
+/// `ProofPlacement::Record`, and does nothing for `ProofPlacement::Inline`.
+/// `payer` pays the rent and may close the account. The transactions go to
+/// `sent` under the create role of `roles`, and the account to
+/// `context_accounts` with the close role once it exists, for
+/// `close_context_state_accounts`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn create_proof_account<T: Pod + ZkProofData<U>, U: Pod>(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    placement: ProofPlacement,
+    (create_role, close_role): (TransactionRole, TransactionRole),
+    context_account: &Keypair,
+    proof_data: &T,
+    sent: &mut SentTransactions,
+    context_accounts: &mut Vec<(TransactionRole, Pubkey)>,
+) -> CtResult<()> {
+    match placement {
+        ProofPlacement::Inline => return Ok(()),
+        ProofPlacement::ContextState => sent.extend(create_role, create_context_state_account(
+            client,
+            operation,
+            payer,
+            context_account,
+            &payer.pubkey(),
+            proof_data,
+        )?),
+        ProofPlacement::Record => {
+            let record = Keypair::new();
+            sent.rent_accounts.push(record.pubkey());
+            sent.extend(create_role, create_context_state_from_record(
+                client,
+                operation,
+                payer,
+                payer,
+                &record,
+                context_account,
+                proof_data,
+            )?);
+        }
+    }
+    context_accounts.push((close_role, context_account.pubkey()));
+    Ok(())
+}
+
+/// Close the context state accounts of an operation, returning rent to `payer`
+///
+/// Callers close on both the success and the failure path, so no account
+/// created before an error keeps its rent. Closing is best-effort: a
+/// failure is logged and never fails the operation, which may already
+/// have landed.
+pub(crate) fn close_context_state_accounts(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    context_accounts: &[(TransactionRole, Pubkey)],
+    sent: &mut SentTransactions,
+) {
+    sent.rent_accounts.extend(context_accounts.iter().map(|(_, context_account)| *context_account));
+    for (role, context_account) in context_accounts {
+        match close_context_state_account(client, operation, payer, payer, context_account, &payer.pubkey()) {
+            Ok(signature) => sent.push(*role, signature),
+            Err(e) => tracing::warn!("Failed to close proof account {}: {}", context_account, e),
+        }
+    }
+}
+
+/// Close a proof context state account, returning its rent to `destination`
+///
+/// The Token-free counterpart of `confidential_transfer_close_context_state_account`.
+pub fn close_context_state_account(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    context_authority: &dyn Signer,
+    context_account: &Pubkey,
+    destination: &Pubkey,
+) -> SigResult {
+    let context_state_info = ContextStateInfo {
+        context_state_account: context_account,
+        context_state_authority: &context_authority.pubkey(),
+    };
+    let instruction = close_context_state(context_state_info, destination);
+    let signature = send(client, operation, payer, &[payer, context_authority], &[instruction])?;
+    events::emit(OperationEvent::ContextAccountClosed { operation, account: *context_account, signature });
+    Ok(signature)
+}
+
+/// Proof program instruction verifying `proof_data` into a context state
+fn verify_proof_instruction<T: Pod + ZkProofData<U>, U: Pod>(
+    context_account: &Pubkey,
+    context_authority: &Pubkey,
+    proof_data: &T,
+) -> CtResult<Instruction> {
+    let context_state_info = ContextStateInfo {
+        context_state_account: context_account,
+        context_state_authority: context_authority,
+    };
+    Ok(zk_proof_type_to_instruction(T::PROOF_TYPE)?.encode_verify_proof(Some(context_state_info), proof_data))
+}
+
+/// Serialized size of a legacy transaction, signatures included
+fn legacy_transaction_size(payer: &Pubkey, instructions: &[Instruction]) -> CtResult<usize> {
+    let mut transaction = Transaction::new_with_payer(instructions, Some(payer));
+    transaction.signatures = vec![Signature::default(); transaction.message.header.num_required_signatures as usize];
+    Ok(bincode::serialized_size(&transaction)? as usize)
+}
+
+/// Bytes left for record data in a transaction made of `instructions`
+///
+/// `instructions` must end with an empty record write.
+fn max_chunk_size(payer: &Pubkey, instructions: &[Instruction]) -> CtResult<usize> {
+    let size = legacy_transaction_size(payer, instructions)?;
+    // The write's data length prefix grows by a byte past 127 bytes of data
+    PACKET_DATA_SIZE.checked_sub(size + 1)
+        .filter(|chunk_size| *chunk_size > 0)
+        .ok_or_else(|| "No room for proof data in a record write transaction".into())
+}
+
+/// Sign and send a legacy transaction, each key signing once
+fn send(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    signers: &[&dyn Signer],
+    instructions: &[Instruction],
+) -> SigResult {
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        &unique_signers(signers),
+        recent_blockhash,
+    );
+    events::send_and_confirm(client, operation, &transaction)
+}
@@ -0,0 +1,279 @@
+//! Where proofs are placed: inline, in context state or record accounts
+//!
+//! Token-2022 reads each proof either from a ZK ElGamal proof program
+//! instruction in the same transaction (`ProofLocation::InstructionOffset`)
+//! or from a context state account verified beforehand. Inline proofs are
+//! free; every context state account costs two extra transactions (create
+//! and close) and temporary rent. A proof whose verification doesn't fit
+//! in a transaction even alone is written to a record account first and
+//! verified from there (see `proof_accounts`), costing a few more.
+//!
+//! The three transfer proofs together (~2.4 KB) never fit in one packet,
+//! but one or two of them often do; likewise for the two withdraw proofs.
+//! `select_placements` picks the placement with the fewest proof accounts
+//! whose transaction fits in `PACKET_DATA_SIZE`, measured on the actual
+//! serialized v0 transaction (with the caller's address lookup table, if
+//! any). `with_record_fallback` then moves proofs too large for a context
+//! state transaction to records.
+
+use crate::authority::unique_signers;
+use crate::events::{self, Operation};
+use crate::proof_accounts::context_state_fits;
+use crate::types::*;
+use bytemuck::Pod;
+use solana_address_lookup_table_interface::state::AddressLookupTable;
+use solana_client::rpc_client::RpcClient;
+use solana_packet::PACKET_DATA_SIZE;
+use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, AddressLookupTableAccount, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+    transaction::VersionedTransaction,
+};
+use spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::proof_data::ZkProofData;
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+use std::fmt;
+use std::num::NonZeroI8;
+
+/// Where a single proof is placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofPlacement {
+    /// Verified by an instruction in the transfer transaction itself
+    Inline,
+    /// Verified beforehand into a context state account
+    ContextState,
+    /// Written to a record account, then verified from it into a context
+    /// state account
+    Record,
+}
+
+impl fmt::Display for ProofPlacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Inline => "inline",
+            Self::ContextState => "context state",
+            Self::Record => "record",
+        })
+    }
+}
+
+/// Use a record for a context state proof whose verification doesn't fit
+///
+/// `payer` pays for the context state transaction and `context_authority`
+/// may close the account; other placements are returned unchanged.
+pub fn with_record_fallback<T: Pod + ZkProofData<U>, U: Pod>(
+    placement: ProofPlacement,
+    payer: &Pubkey,
+    context_authority: &Pubkey,
+    proof_data: &T,
+) -> CtResult<ProofPlacement> {
+    if placement == ProofPlacement::ContextState && !context_state_fits(payer, context_authority, proof_data)? {
+        return Ok(ProofPlacement::Record);
+    }
+    Ok(placement)
+}
+
+/// Placements of all the proofs of one instruction
+pub trait Placements: Copy + fmt::Display {
+    /// Every combination of inline and context state placements
+    fn candidates() -> Vec<Self>;
+
+    /// Each proof's placement, in instruction argument order
+    fn placements(&self) -> Vec<ProofPlacement>;
+
+    /// Number of proof accounts to create and close
+    fn context_accounts(&self) -> usize {
+        self.placements()
+            .iter()
+            .filter(|placement| **placement != ProofPlacement::Inline)
+            .count()
+    }
+}
+
+/// Placement of each of the three transfer proofs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferPlacements {
+    pub equality: ProofPlacement,
+    pub ciphertext_validity: ProofPlacement,
+    pub range: ProofPlacement,
+}
+
+impl TransferPlacements {
+    /// Every proof in a context state account; always fits
+    pub const CONTEXT_STATE: Self = Self {
+        equality: ProofPlacement::ContextState,
+        ciphertext_validity: ProofPlacement::ContextState,
+        range: ProofPlacement::ContextState,
+    };
+}
+
+impl Placements for TransferPlacements {
+    fn candidates() -> Vec<Self> {
+        use ProofPlacement::*;
+        let mut candidates = Vec::new();
+        for equality in [Inline, ContextState] {
+            for ciphertext_validity in [Inline, ContextState] {
+                for range in [Inline, ContextState] {
+                    candidates.push(Self { equality, ciphertext_validity, range });
+                }
+            }
+        }
+        candidates
+    }
+
+    fn placements(&self) -> Vec<ProofPlacement> {
+        vec![self.equality, self.ciphertext_validity, self.range]
+    }
+}
+
+impl fmt::Display for TransferPlacements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "equality {}, ciphertext validity {}, range {}",
+            self.equality, self.ciphertext_validity, self.range
+        )
+    }
+}
+
+/// Placement of each of the two withdraw proofs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawPlacements {
+    pub equality: ProofPlacement,
+    pub range: ProofPlacement,
+}
+
+impl Placements for WithdrawPlacements {
+    fn candidates() -> Vec<Self> {
+        use ProofPlacement::*;
+        let mut candidates = Vec::new();
+        for equality in [Inline, ContextState] {
+            for range in [Inline, ContextState] {
+                candidates.push(Self { equality, range });
+            }
+        }
+        candidates
+    }
+
+    fn placements(&self) -> Vec<ProofPlacement> {
+        vec![self.equality, self.range]
+    }
+}
+
+impl fmt::Display for WithdrawPlacements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "equality {}, range {}", self.equality, self.range)
+    }
+}
+
+/// How a confidential transfer is sent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferOptions {
+    /// Proof placements; `None` selects them by transaction size
+    pub placements: Option<TransferPlacements>,
+    /// Address lookup table compressing the transfer transaction's accounts
+    pub lookup_table: Option<Pubkey>,
+    /// SPL Memo sent right before the transfer (required by some recipients)
+    pub memo: Option<String>,
+}
+
+/// How a withdrawal is sent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WithdrawOptions {
+    /// Proof placements; `None` selects them by transaction size
+    pub placements: Option<WithdrawPlacements>,
+    /// SPL Memo sent right before the withdrawal
+    pub memo: Option<String>,
+}
+
+/// Pick the placements with the fewest proof accounts that fit in a packet
+///
+/// `transaction_size` returns the serialized size of the instruction's
+/// transaction for a candidate. Among candidates with as many proof
+/// accounts, the smallest transaction wins.
+pub fn select_placements<P: Placements>(
+    mut transaction_size: impl FnMut(P) -> CtResult<usize>,
+) -> CtResult<P> {
+    let mut best: Option<(P, usize)> = None;
+    for candidate in P::candidates() {
+        let size = transaction_size(candidate)?;
+        if size > PACKET_DATA_SIZE {
+            continue;
+        }
+        let better = best.is_none_or(|(placements, best_size)| {
+            (candidate.context_accounts(), size) < (placements.context_accounts(), best_size)
+        });
+        if better {
+            best = Some((candidate, size));
+        }
+    }
+    best.map(|(placements, _)| placements)
+        .ok_or_else(|| format!("Transaction exceeds {} bytes with every proof placement", PACKET_DATA_SIZE).into())
+}
+
+/// Serialized size of a v0 transaction, signatures included
+pub fn transaction_size(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+) -> CtResult<usize> {
+    let message = v0::Message::try_compile(payer, instructions, lookup_tables, Hash::default())?;
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+        message: VersionedMessage::V0(message),
+    };
+    Ok(bincode::serialized_size(&transaction)? as usize)
+}
+
+/// Fetch an address lookup table for compiling v0 messages
+pub fn fetch_lookup_table(client: &RpcClient, address: &Pubkey) -> CtResult<AddressLookupTableAccount> {
+    let account = client.get_account(address)?;
+    let table = AddressLookupTable::deserialize(&account.data)
+        .map_err(|e| format!("{} is not an address lookup table: {}", address, e))?;
+    Ok(AddressLookupTableAccount {
+        key: *address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// Sign and send a v0 transaction, reporting both steps
+///
+/// `signers` may repeat the payer; each key signs once.
+pub(crate) fn send_versioned(
+    client: &RpcClient,
+    operation: Operation,
+    payer: &dyn Signer,
+    signers: &[&dyn Signer],
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+) -> SigResult {
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let message = v0::Message::try_compile(&payer.pubkey(), instructions, lookup_tables, recent_blockhash)?;
+    let signers: Vec<&dyn Signer> = std::iter::once(payer).chain(signers.iter().copied()).collect();
+    let transaction = VersionedTransaction::try_new(VersionedMessage::V0(message), &unique_signers(&signers))?;
+
+    events::send_and_confirm(client, operation, &transaction)
+}
+
+/// `ProofLocation` for a proof, taking the next instruction offset if inline
+///
+/// Token-2022 expects inline proofs right after its instruction, at
+/// offsets 1, 2, ... in argument order, so pass the same counter (starting
+/// at 1) for every proof of an instruction.
+pub fn proof_location<'a, T>(
+    placement: ProofPlacement,
+    next_offset: &mut i8,
+    context_account: &'a Pubkey,
+    proof_data: &'a T,
+) -> CtResult<ProofLocation<'a, T>> {
+    match placement {
+        ProofPlacement::Inline => {
+            let offset = NonZeroI8::new(*next_offset).ok_or("Invalid proof instruction offset")?;
+            *next_offset += 1;
+            Ok(ProofLocation::InstructionOffset(offset, proof_data))
+        }
+        ProofPlacement::ContextState | ProofPlacement::Record => Ok(ProofLocation::ContextStateAccount(context_account)),
+    }
+}
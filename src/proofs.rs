@@ -0,0 +1,267 @@
+//! Transfer proof generation off the async runtime
+//!
+//! Generating the equality, ciphertext validity and range proofs of a
+//! transfer is CPU-bound and takes long enough to stall a Tokio worker.
+//! `generate_transfer_proofs` runs it, and the local verification of the
+//! result, on the blocking thread pool. The three proofs, which only share
+//! their inputs, are generated in parallel on the rayon pool.
+//!
+//! The construction follows `transfer_split_proof_data` from
+//! `spl-token-confidential-transfer-proof-generation` step by step, so the
+//! output is interchangeable with `TransferAccountInfo::generate_split_transfer_proof_data`.
+//!
+//! Every generation is timed. The timings are returned, attached to the
+//! `ProofGenerated` event and accumulated in process-wide `proof_metrics`.
+
+use crate::events::Operation;
+use crate::types::*;
+use crate::verification::verify_transfer_proofs;
+use serde::Serialize;
+use serde_with::{serde_as, DurationMilliSeconds};
+use spl_token_2022::{
+    extension::confidential_transfer::account_info::TransferAccountInfo,
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+            grouped_elgamal::GroupedElGamal,
+            pedersen::{Pedersen, PedersenOpening},
+        },
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU128Data,
+            CiphertextCommitmentEqualityProofData, ZkProofData,
+        },
+    },
+};
+use spl_token_confidential_transfer_proof_generation::{
+    transfer::TransferProofData, try_combine_lo_hi_ciphertexts, try_split_u64,
+    CiphertextValidityProofWithAuditorCiphertext, REMAINING_BALANCE_BIT_LENGTH,
+    TRANSFER_AMOUNT_HI_BITS, TRANSFER_AMOUNT_LO_BITS,
+};
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Padding in the 128-bit batched range proof of a transfer
+const RANGE_PROOF_PADDING_BIT_LENGTH: usize = 16;
+
+/// Time spent generating the proofs of one transfer
+///
+/// `total` covers encryption and all three proofs; as the proofs run in
+/// parallel it is less than their sum.
+#[serde_as]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ProofTimings {
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "equality_ms")]
+    pub equality: Duration,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "ciphertext_validity_ms")]
+    pub ciphertext_validity: Duration,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "range_ms")]
+    pub range: Duration,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "total_ms")]
+    pub total: Duration,
+}
+
+/// Proof generation totals since the process started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ProofMetrics {
+    /// Number of transfers proven
+    pub transfers: u64,
+    /// Summed timings of all transfers
+    pub timings: ProofTimings,
+    /// Slowest single transfer
+    pub slowest: ProofTimings,
+}
+
+const NO_TIMINGS: ProofTimings = ProofTimings {
+    equality: Duration::ZERO,
+    ciphertext_validity: Duration::ZERO,
+    range: Duration::ZERO,
+    total: Duration::ZERO,
+};
+
+static METRICS: Mutex<ProofMetrics> = Mutex::new(ProofMetrics {
+    transfers: 0,
+    timings: NO_TIMINGS,
+    slowest: NO_TIMINGS,
+});
+
+/// Snapshot of the proof generation totals
+pub fn proof_metrics() -> ProofMetrics {
+    *METRICS.lock().unwrap()
+}
+
+fn record(timings: ProofTimings) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.transfers += 1;
+    metrics.timings.equality += timings.equality;
+    metrics.timings.ciphertext_validity += timings.ciphertext_validity;
+    metrics.timings.range += timings.range;
+    metrics.timings.total += timings.total;
+    if timings.total > metrics.slowest.total {
+        metrics.slowest = timings;
+    }
+}
+
+fn timed<T>(generate: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    (generate(), start.elapsed())
+}
+
+/// Generate transfer proofs on the blocking thread pool
+///
+/// The proofs are verified locally on the same thread before they're
+/// returned, failing with an `InvalidProof`. Takes its inputs by value so
+/// the work can leave the async task.
+pub async fn generate_transfer_proofs(
+    account_info: TransferAccountInfo,
+    amount: u64,
+    source_elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+    destination_elgamal_pubkey: ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+) -> CtResult<(TransferProofData, ProofTimings)> {
+    tokio::task::spawn_blocking(move || -> Result<_, Box<dyn Error + Send + Sync>> {
+        let (proof_data, timings) = transfer_proofs(
+            &account_info,
+            amount,
+            &source_elgamal_keypair,
+            &aes_key,
+            &destination_elgamal_pubkey,
+            auditor_elgamal_pubkey.as_ref(),
+        ).map_err(|e| e.to_string())?;
+        verify_transfer_proofs(Operation::Transfer, &proof_data)?;
+        Ok((proof_data, timings))
+    })
+    .await?
+    .map_err(|e| e as Box<dyn Error>)
+}
+
+/// Generate transfer proofs on the calling thread, the proofs in parallel
+///
+/// For callers already off the async runtime (e.g. a blocking pipeline).
+#[tracing::instrument(skip_all, fields(amount))]
+pub fn transfer_proofs(
+    account_info: &TransferAccountInfo,
+    amount: u64,
+    source_elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    destination_elgamal_pubkey: &ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<&ElGamalPubkey>,
+) -> CtResult<(TransferProofData, ProofTimings)> {
+    let start = Instant::now();
+
+    let current_available_balance: ElGamalCiphertext = account_info.available_balance.try_into()
+        .map_err(|_| "Failed to convert available_balance")?;
+    let current_decryptable_balance: AeCiphertext = account_info.decryptable_available_balance.try_into()
+        .map_err(|_| "Failed to convert decryptable_available_balance")?;
+
+    let default_auditor_pubkey = ElGamalPubkey::default();
+    let auditor_elgamal_pubkey = auditor_elgamal_pubkey.unwrap_or(&default_auditor_pubkey);
+    let source_elgamal_pubkey = source_elgamal_keypair.pubkey();
+    let pubkeys = [source_elgamal_pubkey, destination_elgamal_pubkey, auditor_elgamal_pubkey];
+
+    // Encrypt the lo and hi parts of the amount for source, destination and auditor
+    let (amount_lo, amount_hi) = try_split_u64(amount, TRANSFER_AMOUNT_LO_BITS)
+        .ok_or("Invalid transfer amount")?;
+    let opening_lo = PedersenOpening::new_rand();
+    let opening_hi = PedersenOpening::new_rand();
+    let grouped_ciphertext_lo = GroupedElGamal::<3>::encrypt_with(pubkeys, amount_lo, &opening_lo);
+    let grouped_ciphertext_hi = GroupedElGamal::<3>::encrypt_with(pubkeys, amount_hi, &opening_hi);
+
+    // Remaining balance, as the program will compute it and as a fresh commitment
+    let current_available = aes_key.decrypt(&current_decryptable_balance)
+        .ok_or("Failed to decrypt available balance")?;
+    let remaining = current_available.checked_sub(amount)
+        .ok_or_else(|| format!("Insufficient balance: have {}, need {}", current_available, amount))?;
+    let (remaining_commitment, remaining_opening) = Pedersen::new(remaining);
+    let source_ciphertext_lo = grouped_ciphertext_lo.to_elgamal_ciphertext(0)
+        .map_err(|_| "Failed to extract source ciphertext")?;
+    let source_ciphertext_hi = grouped_ciphertext_hi.to_elgamal_ciphertext(0)
+        .map_err(|_| "Failed to extract source ciphertext")?;
+    let source_ciphertext = try_combine_lo_hi_ciphertexts(&source_ciphertext_lo, &source_ciphertext_hi, TRANSFER_AMOUNT_LO_BITS)
+        .ok_or("Failed to combine source ciphertexts")?;
+    let remaining_ciphertext = current_available_balance - source_ciphertext;
+
+    let ((equality, equality_time), ((validity, validity_time), (range, range_time))) = rayon::join(
+        || timed(|| CiphertextCommitmentEqualityProofData::new(
+            source_elgamal_keypair,
+            &remaining_ciphertext,
+            &remaining_commitment,
+            &remaining_opening,
+            remaining,
+        )),
+        || rayon::join(
+            || timed(|| BatchedGroupedCiphertext3HandlesValidityProofData::new(
+                source_elgamal_pubkey,
+                destination_elgamal_pubkey,
+                auditor_elgamal_pubkey,
+                &grouped_ciphertext_lo,
+                &grouped_ciphertext_hi,
+                amount_lo,
+                amount_hi,
+                &opening_lo,
+                &opening_hi,
+            )),
+            || timed(|| {
+                // Range proofs need a power-of-two total bit length, hence the zero padding
+                let (padding_commitment, padding_opening) = Pedersen::new(0_u64);
+                BatchedRangeProofU128Data::new(
+                    vec![
+                        &remaining_commitment,
+                        &grouped_ciphertext_lo.commitment,
+                        &grouped_ciphertext_hi.commitment,
+                        &padding_commitment,
+                    ],
+                    vec![remaining, amount_lo, amount_hi, 0],
+                    vec![
+                        REMAINING_BALANCE_BIT_LENGTH,
+                        TRANSFER_AMOUNT_LO_BITS,
+                        TRANSFER_AMOUNT_HI_BITS,
+                        RANGE_PROOF_PADDING_BIT_LENGTH,
+                    ],
+                    vec![&remaining_opening, &opening_lo, &opening_hi, &padding_opening],
+                )
+            }),
+        ),
+    );
+    let equality_proof_data = equality
+        .map_err(|e| format!("Failed to generate equality proof: {}", e))?;
+    let validity_proof_data = validity
+        .map_err(|e| format!("Failed to generate ciphertext validity proof: {}", e))?;
+    let range_proof_data = range
+        .map_err(|e| format!("Failed to generate range proof: {}", e))?;
+
+    // The transfer instruction carries the auditor's part of the amount ciphertexts
+    let context = validity_proof_data.context_data();
+    let ciphertext_lo = context.grouped_ciphertext_lo.try_extract_ciphertext(2)
+        .map_err(|_| "Failed to extract auditor ciphertext")?;
+    let ciphertext_hi = context.grouped_ciphertext_hi.try_extract_ciphertext(2)
+        .map_err(|_| "Failed to extract auditor ciphertext")?;
+
+    let timings = ProofTimings {
+        equality: equality_time,
+        ciphertext_validity: validity_time,
+        range: range_time,
+        total: start.elapsed(),
+    };
+    record(timings);
+    tracing::debug!(?timings, "Generated transfer proofs");
+
+    Ok((
+        TransferProofData {
+            equality_proof_data,
+            ciphertext_validity_proof_data_with_ciphertext: CiphertextValidityProofWithAuditorCiphertext {
+                proof_data: validity_proof_data,
+                ciphertext_lo,
+                ciphertext_hi,
+            },
+            range_proof_data,
+        },
+        timings,
+    ))
+}
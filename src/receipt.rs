@@ -0,0 +1,227 @@
+//! Receipts of confidential operations
+//!
+//! Every operation returns a `Receipt` instead of bare signatures: each
+//! transaction sent with its role, slot, fee, compute units and the rent it
+//! locked in or returned from the accounts the operation created or closed,
+//! and the account's decrypted balances once the operation is done.
+//! Receipts serialize to JSON.
+//!
+//! A receipt is built after the operation has landed, so it never fails:
+//! details the RPC node can't return (no transaction history, commitment
+//! lag) are left empty rather than reporting a landed operation as failed.
+
+use crate::events::Operation;
+use crate::key_provider::KeyProvider;
+use crate::types::*;
+use crate::watch::{BalanceBreakdown, WatchOnlyAccount};
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::collections::HashMap;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+
+/// What a transaction of an operation did
+///
+/// Proof `Create` roles cover every transaction that sets up the proof's
+/// context state account, including writing and closing its record account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionRole {
+    CreateMint,
+    CreateAccount,
+    CloseAccount,
+    Configure,
+    Deposit,
+    ApplyPendingBalance,
+    Withdraw,
+    Transfer,
+    EqualityProofCreate,
+    EqualityProofClose,
+    CiphertextValidityProofCreate,
+    CiphertextValidityProofClose,
+    RangeProofCreate,
+    RangeProofClose,
+    HarvestWithheldFees,
+    WithdrawWithheldFees,
+    ConfidentialMint,
+    ConfidentialBurn,
+    ApplyPendingBurn,
+    RotateSupplyKey,
+}
+
+/// One confirmed transaction of an operation
+///
+/// Everything but the role and signature is `None` when the confirmed
+/// transaction couldn't be fetched.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransactionReceipt {
+    pub role: TransactionRole,
+    #[serde_as(as = "DisplayFromStr")]
+    pub signature: Signature,
+    pub slot: Option<u64>,
+    /// Fee paid, in lamports
+    pub fee: Option<u64>,
+    /// Compute units consumed, when the RPC node reports them
+    pub compute_units: Option<u64>,
+    /// Lamports moved into the accounts this transaction created
+    pub rent_spent: Option<u64>,
+    /// Lamports held by the accounts this transaction closed
+    pub rent_reclaimed: Option<u64>,
+}
+
+/// Outcome of a confidential operation
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Receipt {
+    pub operation: Operation,
+    /// Token account the operation acted on, if any
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub token_account: Option<Pubkey>,
+    /// Transactions in the order they were sent
+    pub transactions: Vec<TransactionReceipt>,
+    /// Total fees, in lamports, of the transactions that could be fetched
+    /// (as are the other totals)
+    pub fee: u64,
+    /// Total compute units reported
+    pub compute_units: u64,
+    pub rent_spent: u64,
+    pub rent_reclaimed: u64,
+    /// Decrypted balances of `token_account` after the operation, when
+    /// keys were given and the account can still be read
+    pub balances: Option<BalanceBreakdown>,
+}
+
+impl Receipt {
+    /// Signatures of every transaction, in order
+    pub fn signatures(&self) -> Vec<Signature> {
+        self.transactions.iter().map(|transaction| transaction.signature).collect()
+    }
+
+    /// Signature of the first transaction with `role`
+    pub fn signature(&self, role: TransactionRole) -> Option<Signature> {
+        self.transactions.iter()
+            .find(|transaction| transaction.role == role)
+            .map(|transaction| transaction.signature)
+    }
+}
+
+/// Transactions an operation sent, and the accounts whose rent it moved
+#[derive(Debug, Default)]
+pub(crate) struct SentTransactions {
+    /// Signatures with their roles, in the order they were sent
+    pub transactions: Vec<(TransactionRole, Signature)>,
+    /// Accounts the operation created or closed (proof context states,
+    /// records, the mint or token account itself)
+    pub rent_accounts: Vec<Pubkey>,
+}
+
+impl SentTransactions {
+    /// A single transaction that created or closed `rent_accounts`
+    pub fn single(role: TransactionRole, signature: Signature, rent_accounts: &[Pubkey]) -> Self {
+        Self { transactions: vec![(role, signature)], rent_accounts: rent_accounts.to_vec() }
+    }
+
+    pub fn push(&mut self, role: TransactionRole, signature: Signature) {
+        self.transactions.push((role, signature));
+    }
+
+    pub fn extend(&mut self, role: TransactionRole, signatures: impl IntoIterator<Item = Signature>) {
+        self.transactions.extend(signatures.into_iter().map(|signature| (role, signature)));
+    }
+}
+
+/// Build the receipt of an operation from its sent transactions
+///
+/// Fetches each confirmed transaction and, with `keys`, decrypts
+/// `token_account`. Rent is counted only for `sent.rent_accounts`: lamports
+/// an account received while going from zero are spent, lamports it held
+/// when going to zero are reclaimed. Transactions that can't be fetched
+/// keep only their role and signature.
+pub(crate) fn fetch_receipt(
+    client: &RpcClient,
+    operation: Operation,
+    token_account: Option<&Pubkey>,
+    sent: &SentTransactions,
+    keys: Option<&dyn KeyProvider>,
+) -> Receipt {
+    let transactions: Vec<_> = sent.transactions.iter()
+        .map(|(role, signature)| {
+            fetch_transaction_receipt(client, *role, signature, &sent.rent_accounts).unwrap_or_else(|e| {
+                tracing::warn!("Failed to fetch transaction {} for its receipt: {}", signature, e);
+                TransactionReceipt {
+                    role: *role,
+                    signature: *signature,
+                    slot: None,
+                    fee: None,
+                    compute_units: None,
+                    rent_spent: None,
+                    rent_reclaimed: None,
+                }
+            })
+        })
+        .collect();
+
+    // The operation already went through; balances that can't be read are left out
+    let balances = token_account.zip(keys)
+        .and_then(|(token_account, keys)| WatchOnlyAccount::from_provider(*token_account, keys).ok())
+        .and_then(|account| account.balances(client).ok());
+
+    Receipt {
+        operation,
+        token_account: token_account.copied(),
+        fee: transactions.iter().filter_map(|transaction| transaction.fee).sum(),
+        compute_units: transactions.iter().filter_map(|transaction| transaction.compute_units).sum(),
+        rent_spent: transactions.iter().filter_map(|transaction| transaction.rent_spent).sum(),
+        rent_reclaimed: transactions.iter().filter_map(|transaction| transaction.rent_reclaimed).sum(),
+        transactions,
+        balances,
+    }
+}
+
+/// Fetch a confirmed transaction's slot, fee, compute units and rent movements
+fn fetch_transaction_receipt(
+    client: &RpcClient,
+    role: TransactionRole,
+    signature: &Signature,
+    rent_accounts: &[Pubkey],
+) -> CtResult<TransactionReceipt> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = client.get_transaction_with_config(signature, config)?;
+    let transaction = confirmed.transaction.transaction.decode()
+        .ok_or_else(|| format!("Failed to decode transaction {}", signature))?;
+    let meta = confirmed.transaction.meta
+        .ok_or_else(|| format!("Transaction {} has no status metadata", signature))?;
+
+    // Created and closed accounts are fresh keys or the operation's own
+    // accounts, never loaded from a lookup table, so they're static keys
+    let balances: HashMap<&Pubkey, (u64, u64)> = transaction.message.static_account_keys().iter()
+        .zip(meta.pre_balances.iter().zip(&meta.post_balances))
+        .map(|(key, (pre, post))| (key, (*pre, *post)))
+        .collect();
+    let moved = rent_accounts.iter().filter_map(|account| balances.get(account));
+    let rent_spent = moved.clone()
+        .filter(|(pre, post)| *pre == 0 && *post > 0)
+        .map(|(_, post)| post)
+        .sum();
+    let rent_reclaimed = moved
+        .filter(|(pre, post)| *pre > 0 && *post == 0)
+        .map(|(pre, _)| pre)
+        .sum();
+
+    Ok(TransactionReceipt {
+        role,
+        signature: *signature,
+        slot: Some(confirmed.slot),
+        fee: Some(meta.fee),
+        compute_units: meta.compute_units_consumed.into(),
+        rent_spent: Some(rent_spent),
+        rent_reclaimed: Some(rent_reclaimed),
+    })
+}
@@ -0,0 +1,290 @@
+//! Pre-flight validation of transfer recipients
+//!
+//! A confidential transfer to an account that can't receive it only fails
+//! once the proofs are generated and the proof accounts paid for.
+//! `validate_recipient` checks the recipient's token account up front and
+//! returns an `InvalidRecipient` with a typed `RecipientReason`, so UIs can
+//! tell users why a transfer can't be sent (downcast the error to read it).
+//!
+//! A recipient is given either as an owner wallet, whose associated token
+//! account receives, or directly as a token account. `RecipientAddress`
+//! tells them apart by whether the address holds a Token-2022 account.
+//!
+//! Accounts requiring incoming transfer memos (`MemoTransfer`) are valid
+//! recipients, flagged by `ValidRecipient::memo_required`; transfers to
+//! them fail with `RecipientReason::MemoRequired` unless given a memo.
+
+use crate::accounts::create_token_account;
+use crate::types::*;
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Signer};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        memo_transfer::memo_required,
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    solana_zk_sdk::encryption::elgamal::ElGamalPubkey,
+    state::{Account as TokenAccount, AccountState},
+};
+use std::fmt;
+
+/// Why a token account can't receive a confidential transfer
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RecipientReason {
+    /// No account at the address (e.g. the recipient has no token account yet)
+    AccountNotFound,
+    /// The account isn't a Token-2022 token account
+    NotATokenAccount,
+    /// The token account holds another mint
+    WrongMint {
+        #[serde_as(as = "DisplayFromStr")]
+        mint: Pubkey,
+    },
+    /// The account was never configured for confidential transfers
+    NotConfigured,
+    /// Configured, but the mint's authority hasn't approved it
+    NotApproved,
+    /// The owner disabled incoming confidential transfers
+    ConfidentialCreditsDisabled,
+    /// The account is frozen
+    Frozen,
+    /// The pending balance must be applied before further credits
+    CreditCounterFull {
+        pending_balance_credit_counter: u64,
+        maximum_pending_balance_credit_counter: u64,
+    },
+    /// The account's ElGamal pubkey isn't a valid point
+    InvalidElGamalPubkey,
+    /// The account requires a memo on incoming transfers and none was given
+    MemoRequired,
+}
+
+impl fmt::Display for RecipientReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AccountNotFound => f.write_str("token account not found"),
+            Self::NotATokenAccount => f.write_str("not a token account"),
+            Self::WrongMint { mint } => write!(f, "token account of mint {}", mint),
+            Self::NotConfigured => f.write_str("not configured for confidential transfers; its owner must configure it first"),
+            Self::NotApproved => f.write_str("not approved"),
+            Self::ConfidentialCreditsDisabled => f.write_str("confidential credits disabled"),
+            Self::Frozen => f.write_str("frozen"),
+            Self::CreditCounterFull { pending_balance_credit_counter, maximum_pending_balance_credit_counter } => write!(
+                f,
+                "pending balance credit counter full ({} of {}); the owner must apply the pending balance",
+                pending_balance_credit_counter, maximum_pending_balance_credit_counter
+            ),
+            Self::InvalidElGamalPubkey => f.write_str("invalid ElGamal pubkey"),
+            Self::MemoRequired => f.write_str("incoming transfers require a memo"),
+        }
+    }
+}
+
+/// A recipient token account that can't receive a confidential transfer
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct InvalidRecipient {
+    #[serde_as(as = "DisplayFromStr")]
+    pub token_account: Pubkey,
+    #[serde(flatten)]
+    pub reason: RecipientReason,
+}
+
+impl fmt::Display for InvalidRecipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recipient token account {} can't receive confidential transfers: {}", self.token_account, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidRecipient {}
+
+/// Where a recipient address sends the transfer
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecipientAddress {
+    /// A wallet (or any non-token address); its associated token account receives
+    Owner {
+        #[serde_as(as = "DisplayFromStr")]
+        owner: Pubkey,
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+    },
+    /// A token account given directly
+    TokenAccount {
+        #[serde_as(as = "DisplayFromStr")]
+        token_account: Pubkey,
+    },
+}
+
+impl RecipientAddress {
+    /// Classify `address` from its account data (`None` when it doesn't exist)
+    ///
+    /// Addresses holding a Token-2022 token account are token accounts;
+    /// anything else, including an address without an account and other
+    /// Token-2022 accounts such as a multisig owner, is an owner.
+    pub fn classify(mint: &Pubkey, address: &Pubkey, account_data: Option<&Account>) -> Self {
+        match account_data {
+            Some(account_data)
+                if account_data.owner == spl_token_2022::id()
+                    && StateWithExtensions::<TokenAccount>::unpack(&account_data.data).is_ok() =>
+            {
+                Self::TokenAccount { token_account: *address }
+            }
+            _ => Self::Owner {
+                owner: *address,
+                token_account: get_associated_token_address_with_program_id(address, mint, &spl_token_2022::id()),
+            },
+        }
+    }
+
+    /// Token account receiving the transfer
+    pub fn token_account(&self) -> Pubkey {
+        match self {
+            Self::Owner { token_account, .. } | Self::TokenAccount { token_account } => *token_account,
+        }
+    }
+}
+
+/// A token account ready to receive a confidential transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidRecipient {
+    pub token_account: Pubkey,
+    pub owner: Pubkey,
+    /// Pubkey the transfer amount is encrypted to
+    pub elgamal_pubkey: ElGamalPubkey,
+    pub pending_balance_credit_counter: u64,
+    pub maximum_pending_balance_credit_counter: u64,
+    /// Incoming transfers must come right after an SPL Memo instruction
+    pub memo_required: bool,
+}
+
+impl ValidRecipient {
+    /// Fail with `RecipientReason::MemoRequired` if a transfer without memo would be rejected
+    pub fn check_memo(&self, memo: Option<&str>) -> Result<(), InvalidRecipient> {
+        if self.memo_required && memo.is_none() {
+            return Err(InvalidRecipient { token_account: self.token_account, reason: RecipientReason::MemoRequired });
+        }
+        Ok(())
+    }
+}
+
+/// Interpret `recipient` as an owner wallet or a token account
+pub fn resolve_recipient(client: &RpcClient, mint: &Pubkey, recipient: &Pubkey) -> CtResult<RecipientAddress> {
+    let account_data = client.get_account_with_commitment(recipient, client.commitment())?.value;
+    Ok(RecipientAddress::classify(mint, recipient, account_data.as_ref()))
+}
+
+/// Check that `recipient` can receive a transfer
+///
+/// `recipient` is an owner wallet or a token account (see
+/// `RecipientAddress`). Fails with an `InvalidRecipient` when it can't
+/// receive, or with the RPC error when an account can't be fetched.
+pub fn validate_recipient(client: &RpcClient, mint: &Pubkey, recipient: &Pubkey) -> CtResult<ValidRecipient> {
+    let account_data = client.get_account_with_commitment(recipient, client.commitment())?.value;
+    match RecipientAddress::classify(mint, recipient, account_data.as_ref()) {
+        RecipientAddress::TokenAccount { token_account } => {
+            Ok(check_recipient_account(mint, &token_account, account_data.as_ref())?)
+        }
+        RecipientAddress::Owner { token_account, .. } => validate_recipient_token_account(client, mint, &token_account),
+    }
+}
+
+/// Create the recipient's associated token account if missing, then validate it
+///
+/// `payer` pays the rent. A new account can only receive public transfers:
+/// configuring it for confidential transfers needs the owner's signature,
+/// so this then fails with `RecipientReason::NotConfigured` until the
+/// owner runs `configure_account_for_confidential_transfers`.
+///
+/// Steps:
+/// 1. Resolve the recipient (explicit token accounts are never created)
+/// 2. Create the owner's associated token account when it doesn't exist
+/// 3. Validate the account
+pub async fn ensure_recipient_account(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+) -> CtResult<ValidRecipient> {
+    let address = resolve_recipient(client, mint, recipient)?;
+    if let RecipientAddress::Owner { owner, token_account } = address {
+        if client.get_account_with_commitment(&token_account, client.commitment())?.value.is_none() {
+            create_token_account(client, payer, &owner, mint).await?;
+        }
+    }
+    validate_recipient_token_account(client, mint, &address.token_account())
+}
+
+/// Check that a specific token account can receive a transfer
+pub fn validate_recipient_token_account(
+    client: &RpcClient,
+    mint: &Pubkey,
+    token_account: &Pubkey,
+) -> CtResult<ValidRecipient> {
+    let account_data = client.get_account_with_commitment(token_account, client.commitment())?.value;
+    Ok(check_recipient_account(mint, token_account, account_data.as_ref())?)
+}
+
+/// Check already fetched recipient account data (`None` when missing)
+///
+/// Checks, in order: the account exists, is a token account of `mint`,
+/// isn't frozen, is configured, approved and accepts confidential credits,
+/// has room for one more pending credit, and has a valid ElGamal pubkey.
+/// Whether it requires memos is reported, not checked (see `check_memo`).
+pub fn check_recipient_account(
+    mint: &Pubkey,
+    token_account: &Pubkey,
+    account_data: Option<&Account>,
+) -> Result<ValidRecipient, InvalidRecipient> {
+    let invalid = |reason| InvalidRecipient { token_account: *token_account, reason };
+
+    let account_data = account_data.ok_or(invalid(RecipientReason::AccountNotFound))?;
+    if account_data.owner != spl_token_2022::id() {
+        return Err(invalid(RecipientReason::NotATokenAccount));
+    }
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)
+        .map_err(|_| invalid(RecipientReason::NotATokenAccount))?;
+    if account.base.mint != *mint {
+        return Err(invalid(RecipientReason::WrongMint { mint: account.base.mint }));
+    }
+    if account.base.state == AccountState::Frozen {
+        return Err(invalid(RecipientReason::Frozen));
+    }
+
+    let ct_extension = account.get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| invalid(RecipientReason::NotConfigured))?;
+    if !bool::from(ct_extension.approved) {
+        return Err(invalid(RecipientReason::NotApproved));
+    }
+    if !bool::from(ct_extension.allow_confidential_credits) {
+        return Err(invalid(RecipientReason::ConfidentialCreditsDisabled));
+    }
+
+    let pending_balance_credit_counter: u64 = ct_extension.pending_balance_credit_counter.into();
+    let maximum_pending_balance_credit_counter: u64 = ct_extension.maximum_pending_balance_credit_counter.into();
+    if pending_balance_credit_counter >= maximum_pending_balance_credit_counter {
+        return Err(invalid(RecipientReason::CreditCounterFull {
+            pending_balance_credit_counter,
+            maximum_pending_balance_credit_counter,
+        }));
+    }
+
+    let elgamal_pubkey = ct_extension.elgamal_pubkey.try_into()
+        .map_err(|_| invalid(RecipientReason::InvalidElGamalPubkey))?;
+
+    Ok(ValidRecipient {
+        token_account: *token_account,
+        owner: account.base.owner,
+        elgamal_pubkey,
+        pending_balance_credit_counter,
+        maximum_pending_balance_credit_counter,
+        memo_required: memo_required(&account),
+    })
+}
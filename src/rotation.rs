@@ -0,0 +1,455 @@
+//! ElGamal key rotation for existing confidential accounts
+//!
+//! Token-2022 only lets an account be configured for confidential transfers
+//! once, so rotating its encryption keys means rebuilding the account:
+//! the confidential balance is withdrawn to public, the account is emptied,
+//! closed and recreated at the same associated address with the new keys,
+//! and the balance is deposited back.
+//!
+//! The public balance is parked in a holding account owned by the same
+//! authority (derived with a per-mint seed, so it needs no extra keypair) while the
+//! associated token account is closed. Progress is written to a JSON file
+//! after every step, so an interrupted rotation resumes where it stopped.
+//!
+//! Accounts with withheld transfer fees must be harvested first, and mints
+//! without auto-approve must re-approve the recreated account.
+
+use crate::accounts::empty_account_instructions;
+use crate::apply_pending::apply_pending_balance_with_keys;
+use crate::configure::configure_account_with_keys;
+use crate::decryption;
+use crate::deposit::deposit_to_confidential;
+use crate::events::{self, Operation};
+use crate::key_provider::KeyProvider;
+use crate::types::*;
+use crate::withdraw::withdraw_with_keys;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::{Pubkey, MAX_SEED_LEN},
+    signature::{Signature, Signer},
+    transaction::Transaction,
+};
+use solana_system_interface::instruction as system_instruction;
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    instruction::{close_account, initialize_account3, transfer_checked},
+    solana_zk_sdk::encryption::elgamal::ElGamalKeypair,
+    state::{Account as TokenAccount, Mint},
+};
+use std::fs;
+use std::path::Path;
+
+/// Seed prefix of the holding account that keeps the public balance during a rotation
+const HOLDING_ACCOUNT_SEED_PREFIX: &str = "ct-rot-";
+
+/// Last completed step of a rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationStep {
+    /// Confidential balance recorded, nothing sent yet
+    Started,
+    /// Pending balance applied with the current keys
+    PendingApplied,
+    /// Available balance withdrawn to public
+    Withdrawn,
+    /// Confidential extension emptied
+    Emptied,
+    /// Public balance parked in the holding account
+    MovedToHolding,
+    /// Associated token account closed
+    Closed,
+    /// Account recreated and configured with the new keys
+    Reconfigured,
+    /// Public balance returned and holding account closed
+    Returned,
+    /// Confidential balance deposited back
+    Redeposited,
+    /// Redeposited balance applied with the new keys
+    Completed,
+}
+
+/// Rotation progress persisted between runs
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationProgress {
+    #[serde_as(as = "DisplayFromStr")]
+    pub mint: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub token_account: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub holding_account: Pubkey,
+    /// ElGamal pubkey the account is being rotated to
+    pub new_elgamal_pubkey: String,
+    /// Confidential balance (pending + available) before the rotation
+    pub confidential_amount: u64,
+    pub step: RotationStep,
+    /// Signatures of every transaction sent so far
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub signatures: Vec<Signature>,
+}
+
+impl RotationProgress {
+    /// Load a saved rotation, if the progress file exists
+    pub fn load(path: &Path) -> CtResult<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    fn save(&self, path: &Path) -> CtResult<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Record a completed step and persist it
+    fn advance(&mut self, step: RotationStep, signatures: Vec<Signature>, path: &Path) -> CtResult<()> {
+        self.step = step;
+        self.signatures.extend(signatures);
+        self.save(path)
+    }
+}
+
+/// Rotate the encryption keys of the authority's confidential token account
+///
+/// Steps:
+/// 1. Apply pending balance and withdraw everything to public (current keys)
+/// 2. Empty the confidential extension and park the public balance
+/// 3. Close and recreate the account, configured with the new keys
+/// 4. Return the public balance and redeposit the confidential amount
+/// 5. Apply the redeposit (new keys)
+///
+/// Resumes from `progress_path` if it holds a rotation of the same account;
+/// a resumed rotation must use the same new keys.
+#[tracing::instrument(skip_all, fields(authority = %authority.pubkey(), %mint))]
+pub async fn rotate_encryption_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &Pubkey,
+    current_keys: &dyn KeyProvider,
+    new_keys: &dyn KeyProvider,
+    progress_path: &Path,
+) -> CtResult<RotationProgress> {
+    let token_account = get_associated_token_address_with_program_id(
+        &authority.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+    let holding_account = Pubkey::create_with_seed(
+        &authority.pubkey(),
+        &holding_account_seed(mint),
+        &spl_token_2022::id(),
+    )?;
+    let current_elgamal_keypair = current_keys.elgamal_keypair(&token_account)?;
+    let new_elgamal_keypair = new_keys.elgamal_keypair(&token_account)?;
+    let new_elgamal_pubkey = new_elgamal_keypair.pubkey().to_string();
+
+    let mint_data = client.get_account(mint)?;
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_data.data)?;
+    let decimals = mint_state.base.decimals;
+
+    let mut progress = match RotationProgress::load(progress_path)? {
+        Some(progress) => {
+            if progress.mint != *mint
+                || progress.token_account != token_account
+                || progress.holding_account != holding_account
+            {
+                return Err(format!(
+                    "Progress file {} belongs to another token account",
+                    progress_path.display()
+                ).into());
+            }
+            if progress.new_elgamal_pubkey != new_elgamal_pubkey {
+                return Err("Rotation in progress uses a different new ElGamal key".into());
+            }
+            events::progress(Operation::KeyRotation, format!("Resuming key rotation after step {:?}", progress.step));
+            progress
+        }
+        None => {
+            let (pending, available) = decrypt_balances(client, &token_account, &current_elgamal_keypair)?;
+            let progress = RotationProgress {
+                mint: *mint,
+                token_account,
+                holding_account,
+                new_elgamal_pubkey,
+                confidential_amount: pending + available,
+                step: RotationStep::Started,
+                signatures: Vec::new(),
+            };
+            progress.save(progress_path)?;
+            events::progress(
+                Operation::KeyRotation,
+                format!("Rotating keys of {} ({} confidential tokens)", token_account, progress.confidential_amount),
+            );
+            progress
+        }
+    };
+
+    if progress.step < RotationStep::PendingApplied {
+        let (pending, _) = decrypt_balances(client, &token_account, &current_elgamal_keypair)?;
+        let mut signatures = Vec::new();
+        if pending > 0 {
+            signatures.extend(apply_pending_balance_with_keys(
+                client, payer, authority, mint, current_keys,
+            ).await?.signatures());
+        }
+        progress.advance(RotationStep::PendingApplied, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Withdrawn {
+        let (_, available) = decrypt_balances(client, &token_account, &current_elgamal_keypair)?;
+        let mut signatures = Vec::new();
+        if available > 0 {
+            signatures.extend(withdraw_with_keys(
+                client, payer, authority, mint, available, decimals, current_keys,
+            ).await?.signatures());
+        }
+        progress.advance(RotationStep::Withdrawn, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Emptied {
+        let signature = empty_confidential_account(client, payer, authority, &token_account, &current_elgamal_keypair)?;
+        progress.advance(RotationStep::Emptied, vec![signature], progress_path)?;
+    }
+
+    if progress.step < RotationStep::MovedToHolding {
+        let signatures = move_public_balance(client, payer, authority, mint, decimals, &token_account, &holding_account, true)?;
+        progress.advance(RotationStep::MovedToHolding, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Closed {
+        let mut signatures = Vec::new();
+        if client.get_account(&token_account).is_ok() {
+            let close_ix = close_account(
+                &spl_token_2022::id(),
+                &token_account,
+                &payer.pubkey(),
+                &authority.pubkey(),
+                &[&authority.pubkey()],
+            )?;
+            signatures.push(send(client, payer, authority, &[close_ix])?);
+            events::progress(Operation::KeyRotation, format!("Closed {} for reconfiguration", token_account));
+        }
+        progress.advance(RotationStep::Closed, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Reconfigured {
+        let create_ix = create_associated_token_account_idempotent(
+            &payer.pubkey(),
+            &authority.pubkey(),
+            mint,
+            &spl_token_2022::id(),
+        );
+        let mut signatures = vec![send(client, payer, authority, &[create_ix])?];
+
+        let account_data = client.get_account(&token_account)?;
+        let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+        if account.get_extension::<ConfidentialTransferAccount>().is_err() {
+            signatures.extend(configure_account_with_keys(
+                client, payer, authority, mint, new_keys,
+            ).await?.signatures());
+        }
+        progress.advance(RotationStep::Reconfigured, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Returned {
+        let signatures = move_public_balance(client, payer, authority, mint, decimals, &holding_account, &token_account, false)?;
+        progress.advance(RotationStep::Returned, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Redeposited {
+        let (pending, _) = decrypt_balances(client, &token_account, &new_elgamal_keypair)?;
+        let mut signatures = Vec::new();
+        if progress.confidential_amount > 0 && pending == 0 {
+            signatures.extend(deposit_to_confidential(
+                client, payer, authority, mint, progress.confidential_amount, decimals,
+            ).await?.signatures());
+        }
+        progress.advance(RotationStep::Redeposited, signatures, progress_path)?;
+    }
+
+    if progress.step < RotationStep::Completed {
+        let (pending, _) = decrypt_balances(client, &token_account, &new_elgamal_keypair)?;
+        let mut signatures = Vec::new();
+        if pending > 0 {
+            signatures.extend(apply_pending_balance_with_keys(
+                client, payer, authority, mint, new_keys,
+            ).await?.signatures());
+        }
+        progress.advance(RotationStep::Completed, signatures, progress_path)?;
+    }
+
+    events::completed(
+        Operation::KeyRotation,
+        format!(
+            "Key rotation complete for {} (new ElGamal pubkey: {})",
+            token_account, progress.new_elgamal_pubkey
+        ),
+    );
+
+    Ok(progress)
+}
+
+/// Decrypt the pending and available balances of a configured account
+///
+/// Returns zeros for an account that doesn't exist or isn't configured
+/// yet, which is the state between closing and reconfiguring.
+fn decrypt_balances(
+    client: &RpcClient,
+    token_account: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+) -> CtResult<(u64, u64)> {
+    let Ok(account_data) = client.get_account(token_account) else {
+        return Ok((0, 0));
+    };
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+    let Ok(ct_extension) = account.get_extension::<ConfidentialTransferAccount>() else {
+        return Ok((0, 0));
+    };
+
+    decryption::engine()
+        .decrypt_balances(elgamal_keypair.secret(), ct_extension)
+        .map_err(|e| format!("{} with the current key", e).into())
+}
+
+/// Zero out the confidential extension so the account can be closed
+fn empty_confidential_account(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    token_account: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+) -> CtResult<Signature> {
+    let instructions = empty_account_instructions(client, &authority.pubkey(), token_account, elgamal_keypair)?;
+    let signature = send(client, payer, authority, &instructions)?;
+    events::progress(Operation::KeyRotation, format!("Emptied confidential balance of {}: {}", token_account, signature));
+
+    Ok(signature)
+}
+
+/// Move the whole public balance between the token account and the holding account
+///
+/// Parking (`to_holding`) creates the holding account if needed; returning
+/// closes it afterwards. Does nothing if the source account doesn't exist.
+#[allow(clippy::too_many_arguments)]
+fn move_public_balance(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &Pubkey,
+    decimals: u8,
+    source: &Pubkey,
+    destination: &Pubkey,
+    to_holding: bool,
+) -> CtResult<Vec<Signature>> {
+    let Ok(source_data) = client.get_account(source) else {
+        return Ok(Vec::new());
+    };
+    let amount = StateWithExtensions::<TokenAccount>::unpack(&source_data.data)?.base.amount;
+
+    let mut instructions = Vec::new();
+    if to_holding && client.get_account(destination).is_err() {
+        instructions.extend(create_holding_account(client, payer, authority, mint, destination)?);
+    }
+    if amount > 0 {
+        instructions.push(transfer_checked(
+            &spl_token_2022::id(),
+            source,
+            mint,
+            destination,
+            &authority.pubkey(),
+            &[&authority.pubkey()],
+            amount,
+            decimals,
+        )?);
+    }
+    if !to_holding {
+        instructions.push(close_account(
+            &spl_token_2022::id(),
+            source,
+            &payer.pubkey(),
+            &authority.pubkey(),
+            &[&authority.pubkey()],
+        )?);
+    }
+    if instructions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let signature = send(client, payer, authority, &instructions)?;
+    events::progress(
+        Operation::KeyRotation,
+        format!("Moved {} public tokens {} -> {}: {}", amount, source, destination, signature),
+    );
+
+    Ok(vec![signature])
+}
+
+/// Seed of an authority's holding account for a mint
+///
+/// Each mint gets its own holding account, so an interrupted rotation of one
+/// mint doesn't collide with a rotation of another. The mint prefix keeps the
+/// seed within the 32-byte limit.
+fn holding_account_seed(mint: &Pubkey) -> String {
+    let mint = mint.to_string();
+    let prefix_len = MAX_SEED_LEN - HOLDING_ACCOUNT_SEED_PREFIX.len();
+    format!("{}{}", HOLDING_ACCOUNT_SEED_PREFIX, &mint[..prefix_len.min(mint.len())])
+}
+
+/// Instructions creating the seed-derived holding account for a mint
+fn create_holding_account(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &Pubkey,
+    holding_account: &Pubkey,
+) -> CtResult<Vec<Instruction>> {
+    let mint_data = client.get_account(mint)?;
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_data.data)?;
+    let extension_types = ExtensionType::get_required_init_account_extensions(
+        &mint_state.get_extension_types()?,
+    );
+    let space = ExtensionType::try_calculate_account_len::<TokenAccount>(&extension_types)?;
+    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+
+    Ok(vec![
+        system_instruction::create_account_with_seed(
+            &payer.pubkey(),
+            holding_account,
+            &authority.pubkey(),
+            &holding_account_seed(mint),
+            rent,
+            space as u64,
+            &spl_token_2022::id(),
+        ),
+        initialize_account3(&spl_token_2022::id(), holding_account, mint, &authority.pubkey())?,
+    ])
+}
+
+/// Send a transaction signed by the payer and authority
+fn send(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    instructions: &[Instruction],
+) -> CtResult<Signature> {
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        &[payer, authority],
+        recent_blockhash,
+    );
+    events::send_and_confirm(client, Operation::KeyRotation, &transaction)
+}
@@ -0,0 +1,347 @@
+//! Load signers from every source the Solana tools accept
+//!
+//! A signer source is a single string, so it fits in a CLI flag or an
+//! environment variable:
+//!
+//! | Source | Example |
+//! |--------|---------|
+//! | Keypair file | `~/.config/solana/id.json`, `file:/keys/owner.json` |
+//! | JSON byte array | `[12,34,...]` (64 bytes, as in keypair files) |
+//! | Base58 keypair | `4Z7cXSyeFR8wNGMVXUE1TwtKn5D5Vu7FzEv69dokLv7K...` |
+//! | Seed phrase | `seed:word1 word2 ... word12?key=0/0` |
+//! | Prompt | `prompt://?key=0/0` (seed phrase typed at a prompt) |
+//! | Legacy prompt | `ASK` (seed phrase typed at a prompt, no derivation path) |
+//! | Remote signer | `remote://127.0.0.1:9000` |
+//!
+//! `?key=<account>/<change>` selects the BIP44 derivation path
+//! `m/44'/501'/<account>'/<change>'`; without it the default Solana path
+//! `m/44'/501'` is used. `ASK` keeps the Solana CLI's legacy derivation,
+//! the keypair built straight from the seed bytes, so the same phrase gives
+//! the same address as `solana-keygen` with `ASK`.
+//!
+//! The remote signer is a stand-in for hardware wallets and signing
+//! services: it speaks one JSON line per request over plain TCP and must
+//! only be used on localhost (see `serve_signing_requests`). Every request
+//! carries a shared token; `remote://` sources read it from the
+//! `CT_REMOTE_SIGNER_TOKEN` environment variable so it never shows up in
+//! a command line.
+
+use crate::key_provider::constant_time_eq;
+use crate::types::*;
+use bip39::{Language, Mnemonic, Seed};
+use serde::{Deserialize, Serialize};
+use solana_derivation_path::DerivationPath;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{
+        keypair_from_seed, keypair_from_seed_and_derivation_path, read_keypair_file, Keypair, Signature, Signer,
+    },
+    signer::SignerError,
+};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+
+/// Environment variable holding the shared token of `remote://` signers
+pub const REMOTE_SIGNER_TOKEN_VAR: &str = "CT_REMOTE_SIGNER_TOKEN";
+
+/// Where to load a signer from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignerSource {
+    /// Solana CLI keypair file
+    File(PathBuf),
+    /// JSON array of the 64 keypair bytes
+    JsonBytes(Vec<u8>),
+    /// Base58-encoded 64 keypair bytes
+    Base58(String),
+    /// BIP39 seed phrase
+    SeedPhrase {
+        phrase: String,
+        derivation_path: Option<DerivationPath>,
+    },
+    /// Seed phrase and passphrase typed at a prompt
+    Prompt { derivation_path: Option<DerivationPath> },
+    /// Seed phrase and passphrase typed at a prompt, keypair built from the
+    /// seed bytes without a derivation path (the Solana CLI's `ASK`)
+    LegacyPrompt,
+    /// Remote signer listening on a local socket
+    Remote { address: String },
+}
+
+impl SignerSource {
+    /// Parse a signer source string (see the module docs for the formats)
+    pub fn parse(source: &str) -> CtResult<Self> {
+        let source = source.trim();
+
+        if source == "ASK" {
+            return Ok(Self::LegacyPrompt);
+        }
+        if let Some(rest) = source.strip_prefix("prompt:") {
+            let (_, derivation_path) = split_derivation_path(rest.trim_start_matches("//"))?;
+            return Ok(Self::Prompt { derivation_path });
+        }
+        if let Some(address) = source.strip_prefix("remote://") {
+            return Ok(Self::Remote { address: address.to_string() });
+        }
+        if let Some(rest) = source.strip_prefix("seed:") {
+            let (phrase, derivation_path) = split_derivation_path(rest)?;
+            return Ok(Self::SeedPhrase { phrase: phrase.to_string(), derivation_path });
+        }
+        if let Some(path) = source.strip_prefix("file:") {
+            return Ok(Self::File(expand_home(path)));
+        }
+        if source.starts_with('[') {
+            let bytes: Vec<u8> = serde_json::from_str(source)
+                .map_err(|e| format!("Invalid JSON keypair bytes: {}", e))?;
+            return Ok(Self::JsonBytes(bytes));
+        }
+        if !source.contains(['/', '.', '~']) && bs58::decode(source).into_vec().is_ok_and(|bytes| bytes.len() == 64) {
+            return Ok(Self::Base58(source.to_string()));
+        }
+
+        Ok(Self::File(expand_home(source)))
+    }
+}
+
+/// `<prefix>?key=<account>/<change>` → (`<prefix>`, derivation path)
+fn split_derivation_path(source: &str) -> CtResult<(&str, Option<DerivationPath>)> {
+    let Some((prefix, query)) = source.split_once('?') else {
+        return Ok((source, None));
+    };
+    let key = query.strip_prefix("key=")
+        .ok_or_else(|| format!("Unsupported signer query: {}", query))?;
+    let derivation_path = DerivationPath::from_key_str(key)
+        .map_err(|e| format!("Invalid derivation path {}: {}", key, e))?;
+    Ok((prefix, Some(derivation_path)))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Load a signer from any source string
+pub fn load_signer(source: &str) -> CtResult<Box<dyn Signer>> {
+    match SignerSource::parse(source)? {
+        SignerSource::Remote { address } => {
+            let auth_token = std::env::var(REMOTE_SIGNER_TOKEN_VAR)
+                .map_err(|_| format!("{} is not set for remote://{}", REMOTE_SIGNER_TOKEN_VAR, address))?;
+            Ok(Box::new(RemoteSigner::connect(&address, &auth_token)?))
+        }
+        other => Ok(Box::new(keypair_from_source(other)?)),
+    }
+}
+
+/// Load a keypair from any local source
+///
+/// For keys that must be a `Keypair` (e.g. a new mint account's keypair
+/// saved for later); remote signers can't be loaded this way.
+pub fn load_keypair(source: &str) -> CtResult<Keypair> {
+    keypair_from_source(SignerSource::parse(source)?)
+}
+
+fn keypair_from_source(source: SignerSource) -> CtResult<Keypair> {
+    match source {
+        SignerSource::File(path) => read_keypair_file(&path)
+            .map_err(|e| format!("Failed to read keypair from {}: {}", path.display(), e).into()),
+        SignerSource::JsonBytes(bytes) => keypair_from_bytes(&bytes),
+        SignerSource::Base58(encoded) => {
+            let bytes = bs58::decode(&encoded).into_vec()
+                .map_err(|_| "Invalid base58 keypair")?;
+            keypair_from_bytes(&bytes)
+        }
+        SignerSource::SeedPhrase { phrase, derivation_path } => {
+            keypair_from_seed_phrase(&phrase, "", derivation_path)
+        }
+        SignerSource::Prompt { derivation_path } => {
+            let phrase = rpassword::prompt_password("Seed phrase: ")?;
+            let passphrase = rpassword::prompt_password("BIP39 passphrase (empty for none): ")?;
+            keypair_from_seed_phrase(&phrase, &passphrase, derivation_path)
+        }
+        SignerSource::LegacyPrompt => {
+            let phrase = rpassword::prompt_password("Seed phrase: ")?;
+            let passphrase = rpassword::prompt_password("BIP39 passphrase (empty for none): ")?;
+            keypair_from_legacy_seed_phrase(&phrase, &passphrase)
+        }
+        SignerSource::Remote { address } => {
+            Err(format!("remote://{} can't be loaded as a local keypair", address).into())
+        }
+    }
+}
+
+/// Keypair from the 64 bytes of a keypair file (secret key then pubkey)
+fn keypair_from_bytes(bytes: &[u8]) -> CtResult<Keypair> {
+    if bytes.len() != 64 {
+        return Err(format!("Invalid keypair: expected 64 bytes, got {}", bytes.len()).into());
+    }
+    Ok(Keypair::try_from(bytes).map_err(|_| "Invalid keypair: pubkey doesn't match secret key")?)
+}
+
+/// Keypair from a BIP39 seed phrase, optional passphrase and derivation path
+pub fn keypair_from_seed_phrase(
+    phrase: &str,
+    passphrase: &str,
+    derivation_path: Option<DerivationPath>,
+) -> CtResult<Keypair> {
+    keypair_from_seed_and_derivation_path(seed_from_phrase(phrase, passphrase)?.as_bytes(), derivation_path)
+}
+
+/// Keypair from a BIP39 seed phrase the legacy way, from the seed bytes
+///
+/// Matches the Solana CLI's `ASK` keypairs, which predate derivation paths.
+pub fn keypair_from_legacy_seed_phrase(phrase: &str, passphrase: &str) -> CtResult<Keypair> {
+    keypair_from_seed(seed_from_phrase(phrase, passphrase)?.as_bytes())
+}
+
+fn seed_from_phrase(phrase: &str, passphrase: &str) -> CtResult<Seed> {
+    let phrase = phrase.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mnemonic = Mnemonic::from_phrase(&phrase, Language::English)
+        .map_err(|e| format!("Invalid seed phrase: {}", e))?;
+    Ok(Seed::new(&mnemonic, passphrase))
+}
+
+/// Request sent to a remote signer
+#[derive(Debug, Serialize, Deserialize)]
+struct RemoteSignRequest {
+    /// Shared token the service was started with
+    auth_token: String,
+    #[serde(flatten)]
+    call: RemoteSignCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum RemoteSignCall {
+    Pubkey,
+    Sign { message: String },
+}
+
+/// Response of a remote signer
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RemoteSignResponse {
+    pubkey: Option<String>,
+    signature: Option<String>,
+    error: Option<String>,
+}
+
+/// Signer that forwards signing requests to a remote signer over TCP
+pub struct RemoteSigner {
+    address: String,
+    auth_token: String,
+    pubkey: Pubkey,
+}
+
+impl RemoteSigner {
+    /// Connect with the service's shared token and fetch its pubkey
+    pub fn connect(address: &str, auth_token: &str) -> CtResult<Self> {
+        let mut signer = Self { address: address.to_string(), auth_token: auth_token.to_string(), pubkey: Pubkey::default() };
+        let response = signer.request(RemoteSignCall::Pubkey)
+            .map_err(|e| format!("Remote signer at {}: {}", address, e))?;
+        signer.pubkey = response.pubkey
+            .ok_or("Remote signer sent no pubkey")?
+            .parse()
+            .map_err(|_| "Remote signer sent an invalid pubkey")?;
+        Ok(signer)
+    }
+
+    /// Send one request and read its response
+    fn request(&self, call: RemoteSignCall) -> Result<RemoteSignResponse, String> {
+        let request = RemoteSignRequest { auth_token: self.auth_token.clone(), call };
+        let mut stream = TcpStream::connect(&self.address).map_err(|e| e.to_string())?;
+        let line = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+        writeln!(stream, "{}", line).map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).map_err(|e| e.to_string())?;
+        let response: RemoteSignResponse = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+        match response.error {
+            Some(error) => Err(error),
+            None => Ok(response),
+        }
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let response = self.request(RemoteSignCall::Sign { message: hex::encode(message) })
+            .map_err(SignerError::Connection)?;
+        let signature: Signature = response.signature
+            .ok_or_else(|| SignerError::Protocol("Remote signer sent no signature".to_string()))?
+            .parse()
+            .map_err(|_| SignerError::Protocol("Remote signer sent an invalid signature".to_string()))?;
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(SignerError::Protocol("Remote signature doesn't verify".to_string()));
+        }
+        Ok(signature)
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// Answer remote signing requests with a local signer (stand-in service)
+///
+/// The service signs whatever it's sent, so it only listens on a loopback
+/// address, only answers local peers, and only requests carrying
+/// `auth_token`. Serves `max_requests` connections, or forever if `None`;
+/// a failing connection is logged and doesn't stop the service.
+pub fn serve_signing_requests(
+    listener: TcpListener,
+    signer: &dyn Signer,
+    auth_token: &str,
+    max_requests: Option<usize>,
+) -> CtResult<()> {
+    if auth_token.is_empty() {
+        return Err("Remote signer needs a non-empty auth token".into());
+    }
+    let local_address = listener.local_addr()?;
+    if !local_address.ip().is_loopback() {
+        return Err(format!("Remote signer must listen on a loopback address, not {}", local_address).into());
+    }
+    let mut served = 0;
+    while max_requests.is_none_or(|max| served < max) {
+        let (stream, peer) = listener.accept()?;
+        served += 1;
+        if !peer.ip().is_loopback() {
+            tracing::warn!("Refused signing request from non-local peer {}", peer);
+            continue;
+        }
+        if let Err(e) = answer_signing_request(stream, signer, auth_token) {
+            tracing::warn!("Failed to answer signing request from {}: {}", peer, e);
+        }
+    }
+    Ok(())
+}
+
+/// Answer the single request of one remote signer connection
+fn answer_signing_request(mut stream: TcpStream, signer: &dyn Signer, auth_token: &str) -> CtResult<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match serde_json::from_str::<RemoteSignRequest>(&line) {
+        Ok(request) if !constant_time_eq(request.auth_token.as_bytes(), auth_token.as_bytes()) => {
+            RemoteSignResponse { error: Some("Unauthorized".to_string()), ..Default::default() }
+        }
+        Ok(RemoteSignRequest { call: RemoteSignCall::Pubkey, .. }) => RemoteSignResponse {
+            pubkey: Some(signer.pubkey().to_string()),
+            ..Default::default()
+        },
+        Ok(RemoteSignRequest { call: RemoteSignCall::Sign { message }, .. }) => match hex::decode(&message) {
+            Ok(message) => match signer.try_sign_message(&message) {
+                Ok(signature) => RemoteSignResponse { signature: Some(signature.to_string()), ..Default::default() },
+                Err(e) => RemoteSignResponse { error: Some(e.to_string()), ..Default::default() },
+            },
+            Err(_) => RemoteSignResponse { error: Some("Message isn't valid hex".to_string()), ..Default::default() },
+        },
+        Err(e) => RemoteSignResponse { error: Some(format!("Invalid request: {}", e)), ..Default::default() },
+    };
+
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
@@ -1,13 +1,27 @@
 //! Confidential transfer between accounts
 //!
-//! This implements confidential transfers using proof context state accounts
-//! to avoid transaction size limitations.
-
+//! The three transfer proofs don't fit in one transaction together. Each
+//! one is either verified inline, by an instruction next to the transfer,
+//! or beforehand into a proof context state account; by default as many as
+//! fit are inlined (see `proof_placement`).
+
+use crate::authority::Authority;
+use crate::decryption;
+use crate::events::{self, Operation, OperationEvent};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::memo::with_memo;
+use crate::proof_placement::{
+    fetch_lookup_table, proof_location, select_placements, send_versioned, transaction_size,
+    with_record_fallback, TransferOptions, TransferPlacements,
+};
+use crate::proof_accounts::{close_context_state_accounts, create_proof_account};
+use crate::proofs::generate_transfer_proofs;
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
+use crate::recipient::validate_recipient;
 use crate::types::*;
 use solana_client::rpc_client::RpcClient;
-use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
-use solana_commitment_config::CommitmentConfig;
 use solana_sdk::{
+    instruction::Instruction,
     signature::{Keypair, Signer},
 };
 use spl_associated_token_account::get_associated_token_address_with_program_id;
@@ -15,73 +29,129 @@ use spl_token_2022::{
     extension::{
         confidential_transfer::{
             account_info::TransferAccountInfo,
+            instruction::transfer,
             ConfidentialTransferAccount,
         },
         BaseStateWithExtensions, StateWithExtensions,
     },
-    solana_zk_sdk::encryption::{
-        auth_encryption::AeKey,
-        elgamal::ElGamalKeypair,
-    },
     state::Account as TokenAccount,
 };
-use spl_token_client::{
-    client::{ProgramRpcClient, ProgramRpcClientSendTransaction, RpcClientResponse},
-    token::{ProofAccountWithCiphertext, Token},
-};
 use spl_token_confidential_transfer_proof_generation::transfer::TransferProofData;
-use std::sync::Arc;
-
-/// Helper to extract signature from RpcClientResponse
-fn extract_signature(response: RpcClientResponse) -> Result<solana_sdk::signature::Signature, Box<dyn std::error::Error>> {
-    match response {
-        RpcClientResponse::Signature(sig) => Ok(sig),
-        _ => Err("Expected Signature response".into()),
-    }
-}
 
-/// Transfer tokens confidentially from sender to recipient using proof context state accounts
+/// Transfer tokens confidentially from sender to recipient
+///
+/// `recipient` is either the recipient's wallet, whose associated token
+/// account receives, or the destination token account itself.
 ///
 /// This implementation:
-/// 1. Fetches recipient's and auditor's ElGamal public keys from their accounts
+/// 1. Validates the recipient's account (see `validate_recipient`),
+///    including that a memo is given if it requires one, and fetches the
+///    recipient's and auditor's ElGamal public keys
 /// 2. Generates ZK proofs for the transfer
-/// 3. Creates temporary on-chain accounts to store the proofs
-/// 4. Executes the transfer referencing those proof accounts
-/// 5. Closes the proof accounts to reclaim rent
+/// 3. Picks the proof placements: as many proofs inline as fit in the
+///    transfer transaction, the rest in proof context state accounts
+/// 4. Creates temporary on-chain accounts for the proofs that aren't inline
+/// 5. Executes the transfer with the inline proofs, referencing the others
+/// 6. Closes the proof accounts to reclaim rent
 ///
-/// This approach avoids transaction size limitations by not including proofs inline.
+/// `payer` pays for the transfer transaction and the proof accounts, and
+/// gets their rent back.
 ///
-/// Note: sender must be a Keypair (not just a Signer) because the Token client requires
-/// cloning the keypair for fee payment.
-///
-/// Returns signatures for all transactions (proof creation + transfer + cleanup)
+/// Returns the receipt of all transactions (proof creation + transfer +
+/// cleanup) with the sender's remaining balances
 pub async fn transfer_confidential(
     client: &RpcClient,
-    _payer: &dyn Signer,
-    sender: &Keypair,
+    payer: &dyn Signer,
+    sender: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    recipient: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+) -> ReceiptResult {
+    transfer_confidential_with_keys(client, payer, sender, mint, recipient, amount, &SignerKeyProvider::new(sender)).await
+}
+
+/// Transfer confidentially with the sender's keys taken from a key provider
+///
+/// Same as `transfer_confidential`, for senders whose keys aren't derived
+/// from their keypair.
+pub async fn transfer_confidential_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    sender: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    recipient: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    transfer_confidential_with_options(client, payer, sender, mint, recipient, amount, keys, &TransferOptions::default()).await
+}
+
+/// Transfer confidentially with explicit proof placements or a lookup table
+///
+/// Same as `transfer_confidential_with_keys`. `options.placements` forces
+/// where each proof goes (the transfer fails if it doesn't fit);
+/// `options.lookup_table` sends the transfer as a v0 transaction using that
+/// address lookup table, leaving room for more inline proofs;
+/// `options.memo` is sent in an SPL Memo instruction right before it.
+#[allow(clippy::too_many_arguments)]
+pub async fn transfer_confidential_with_options(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    sender: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    recipient: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    keys: &dyn KeyProvider,
+    options: &TransferOptions,
+) -> ReceiptResult {
+    transfer_with_authority(client, payer, &Authority::Signer(sender), mint, recipient, amount, keys, options).await
+}
+
+/// Transfer confidentially from a single-signer or multisig sender
+///
+/// Same as `transfer_confidential_with_options`; a multisig's signers are
+/// checked against the multisig account before any proof is generated.
+#[allow(clippy::too_many_arguments)]
+pub async fn transfer_with_authority(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    sender: &Authority<'_>,
     mint: &solana_sdk::pubkey::Pubkey,
     recipient: &solana_sdk::pubkey::Pubkey,
     amount: u64,
-) -> MultiSigResult {
+    keys: &dyn KeyProvider,
+    options: &TransferOptions,
+) -> ReceiptResult {
+    let sent = send_transfer(client, payer, sender, mint, recipient, amount, keys, options).await?;
+    let sender_token_account = get_associated_token_address_with_program_id(&sender.pubkey(), mint, &spl_token_2022::id());
+    Ok(fetch_receipt(client, Operation::Transfer, Some(&sender_token_account), &sent, Some(keys)))
+}
+
+/// Transfer, returning every transaction sent with its role
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(sender = %sender.pubkey(), %mint, %recipient, amount))]
+async fn send_transfer(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    sender: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    recipient: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    keys: &dyn KeyProvider,
+    options: &TransferOptions,
+) -> CtResult<SentTransactions> {
+    sender.check(client)?;
     let sender_token_account = get_associated_token_address_with_program_id(
         &sender.pubkey(),
         mint,
         &spl_token_2022::id(),
     );
 
-    let recipient_token_account = get_associated_token_address_with_program_id(
-        recipient,
-        mint,
-        &spl_token_2022::id(),
-    );
-
-    // Fetch recipient's ElGamal public key from their account
-    let recipient_account_data = client.get_account(&recipient_token_account)?;
-    let recipient_account = StateWithExtensions::<TokenAccount>::unpack(&recipient_account_data.data)?;
-    let recipient_ct_extension = recipient_account.get_extension::<ConfidentialTransferAccount>()?;
-    let recipient_elgamal_pubkey: spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalPubkey =
-        recipient_ct_extension.elgamal_pubkey.try_into()
-            .map_err(|_| "Failed to convert recipient ElGamal pubkey")?;
+    // Check the recipient can receive before paying for proofs, and take its ElGamal public key
+    let recipient_account = validate_recipient(client, mint, recipient)?;
+    recipient_account.check_memo(options.memo.as_deref())?;
+    let recipient_token_account = recipient_account.token_account;
+    let recipient_elgamal_pubkey = recipient_account.elgamal_pubkey;
 
     // Fetch auditor's ElGamal public key from the mint account
     use spl_token_2022::extension::confidential_transfer::ConfidentialTransferMint;
@@ -97,16 +167,9 @@ pub async fn transfer_confidential(
             .transpose()
             .map_err(|_| "Failed to convert auditor ElGamal pubkey")?;
 
-    // Derive sender's encryption keys
-    let sender_elgamal = ElGamalKeypair::new_from_signer(
-        sender,
-        &sender_token_account.to_bytes(),
-    )?;
-
-    let sender_aes = AeKey::new_from_signer(
-        sender,
-        &sender_token_account.to_bytes(),
-    )?;
+    // Fetch sender's encryption keys
+    let sender_elgamal = keys.elgamal_keypair(&sender_token_account)?;
+    let sender_aes = keys.aes_key(&sender_token_account)?;
 
     // Fetch sender's account state
     let account_data = client.get_account(&sender_token_account)?;
@@ -121,7 +184,7 @@ pub async fn transfer_confidential(
         transfer_info.available_balance.try_into()
             .map_err(|_| "Failed to convert available_balance")?;
 
-    let current_available = available_balance.decrypt_u32(sender_elgamal.secret())
+    let current_available = decryption::engine().decrypt_u32(sender_elgamal.secret(), &available_balance)
         .ok_or("Failed to decrypt available balance")?;
 
     if current_available < amount {
@@ -131,141 +194,144 @@ pub async fn transfer_confidential(
         ).into());
     }
 
-    println!("🔐 Generating transfer proofs for {} tokens...", amount);
-
-    // Generate transfer proofs
+    // Generate transfer proofs and check them locally, before paying for any
+    // proof account, off the async runtime
+    let (proof_data, timings) = generate_transfer_proofs(
+        transfer_info,
+        amount,
+        sender_elgamal.clone(),
+        sender_aes.clone(),
+        recipient_elgamal_pubkey,
+        auditor_elgamal_pubkey,
+    ).await?;
+    events::emit(OperationEvent::ProofGenerated { operation: Operation::Transfer, amount, timings: Some(timings) });
     let TransferProofData {
         equality_proof_data,
         ciphertext_validity_proof_data_with_ciphertext,
         range_proof_data,
-    } = transfer_info.generate_split_transfer_proof_data(
-        amount,
-        &sender_elgamal,
-        &sender_aes,
-        &recipient_elgamal_pubkey,
-        auditor_elgamal_pubkey.as_ref(),
-    )?;
-
-    println!("📦 Creating proof context state accounts...");
-
-    // Create async RpcClient for spl-token-client
-    let rpc_url = client.url();
-    let async_client = Arc::new(AsyncRpcClient::new_with_commitment(
-        rpc_url,
-        CommitmentConfig::confirmed(),
-    ));
-
-    // Create Token client wrapper
-    // Note: We use sender as the fee payer for Token operations since they must
-    // have SOL anyway to pay for the proof account rent
-    let program_client = Arc::new(ProgramRpcClient::new(
-        async_client,
-        ProgramRpcClientSendTransaction,
-    ));
-
-    // Clone sender keypair to create Arc<dyn Signer> for Token client
-    let sender_clone = Keypair::new_from_array(*sender.secret_bytes());
-    let sender_arc: Arc<dyn Signer> = Arc::new(sender_clone);
-
-    let token = Token::new(
-        program_client,
-        &spl_token_2022::id(),
-        mint,
-        None, // decimals - not needed for this operation
-        sender_arc,
-    );
+    } = proof_data;
 
-    // Create proof context state accounts
+    // Assemble the transfer instruction (and inline proof instructions) for a placement
+    let new_decryptable_balance = sender_aes.encrypt(current_available - amount).into();
+    let multisig_signers = sender.multisig_signers();
+    let multisig_signers: Vec<_> = multisig_signers.iter().collect();
     let equality_proof_account = Keypair::new();
     let ciphertext_validity_proof_account = Keypair::new();
     let range_proof_account = Keypair::new();
-
-    let mut signatures = Vec::new();
-
-    // Create equality proof account
-    let response = token.confidential_transfer_create_context_state_account(
-        &equality_proof_account.pubkey(),
-        &sender.pubkey(),
-        &equality_proof_data,
-        false,
-        &[&equality_proof_account],
-    ).await?;
-    signatures.push(extract_signature(response)?);
-
-    // Create ciphertext validity proof account
-    let response = token.confidential_transfer_create_context_state_account(
-        &ciphertext_validity_proof_account.pubkey(),
-        &sender.pubkey(),
-        &ciphertext_validity_proof_data_with_ciphertext.proof_data,
-        false,
-        &[&ciphertext_validity_proof_account],
-    ).await?;
-    signatures.push(extract_signature(response)?);
-
-    // Create range proof account
-    let response = token.confidential_transfer_create_context_state_account(
-        &range_proof_account.pubkey(),
-        &sender.pubkey(),
-        &range_proof_data,
-        true, // range proofs require split proof
-        &[&range_proof_account],
-    ).await?;
-    signatures.push(extract_signature(response)?);
-
-    println!("🔄 Executing confidential transfer...");
-
-    // Execute transfer using proof context accounts
-    let ciphertext_validity_proof = ProofAccountWithCiphertext {
-        context_state_account: ciphertext_validity_proof_account.pubkey(),
-        ciphertext_lo: ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
-        ciphertext_hi: ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+    let transfer_instructions = |placements: TransferPlacements| -> CtResult<Vec<Instruction>> {
+        let mut next_offset = 1;
+        Ok(with_memo(options.memo.as_deref(), transfer(
+            &spl_token_2022::id(),
+            &sender_token_account,
+            mint,
+            &recipient_token_account,
+            &new_decryptable_balance,
+            &ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+            &ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+            &sender.pubkey(),
+            &multisig_signers,
+            proof_location(placements.equality, &mut next_offset, &equality_proof_account.pubkey(), &equality_proof_data)?,
+            proof_location(
+                placements.ciphertext_validity,
+                &mut next_offset,
+                &ciphertext_validity_proof_account.pubkey(),
+                &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            )?,
+            proof_location(placements.range, &mut next_offset, &range_proof_account.pubkey(), &range_proof_data)?,
+        )?))
     };
 
-    let response = token.confidential_transfer_transfer(
-        &sender_token_account,
-        &recipient_token_account,
-        &sender.pubkey(),
-        Some(&equality_proof_account.pubkey()),
-        Some(&ciphertext_validity_proof),
-        Some(&range_proof_account.pubkey()),
-        amount,
-        None, // Let Token client fetch account info internally
-        &sender_elgamal,
-        &sender_aes,
-        &recipient_elgamal_pubkey,
-        auditor_elgamal_pubkey.as_ref(),
-        &[sender],
-    ).await?;
-    signatures.push(extract_signature(response)?);
-
-    println!("🧹 Closing proof context accounts...");
+    // Inline as many proofs as fit in one packet; the rest go to context state accounts
+    let lookup_tables = options.lookup_table.as_ref()
+        .map(|address| fetch_lookup_table(client, address))
+        .transpose()?
+        .into_iter()
+        .collect::<Vec<_>>();
+    // Proofs whose context state verification alone is too large go through a record
+    let placements = match options.placements {
+        Some(placements) => placements,
+        None => {
+            let placements: TransferPlacements = select_placements(|placements| {
+                transaction_size(&payer.pubkey(), &transfer_instructions(placements)?, &lookup_tables)
+            })?;
+            let payer = payer.pubkey();
+            TransferPlacements {
+                equality: with_record_fallback(placements.equality, &payer, &payer, &equality_proof_data)?,
+                ciphertext_validity: with_record_fallback(
+                    placements.ciphertext_validity,
+                    &payer,
+                    &payer,
+                    &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+                )?,
+                range: with_record_fallback(placements.range, &payer, &payer, &range_proof_data)?,
+            }
+        }
+    };
+    events::progress(Operation::Transfer, format!("Proof placement: {}", placements));
+
+    let mut sent = SentTransactions::default();
+    let mut context_accounts = Vec::new();
+
+    // Create proof context state accounts for proofs that aren't inline, through
+    // a record where the verification doesn't fit alone, then execute the
+    // transfer; the proof accounts are closed below even if this fails partway
+    let transferred = (|| -> SigResult {
+        create_proof_account(
+            client,
+            Operation::Transfer,
+            payer,
+            placements.equality,
+            (TransactionRole::EqualityProofCreate, TransactionRole::EqualityProofClose),
+            &equality_proof_account,
+            &equality_proof_data,
+            &mut sent,
+            &mut context_accounts,
+        )?;
+        create_proof_account(
+            client,
+            Operation::Transfer,
+            payer,
+            placements.ciphertext_validity,
+            (TransactionRole::CiphertextValidityProofCreate, TransactionRole::CiphertextValidityProofClose),
+            &ciphertext_validity_proof_account,
+            &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            &mut sent,
+            &mut context_accounts,
+        )?;
+        create_proof_account(
+            client,
+            Operation::Transfer,
+            payer,
+            placements.range,
+            (TransactionRole::RangeProofCreate, TransactionRole::RangeProofClose),
+            &range_proof_account,
+            &range_proof_data,
+            &mut sent,
+            &mut context_accounts,
+        )?;
+
+        // Execute transfer with the inline proofs and references to the context accounts
+        send_versioned(
+            client,
+            Operation::Transfer,
+            payer,
+            &sender.signers(),
+            &transfer_instructions(placements)?,
+            &lookup_tables,
+        )
+    })();
+    if let Ok(signature) = transferred {
+        sent.push(TransactionRole::Transfer, signature);
+    }
 
     // Close proof accounts to reclaim rent
-    let response = token.confidential_transfer_close_context_state_account(
-        &equality_proof_account.pubkey(),
-        &sender_token_account,
-        &sender.pubkey(),
-        &[sender],
-    ).await?;
-    signatures.push(extract_signature(response)?);
-
-    let response = token.confidential_transfer_close_context_state_account(
-        &ciphertext_validity_proof_account.pubkey(),
-        &sender_token_account,
-        &sender.pubkey(),
-        &[sender],
-    ).await?;
-    signatures.push(extract_signature(response)?);
+    close_context_state_accounts(client, Operation::Transfer, payer, &context_accounts, &mut sent);
+    transferred?;
 
-    let response = token.confidential_transfer_close_context_state_account(
-        &range_proof_account.pubkey(),
-        &sender_token_account,
-        &sender.pubkey(),
-        &[sender],
-    ).await?;
-    signatures.push(extract_signature(response)?);
-
-    println!("✅ Transfer complete with {} transactions", signatures.len());
+    events::completed(
+        Operation::Transfer,
+        format!("Transfer complete with {} transactions", sent.transactions.len()),
+    );
 
-    Ok(signatures)
+    Ok(sent)
 }
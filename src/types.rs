@@ -11,3 +11,6 @@ pub type SigResult = CtResult<Signature>;
 
 /// Signature result for multi-transaction operations
 pub type MultiSigResult = CtResult<Vec<Signature>>;
+
+/// Receipt result for operations reporting their transactions and balances
+pub type ReceiptResult = CtResult<crate::receipt::Receipt>;
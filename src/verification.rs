@@ -0,0 +1,98 @@
+//! Local verification of generated proofs before they are sent
+//!
+//! The ZK ElGamal proof program rejects an invalid proof only once it is
+//! on chain, after the proof context accounts have been paid for. Every
+//! operation runs the same zk-sdk verifiers locally right after
+//! generating its proofs and stops with an `InvalidProof` naming the
+//! proof that failed (downcast the error to read it).
+
+use crate::events::Operation;
+use bytemuck::Pod;
+use serde::Serialize;
+use spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::proof_data::{PubkeyValidityProofData, ZkProofData};
+use spl_token_confidential_transfer_proof_generation::{transfer::TransferProofData, withdraw::WithdrawProofData};
+use std::fmt;
+
+/// Which proof of an operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofKind {
+    /// The account owner knows the secret of their ElGamal pubkey (configure)
+    PubkeyValidity,
+    /// The new available balance ciphertext matches its commitment
+    Equality,
+    /// The amount ciphertexts are well-formed for every pubkey (transfer)
+    CiphertextValidity,
+    /// The remaining balance (and amount) are non-negative and in range
+    Range,
+}
+
+impl fmt::Display for ProofKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::PubkeyValidity => "pubkey validity",
+            Self::Equality => "equality",
+            Self::CiphertextValidity => "ciphertext validity",
+            Self::Range => "range",
+        })
+    }
+}
+
+/// A generated proof that fails local verification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InvalidProof {
+    pub operation: Operation,
+    pub proof: ProofKind,
+    /// Verifier error
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The {} {} proof failed local verification: {}", self.operation, self.proof, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidProof {}
+
+/// Verify one proof with the zk-sdk verifier
+pub fn verify_proof<T: Pod + ZkProofData<U>, U: Pod>(
+    operation: Operation,
+    proof: ProofKind,
+    proof_data: &T,
+) -> Result<(), InvalidProof> {
+    proof_data.verify_proof().map_err(|e| InvalidProof { operation, proof, reason: e.to_string() })
+}
+
+/// Verify the pubkey validity proof of a configure
+pub fn verify_configure_proof(proof_data: &PubkeyValidityProofData) -> Result<(), InvalidProof> {
+    verify_proof(Operation::Configure, ProofKind::PubkeyValidity, proof_data)
+}
+
+/// Verify the equality and range proofs of a withdrawal
+pub fn verify_withdraw_proofs(proof_data: &WithdrawProofData) -> Result<(), InvalidProof> {
+    let (equality, range) = rayon::join(
+        || verify_proof(Operation::Withdraw, ProofKind::Equality, &proof_data.equality_proof_data),
+        || verify_proof(Operation::Withdraw, ProofKind::Range, &proof_data.range_proof_data),
+    );
+    equality.and(range)
+}
+
+/// Verify the equality, ciphertext validity and range proofs of a transfer
+///
+/// The three are verified in parallel; the first failure, in that order,
+/// is returned.
+pub fn verify_transfer_proofs(operation: Operation, proof_data: &TransferProofData) -> Result<(), InvalidProof> {
+    let (equality, (validity, range)) = rayon::join(
+        || verify_proof(operation, ProofKind::Equality, &proof_data.equality_proof_data),
+        || rayon::join(
+            || verify_proof(
+                operation,
+                ProofKind::CiphertextValidity,
+                &proof_data.ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            ),
+            || verify_proof(operation, ProofKind::Range, &proof_data.range_proof_data),
+        ),
+    );
+    equality.and(validity).and(range)
+}
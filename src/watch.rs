@@ -0,0 +1,165 @@
+//! View-only (watch) mode for confidential balances
+//!
+//! Accounting and monitoring services need to read confidential balances
+//! without ever being able to move funds. A `WatchOnlyAccount` holds only
+//! the ElGamal secret and AE key of a token account — never a `Signer` —
+//! and only exposes balance queries.
+//!
+//! It deliberately implements `KeyProvider` by refusing: handing a
+//! watch-only account to any operation (`withdraw_with_keys`,
+//! `apply_pending_balance_with_keys`, rotation, ...) fails before an
+//! instruction is built.
+
+use crate::decryption;
+use crate::key_provider::{ExplicitKeyProvider, KeyProvider};
+use crate::types::*;
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::{AeCiphertext, AeKey},
+        elgamal::ElGamalKeypair,
+    },
+    state::Account as TokenAccount,
+};
+
+/// Maximum accounts per `getMultipleAccounts` request
+pub(crate) const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Decrypted balances of a confidential token account
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceBreakdown {
+    #[serde_as(as = "DisplayFromStr")]
+    pub token_account: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub mint: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: Pubkey,
+    /// Non-confidential balance, visible to everyone
+    pub public: u64,
+    /// Incoming confidential balance not yet applied
+    pub pending: u64,
+    /// Confidential balance ready to transfer or withdraw
+    pub available: u64,
+    pub total: u64,
+    pub pending_balance_credit_counter: u64,
+    pub approved: bool,
+    pub allow_confidential_credits: bool,
+}
+
+/// Read-only handle on a confidential token account
+pub struct WatchOnlyAccount {
+    token_account: Pubkey,
+    keys: ExplicitKeyProvider,
+}
+
+impl WatchOnlyAccount {
+    /// Watch an account with explicit key material
+    pub fn new(token_account: Pubkey, elgamal_keypair: ElGamalKeypair, aes_key: AeKey) -> Self {
+        Self {
+            token_account,
+            keys: ExplicitKeyProvider::new(elgamal_keypair, aes_key),
+        }
+    }
+
+    /// Watch an account from raw ElGamal secret key and AE key bytes
+    pub fn from_bytes(token_account: Pubkey, elgamal_secret: &[u8], aes_key: &[u8]) -> CtResult<Self> {
+        Ok(Self {
+            token_account,
+            keys: ExplicitKeyProvider::from_bytes(elgamal_secret, aes_key)?,
+        })
+    }
+
+    /// Watch an account with keys taken once from a provider (e.g. a keystore)
+    pub fn from_provider(token_account: Pubkey, keys: &dyn KeyProvider) -> CtResult<Self> {
+        Ok(Self::new(
+            token_account,
+            keys.elgamal_keypair(&token_account)?,
+            keys.aes_key(&token_account)?,
+        ))
+    }
+
+    pub fn token_account(&self) -> &Pubkey {
+        &self.token_account
+    }
+
+    /// Fetch and decrypt the account's balances
+    pub fn balances(&self, client: &RpcClient) -> CtResult<BalanceBreakdown> {
+        let account_data = client.get_account(&self.token_account)?;
+        self.decrypt(&account_data)
+    }
+
+    /// Decrypt balances from already fetched account data
+    pub fn decrypt(&self, account_data: &Account) -> CtResult<BalanceBreakdown> {
+        let account = StateWithExtensions::<TokenAccount>::unpack(&account_data.data)?;
+        let ct_extension = account.get_extension::<ConfidentialTransferAccount>()?;
+        let elgamal_keypair = self.keys.elgamal_keypair(&self.token_account)?;
+        let aes_key = self.keys.aes_key(&self.token_account)?;
+
+        let pending = decryption::engine().decrypt_pending_balance(elgamal_keypair.secret(), ct_extension)?;
+
+        // The AES-encrypted copy of the available balance is much faster to decrypt
+        let decryptable_balance: AeCiphertext = ct_extension.decryptable_available_balance.try_into()
+            .map_err(|_| "Failed to convert decryptable_available_balance")?;
+        let available = aes_key.decrypt(&decryptable_balance)
+            .ok_or("Failed to decrypt decryptable_available_balance")?;
+
+        let public = account.base.amount;
+        let total = public.checked_add(pending)
+            .and_then(|total| total.checked_add(available))
+            .ok_or_else(|| format!("Total balance of {} overflows u64", self.token_account))?;
+
+        Ok(BalanceBreakdown {
+            token_account: self.token_account,
+            mint: account.base.mint,
+            owner: account.base.owner,
+            public,
+            pending,
+            available,
+            total,
+            pending_balance_credit_counter: ct_extension.pending_balance_credit_counter.into(),
+            approved: ct_extension.approved.into(),
+            allow_confidential_credits: ct_extension.allow_confidential_credits.into(),
+        })
+    }
+}
+
+impl KeyProvider for WatchOnlyAccount {
+    fn elgamal_keypair(&self, _token_account: &Pubkey) -> CtResult<ElGamalKeypair> {
+        Err(format!("{} is watch-only and cannot build state-changing instructions", self.token_account).into())
+    }
+
+    fn aes_key(&self, _token_account: &Pubkey) -> CtResult<AeKey> {
+        Err(format!("{} is watch-only and cannot build state-changing instructions", self.token_account).into())
+    }
+}
+
+/// Decrypt the balances of many watched accounts
+///
+/// Accounts are fetched in batches of 100 with `getMultipleAccounts`.
+/// Results are returned in input order; an account that is missing or fails
+/// to decrypt gets an error without affecting the others.
+pub fn get_balances(
+    client: &RpcClient,
+    accounts: &[WatchOnlyAccount],
+) -> CtResult<Vec<CtResult<BalanceBreakdown>>> {
+    let mut results = Vec::with_capacity(accounts.len());
+    for chunk in accounts.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let addresses: Vec<Pubkey> = chunk.iter().map(|account| account.token_account).collect();
+        let fetched = client.get_multiple_accounts(&addresses)?;
+        for (account, account_data) in chunk.iter().zip(fetched) {
+            results.push(match account_data {
+                Some(account_data) => account.decrypt(&account_data),
+                None => Err(format!("Token account {} not found", account.token_account).into()),
+            });
+        }
+    }
+    Ok(results)
+}
@@ -1,10 +1,22 @@
 //! Withdraw tokens from confidential balance to public balance
 
+use crate::authority::Authority;
+use crate::decryption;
+use crate::events::{self, Operation};
+use crate::key_provider::{KeyProvider, SignerKeyProvider};
+use crate::memo::with_memo;
+use crate::proof_accounts::{close_context_state_accounts, create_proof_account};
+use crate::receipt::{fetch_receipt, SentTransactions, TransactionRole};
+use crate::proof_placement::{
+    proof_location, select_placements, send_versioned, transaction_size, with_record_fallback,
+    WithdrawOptions, WithdrawPlacements,
+};
 use crate::types::*;
+use crate::verification::verify_withdraw_proofs;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
-    signature::Signer,
-    transaction::Transaction,
+    instruction::Instruction,
+    signature::{Keypair, Signer},
 };
 use spl_associated_token_account::get_associated_token_address_with_program_id;
 use spl_token_2022::{
@@ -16,10 +28,6 @@ use spl_token_2022::{
         },
         BaseStateWithExtensions, StateWithExtensions,
     },
-    solana_zk_sdk::encryption::{
-        auth_encryption::AeKey,
-        elgamal::ElGamalKeypair,
-    },
     state::Account as TokenAccount,
 };
 
@@ -29,7 +37,9 @@ use spl_token_2022::{
 /// - Equality proof: proves withdrawal amount matches ciphertext
 /// - Range proof: proves remaining balance is non-negative
 ///
-/// These proofs can fit inline in the transaction for small amounts.
+/// Both are verified locally before anything is sent. Their placements are
+/// chosen automatically: as many proofs as fit go inline, the others in
+/// proof context state accounts (see `withdraw_with_options`).
 pub async fn withdraw_from_confidential(
     client: &RpcClient,
     payer: &dyn Signer,
@@ -37,22 +47,95 @@ pub async fn withdraw_from_confidential(
     mint: &solana_sdk::pubkey::Pubkey,
     amount: u64,
     decimals: u8,
-) -> SigResult {
+) -> ReceiptResult {
+    withdraw_with_keys(client, payer, authority, mint, amount, decimals, &SignerKeyProvider::new(authority)).await
+}
+
+/// Withdraw from confidential balance with explicit encryption keys
+///
+/// Same as `withdraw_from_confidential`, for accounts whose keys aren't
+/// derived from the authority.
+pub async fn withdraw_with_keys(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    decimals: u8,
+    keys: &dyn KeyProvider,
+) -> ReceiptResult {
+    withdraw_with_options(client, payer, authority, mint, amount, decimals, keys, &WithdrawOptions::default()).await
+}
+
+/// Withdraw with explicit proof placements
+///
+/// Same as `withdraw_with_keys`. `options.placements` forces where each
+/// proof goes (the withdrawal fails if it doesn't fit); by default as many
+/// proofs as fit are inlined, the others verified into context state
+/// accounts, through a record account when their verification doesn't fit
+/// in a transaction alone. `options.memo` is sent in an SPL Memo
+/// instruction right before the withdrawal. `payer` pays for the proof
+/// accounts, owns them and gets the rent back.
+///
+/// Returns the receipt of all transactions (proof accounts + withdraw +
+/// cleanup) with the remaining balances
+#[allow(clippy::too_many_arguments)]
+pub async fn withdraw_with_options(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &dyn Signer,
+    mint: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    decimals: u8,
+    keys: &dyn KeyProvider,
+    options: &WithdrawOptions,
+) -> ReceiptResult {
+    withdraw_with_authority(client, payer, &Authority::Signer(authority), mint, amount, decimals, keys, options).await
+}
+
+/// Withdraw for a single-signer or multisig authority
+///
+/// Same as `withdraw_with_options`; a multisig's signers are checked
+/// against the multisig account before any proof is generated.
+#[allow(clippy::too_many_arguments)]
+pub async fn withdraw_with_authority(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    decimals: u8,
+    keys: &dyn KeyProvider,
+    options: &WithdrawOptions,
+) -> ReceiptResult {
+    let sent = send_withdraw(client, payer, authority, mint, amount, decimals, keys, options).await?;
+    let token_account = get_associated_token_address_with_program_id(&authority.pubkey(), mint, &spl_token_2022::id());
+    Ok(fetch_receipt(client, Operation::Withdraw, Some(&token_account), &sent, Some(keys)))
+}
+
+/// Withdraw, returning every transaction sent with its role
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(authority = %authority.pubkey(), %mint, amount))]
+async fn send_withdraw(
+    client: &RpcClient,
+    payer: &dyn Signer,
+    authority: &Authority<'_>,
+    mint: &solana_sdk::pubkey::Pubkey,
+    amount: u64,
+    decimals: u8,
+    keys: &dyn KeyProvider,
+    options: &WithdrawOptions,
+) -> CtResult<SentTransactions> {
+    authority.check(client)?;
     let token_account = get_associated_token_address_with_program_id(
         &authority.pubkey(),
         mint,
         &spl_token_2022::id(),
     );
 
-    // Derive encryption keys
-    let elgamal_keypair = ElGamalKeypair::new_from_signer(
-        authority,
-        &token_account.to_bytes(),
-    )?;
-    let aes_key = AeKey::new_from_signer(
-        authority,
-        &token_account.to_bytes(),
-    )?;
+    // Fetch encryption keys from the provider
+    let elgamal_keypair = keys.elgamal_keypair(&token_account)?;
+    let aes_key = keys.aes_key(&token_account)?;
 
     // Fetch account state
     let account_data = client.get_account(&token_account)?;
@@ -67,7 +150,7 @@ pub async fn withdraw_from_confidential(
         withdraw_info.available_balance.try_into()
             .map_err(|_| "Failed to convert available_balance")?;
 
-    let current_available = available_balance.decrypt_u32(elgamal_keypair.secret())
+    let current_available = decryption::engine().decrypt_u32(elgamal_keypair.secret(), &available_balance)
         .ok_or("Failed to decrypt available balance")?;
 
     if current_available < amount {
@@ -77,44 +160,113 @@ pub async fn withdraw_from_confidential(
         ).into());
     }
 
-    // Generate withdrawal proofs
-    let proof_data = withdraw_info.generate_proof_data(
-        amount,
-        &elgamal_keypair,
-        &aes_key,
-    )?;
+    // Generate withdrawal proofs and check them locally, off the async runtime
+    let proof_data = {
+        let (elgamal_keypair, aes_key) = (elgamal_keypair.clone(), aes_key.clone());
+        tokio::task::spawn_blocking(move || -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+            let proof_data = withdraw_info.generate_proof_data(amount, &elgamal_keypair, &aes_key)?;
+            verify_withdraw_proofs(&proof_data)?;
+            Ok(proof_data)
+        })
+        .await?
+        .map_err(|e| e as Box<dyn std::error::Error>)?
+    };
 
     // Calculate new decryptable available balance after withdrawal
     let new_available = current_available - amount;
     let new_decryptable_balance = aes_key.encrypt(new_available);
 
-    // Build withdraw instruction (returns Vec<Instruction>)
-    use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
-    let withdraw_instructions = withdraw(
-        &spl_token_2022::id(),
-        &token_account,
-        mint,
-        amount,
-        decimals,
-        &new_decryptable_balance.into(),
-        &authority.pubkey(),
-        &[&authority.pubkey()],
-        ProofLocation::InstructionOffset(1.try_into().unwrap(), &proof_data.equality_proof_data),
-        ProofLocation::InstructionOffset(2.try_into().unwrap(), &proof_data.range_proof_data),
-    )?;
-
-    // Send transaction
-    let recent_blockhash = client.get_latest_blockhash()?;
-    let transaction = Transaction::new_signed_with_payer(
-        &withdraw_instructions,
-        Some(&payer.pubkey()),
-        &[payer, authority],
-        recent_blockhash,
-    );
+    // Build withdraw instruction (and inline proof instructions) for a placement
+    let multisig_signers = authority.multisig_signers();
+    let multisig_signers: Vec<_> = multisig_signers.iter().collect();
+    let equality_proof_account = Keypair::new();
+    let range_proof_account = Keypair::new();
+    let withdraw_instructions = |placements: WithdrawPlacements| -> CtResult<Vec<Instruction>> {
+        let mut next_offset = 1;
+        Ok(with_memo(options.memo.as_deref(), withdraw(
+            &spl_token_2022::id(),
+            &token_account,
+            mint,
+            amount,
+            decimals,
+            &new_decryptable_balance.into(),
+            &authority.pubkey(),
+            &multisig_signers,
+            proof_location(placements.equality, &mut next_offset, &equality_proof_account.pubkey(), &proof_data.equality_proof_data)?,
+            proof_location(placements.range, &mut next_offset, &range_proof_account.pubkey(), &proof_data.range_proof_data)?,
+        )?))
+    };
+
+    // Inline as many proofs as fit; proofs whose verification alone is too large go through a record
+    let placements = match options.placements {
+        Some(placements) => placements,
+        None => {
+            let placements: WithdrawPlacements = select_placements(|placements| {
+                transaction_size(&payer.pubkey(), &withdraw_instructions(placements)?, &[])
+            })?;
+            let payer = payer.pubkey();
+            WithdrawPlacements {
+                equality: with_record_fallback(placements.equality, &payer, &payer, &proof_data.equality_proof_data)?,
+                range: with_record_fallback(placements.range, &payer, &payer, &proof_data.range_proof_data)?,
+            }
+        }
+    };
+    events::progress(Operation::Withdraw, format!("Proof placement: {}", placements));
 
-    let signature = client.send_and_confirm_transaction(&transaction)?;
-    println!("✅ Withdrew {} tokens to public balance: {}", amount, signature);
-    println!("   Remaining confidential: {}", current_available - amount);
+    let mut sent = SentTransactions::default();
+    let mut context_accounts = Vec::new();
+
+    // Verify the other proofs into context state accounts and withdraw; the
+    // proof accounts are closed below even if this fails partway
+    let withdrawn = (|| -> SigResult {
+        create_proof_account(
+            client,
+            Operation::Withdraw,
+            payer,
+            placements.equality,
+            (TransactionRole::EqualityProofCreate, TransactionRole::EqualityProofClose),
+            &equality_proof_account,
+            &proof_data.equality_proof_data,
+            &mut sent,
+            &mut context_accounts,
+        )?;
+        create_proof_account(
+            client,
+            Operation::Withdraw,
+            payer,
+            placements.range,
+            (TransactionRole::RangeProofCreate, TransactionRole::RangeProofClose),
+            &range_proof_account,
+            &proof_data.range_proof_data,
+            &mut sent,
+            &mut context_accounts,
+        )?;
+        send_versioned(
+            client,
+            Operation::Withdraw,
+            payer,
+            &authority.signers(),
+            &withdraw_instructions(placements)?,
+            &[],
+        )
+    })();
+    if let Ok(signature) = withdrawn {
+        sent.push(TransactionRole::Withdraw, signature);
+    }
+
+    // Close proof accounts to reclaim rent
+    close_context_state_accounts(client, Operation::Withdraw, payer, &context_accounts, &mut sent);
+    let signature = withdrawn?;
+
+    events::completed(
+        Operation::Withdraw,
+        format!(
+            "Withdrew {} tokens to public balance: {} (remaining confidential: {})",
+            amount,
+            signature,
+            current_available - amount
+        ),
+    );
 
-    Ok(signature)
+    Ok(sent)
 }
@@ -1,9 +1,16 @@
 //! Common test utilities and helpers
 
+use conf_balances_examples::{
+    apply_pending, configure, deposit,
+    events::{self, Operation},
+    key_provider::{KeyProvider, SignerKeyProvider},
+    proof_accounts, signer,
+};
 use solana_client::rpc_client::RpcClient;
 use solana_commitment_config::CommitmentConfig;
 use solana_sdk::{
     native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
     signature::{Keypair, Signer},
     transaction::Transaction,
 };
@@ -14,13 +21,28 @@ use spl_associated_token_account::{
 };
 use spl_token_2022::{
     extension::{
-        confidential_transfer::instruction::initialize_mint,
-        ExtensionType,
+        confidential_mint_burn::instruction::initialize_mint as initialize_mint_burn,
+        confidential_transfer::{
+            account_info::TransferAccountInfo,
+            instruction::{initialize_mint, transfer_with_fee},
+            ConfidentialTransferAccount,
+        },
+        confidential_transfer_fee::instruction::initialize_confidential_transfer_fee_config,
+        memo_transfer::instruction::enable_required_transfer_memos,
+        transfer_fee::instruction::initialize_transfer_fee_config,
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
     },
     instruction::initialize_mint as initialize_mint_base,
-    solana_zk_sdk::encryption::elgamal::ElGamalKeypair,
-    state::Mint,
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalKeypair, ElGamalPubkey},
+        },
+        zk_elgamal_proof_program::proof_data::ZkProofData,
+    },
+    state::{Account as TokenAccount, Mint},
 };
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
 use std::env;
 
 /// Test environment configuration
@@ -42,6 +64,8 @@ impl TestEnv {
 
         let is_local = rpc_url.contains("127.0.0.1") || rpc_url.contains("localhost");
 
+        events::print_events();
+
         println!("🔗 Connecting to: {}", rpc_url);
         println!("📍 Environment: {}", if is_local { "Local" } else { "Custom cluster" });
 
@@ -51,22 +75,10 @@ impl TestEnv {
         );
 
         // Load payer from environment or generate new one
-        let payer = if let Ok(keypair_json) = env::var("PAYER_KEYPAIR") {
-            // Parse the JSON array into a byte array
-            let bytes: Vec<u8> = serde_json::from_str(&keypair_json)
-                .expect("Failed to parse PAYER_KEYPAIR as JSON array");
-
-            // The keypair file is an array of 64 bytes: first 32 are secret key, last 32 are public key
-            if bytes.len() != 64 {
-                panic!("Invalid keypair: expected 64 bytes, got {}", bytes.len());
-            }
-
-            // Extract the first 32 bytes (secret key)
-            let mut secret_key = [0u8; 32];
-            secret_key.copy_from_slice(&bytes[0..32]);
-
-            // Create keypair from secret key
-            Keypair::new_from_array(secret_key)
+        // Load payer from environment (any signer source: path, JSON bytes,
+        // base58, seed phrase) or generate new one
+        let payer = if let Ok(source) = env::var("PAYER_KEYPAIR") {
+            signer::load_keypair(&source).expect("Failed to load PAYER_KEYPAIR")
         } else {
             // Generate new keypair for testing
             Keypair::new()
@@ -151,6 +163,16 @@ pub fn create_confidential_mint(
     authority: &Keypair,
     decimals: u8,
 ) -> Result<Keypair, Box<dyn std::error::Error>> {
+    create_confidential_mint_with_auditor(env, authority, decimals)
+        .map(|(mint, _auditor_elgamal)| mint)
+}
+
+/// Create a confidential transfer-enabled mint, returning the auditor ElGamal keypair
+pub fn create_confidential_mint_with_auditor(
+    env: &TestEnv,
+    authority: &Keypair,
+    decimals: u8,
+) -> Result<(Keypair, ElGamalKeypair), Box<dyn std::error::Error>> {
     let mint = Keypair::new();
 
     println!("🏭 Creating confidential mint: {}", mint.pubkey());
@@ -212,7 +234,160 @@ pub fn create_confidential_mint(
     let signature = env.client.send_and_confirm_transaction(&transaction)?;
     println!("✅ Mint created: {}", signature);
 
-    Ok(mint)
+    Ok((mint, auditor_elgamal))
+}
+
+/// Create a confidential transfer-enabled mint with confidential transfer fees
+///
+/// `authority` is both the mint authority and the withdraw-withheld authority.
+/// Returns the mint and the withdraw-withheld authority ElGamal keypair.
+pub fn create_confidential_fee_mint(
+    env: &TestEnv,
+    authority: &Keypair,
+    decimals: u8,
+    transfer_fee_basis_points: u16,
+    maximum_fee: u64,
+) -> Result<(Keypair, ElGamalKeypair), Box<dyn std::error::Error>> {
+    let mint = Keypair::new();
+
+    println!("🏭 Creating confidential fee mint: {}", mint.pubkey());
+
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&[
+        ExtensionType::TransferFeeConfig,
+        ExtensionType::ConfidentialTransferMint,
+        ExtensionType::ConfidentialTransferFeeConfig,
+    ])?;
+    let rent = env.client.get_minimum_balance_for_rent_exemption(space)?;
+
+    let withdraw_withheld_elgamal = ElGamalKeypair::new_rand();
+    let withdraw_withheld_pubkey_pod: spl_token_2022::solana_zk_sdk::encryption::pod::elgamal::PodElGamalPubkey =
+        (*withdraw_withheld_elgamal.pubkey()).into();
+
+    let create_account_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &mint.pubkey(),
+        rent,
+        space as u64,
+        &spl_token_2022::id(),
+    );
+
+    let init_fee_ix = initialize_transfer_fee_config(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        Some(&authority.pubkey()),
+        Some(&authority.pubkey()),
+        transfer_fee_basis_points,
+        maximum_fee,
+    )?;
+
+    let init_ct_ix = initialize_mint(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        None,
+        true,
+        None,
+    )?;
+
+    let init_ct_fee_ix = initialize_confidential_transfer_fee_config(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        Some(authority.pubkey()),
+        &withdraw_withheld_pubkey_pod,
+    )?;
+
+    let init_mint_ix = initialize_mint_base(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        &authority.pubkey(),
+        None,
+        decimals,
+    )?;
+
+    let recent_blockhash = env.client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[create_account_ix, init_fee_ix, init_ct_ix, init_ct_fee_ix, init_mint_ix],
+        Some(&env.payer.pubkey()),
+        &[&env.payer, &mint],
+        recent_blockhash,
+    );
+
+    let signature = env.client.send_and_confirm_transaction(&transaction)?;
+    println!("✅ Fee mint created: {}", signature);
+
+    Ok((mint, withdraw_withheld_elgamal))
+}
+
+/// Create a mint with confidential mint/burn (encrypted supply) enabled
+///
+/// Returns the mint and the supply ElGamal keypair and AES key.
+pub fn create_confidential_mint_burn_mint(
+    env: &TestEnv,
+    authority: &Keypair,
+    decimals: u8,
+) -> Result<(Keypair, ElGamalKeypair, AeKey), Box<dyn std::error::Error>> {
+    let mint = Keypair::new();
+
+    println!("🏭 Creating confidential mint/burn mint: {}", mint.pubkey());
+
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&[
+        ExtensionType::ConfidentialTransferMint,
+        ExtensionType::ConfidentialMintBurn,
+    ])?;
+    let rent = env.client.get_minimum_balance_for_rent_exemption(space)?;
+
+    let auditor_elgamal = ElGamalKeypair::new_rand();
+    let auditor_pubkey_pod: spl_token_2022::solana_zk_sdk::encryption::pod::elgamal::PodElGamalPubkey =
+        (*auditor_elgamal.pubkey()).into();
+
+    // Supply starts at zero, encrypted for the mint authority
+    let supply_elgamal = ElGamalKeypair::new_rand();
+    let supply_aes = AeKey::new_rand();
+    let supply_pubkey_pod: spl_token_2022::solana_zk_sdk::encryption::pod::elgamal::PodElGamalPubkey =
+        (*supply_elgamal.pubkey()).into();
+
+    let create_account_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &mint.pubkey(),
+        rent,
+        space as u64,
+        &spl_token_2022::id(),
+    );
+
+    let init_ct_ix = initialize_mint(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        None,
+        true,
+        Some(auditor_pubkey_pod),
+    )?;
+
+    let init_mint_burn_ix = initialize_mint_burn(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        &supply_pubkey_pod,
+        &supply_aes.encrypt(0).into(),
+    )?;
+
+    let init_mint_ix = initialize_mint_base(
+        &spl_token_2022::id(),
+        &mint.pubkey(),
+        &authority.pubkey(),
+        None,
+        decimals,
+    )?;
+
+    let recent_blockhash = env.client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[create_account_ix, init_ct_ix, init_mint_burn_ix, init_mint_ix],
+        Some(&env.payer.pubkey()),
+        &[&env.payer, &mint],
+        recent_blockhash,
+    );
+
+    let signature = env.client.send_and_confirm_transaction(&transaction)?;
+    println!("✅ Mint/burn mint created: {}", signature);
+
+    Ok((mint, supply_elgamal, supply_aes))
 }
 
 /// Create an associated token account
@@ -282,3 +457,244 @@ pub fn mint_tokens(
 
     Ok(())
 }
+
+/// Create an M-of-N SPL Token-2022 multisig account
+pub fn create_multisig(
+    env: &TestEnv,
+    m: u8,
+    signers: &[&solana_sdk::pubkey::Pubkey],
+) -> Result<solana_sdk::pubkey::Pubkey, Box<dyn std::error::Error>> {
+    use solana_sdk::program_pack::Pack;
+
+    let multisig = Keypair::new();
+    println!("🔐 Creating {}-of-{} multisig: {}", m, signers.len(), multisig.pubkey());
+
+    let space = spl_token_2022::state::Multisig::LEN;
+    let rent = env.client.get_minimum_balance_for_rent_exemption(space)?;
+    let create_account_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &multisig.pubkey(),
+        rent,
+        space as u64,
+        &spl_token_2022::id(),
+    );
+    let initialize_ix = spl_token_2022::instruction::initialize_multisig(
+        &spl_token_2022::id(),
+        &multisig.pubkey(),
+        signers,
+        m,
+    )?;
+
+    let recent_blockhash = env.client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[create_account_ix, initialize_ix],
+        Some(&env.payer.pubkey()),
+        &[&env.payer, &multisig],
+        recent_blockhash,
+    );
+
+    let signature = env.client.send_and_confirm_transaction(&transaction)?;
+    println!("✅ Multisig created: {}", signature);
+
+    Ok(multisig.pubkey())
+}
+
+/// Verify a proof into a new context state account owned by the payer
+///
+/// Goes through a record account when the verification doesn't fit in a
+/// transaction alone.
+fn create_proof_context_account<T, U>(
+    env: &TestEnv,
+    proof_data: &T,
+) -> Result<Keypair, Box<dyn std::error::Error>>
+where
+    T: bytemuck::Pod + ZkProofData<U>,
+    U: bytemuck::Pod,
+{
+    let context_account = Keypair::new();
+    if proof_accounts::context_state_fits(&env.payer.pubkey(), &env.payer.pubkey(), proof_data)? {
+        proof_accounts::create_context_state_account(
+            &env.client,
+            Operation::Transfer,
+            &env.payer,
+            &context_account,
+            &env.payer.pubkey(),
+            proof_data,
+        )?;
+    } else {
+        proof_accounts::create_context_state_from_record(
+            &env.client,
+            Operation::Transfer,
+            &env.payer,
+            &env.payer,
+            &Keypair::new(),
+            &context_account,
+            proof_data,
+        )?;
+    }
+    Ok(context_account)
+}
+
+/// Confidentially transfer on a mint with transfer fees
+///
+/// The library's transfers don't support fee mints, so this builds
+/// `TransferWithFee` directly, with all five proofs in context state
+/// accounts closed afterwards. The fee is withheld in the destination
+/// account, encrypted under `withdraw_withheld_elgamal_pubkey`.
+#[allow(clippy::too_many_arguments)]
+pub fn confidential_transfer_with_fee(
+    env: &TestEnv,
+    sender: &Keypair,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    transfer_fee_basis_points: u16,
+    maximum_fee: u64,
+    withdraw_withheld_elgamal_pubkey: &ElGamalPubkey,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔐 Transferring {} tokens with fee to {}", amount, recipient);
+
+    let source = get_associated_token_address_with_program_id(&sender.pubkey(), mint, &spl_token_2022::id());
+    let destination = get_associated_token_address_with_program_id(recipient, mint, &spl_token_2022::id());
+    let keys = SignerKeyProvider::new(sender);
+    let elgamal_keypair = keys.elgamal_keypair(&source)?;
+    let aes_key = keys.aes_key(&source)?;
+
+    let source_data = env.client.get_account(&source)?;
+    let source_account = StateWithExtensions::<TokenAccount>::unpack(&source_data.data)?;
+    let account_info = TransferAccountInfo::new(source_account.get_extension::<ConfidentialTransferAccount>()?);
+
+    let destination_data = env.client.get_account(&destination)?;
+    let destination_account = StateWithExtensions::<TokenAccount>::unpack(&destination_data.data)?;
+    let destination_elgamal_pubkey: ElGamalPubkey = destination_account
+        .get_extension::<ConfidentialTransferAccount>()?
+        .elgamal_pubkey
+        .try_into()
+        .map_err(|_| "Failed to convert destination ElGamal pubkey")?;
+
+    let proof_data = account_info.generate_split_transfer_with_fee_proof_data(
+        amount,
+        &elgamal_keypair,
+        &aes_key,
+        &destination_elgamal_pubkey,
+        None,
+        withdraw_withheld_elgamal_pubkey,
+        transfer_fee_basis_points,
+        maximum_fee,
+    )?;
+    let new_decryptable_balance = account_info.new_decryptable_available_balance(amount, &aes_key)?;
+
+    let equality = create_proof_context_account(env, &proof_data.equality_proof_data)?;
+    let transfer_amount_validity = create_proof_context_account(
+        env,
+        &proof_data.transfer_amount_ciphertext_validity_proof_data_with_ciphertext.proof_data,
+    )?;
+    let percentage_with_cap = create_proof_context_account(env, &proof_data.percentage_with_cap_proof_data)?;
+    let fee_validity = create_proof_context_account(env, &proof_data.fee_ciphertext_validity_proof_data)?;
+    let range = create_proof_context_account(env, &proof_data.range_proof_data)?;
+
+    let instructions = transfer_with_fee(
+        &spl_token_2022::id(),
+        &source,
+        mint,
+        &destination,
+        &new_decryptable_balance.into(),
+        &proof_data.transfer_amount_ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+        &proof_data.transfer_amount_ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+        &sender.pubkey(),
+        &[],
+        ProofLocation::ContextStateAccount(&equality.pubkey()),
+        ProofLocation::ContextStateAccount(&transfer_amount_validity.pubkey()),
+        ProofLocation::ContextStateAccount(&percentage_with_cap.pubkey()),
+        ProofLocation::ContextStateAccount(&fee_validity.pubkey()),
+        ProofLocation::ContextStateAccount(&range.pubkey()),
+    )?;
+
+    let recent_blockhash = env.client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&env.payer.pubkey()),
+        &[&env.payer, sender],
+        recent_blockhash,
+    );
+    let signature = env.client.send_and_confirm_transaction(&transaction)?;
+    println!("✅ Transfer with fee confirmed: {}", signature);
+
+    for context_account in [equality, transfer_amount_validity, percentage_with_cap, fee_validity, range] {
+        proof_accounts::close_context_state_account(
+            &env.client,
+            Operation::Transfer,
+            &env.payer,
+            &env.payer,
+            &context_account.pubkey(),
+            &env.payer.pubkey(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Set up a funded sender and a configured recipient on a new confidential mint
+///
+/// The sender has `available` in its available confidential balance; the
+/// recipient's account is configured but empty. Returns the mint, sender and
+/// recipient.
+pub async fn create_transfer_parties(
+    env: &TestEnv,
+    available: u64,
+) -> Result<(Pubkey, Keypair, Keypair), Box<dyn std::error::Error>> {
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)?;
+    let mint = create_confidential_mint(env, &mint_authority, 9)?.pubkey();
+
+    let sender = Keypair::new();
+    let recipient = Keypair::new();
+    for owner in [&sender, &recipient] {
+        env.airdrop_if_needed(&owner.pubkey(), 100_000_000)?;
+        create_token_account(env, &mint, &owner.pubkey())?;
+        configure::configure_account_for_confidential_transfers(&env.client, &env.payer, owner, &mint).await?;
+    }
+
+    let sender_token_account = get_associated_token_address_with_program_id(&sender.pubkey(), &mint, &spl_token_2022::id());
+    mint_tokens(env, &mint, &sender_token_account, &mint_authority, available)?;
+    deposit::deposit_to_confidential(&env.client, &env.payer, &sender, &mint, available, 9).await?;
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &sender, &mint).await?;
+
+    Ok((mint, sender, recipient))
+}
+
+/// Make a token account require a memo before every incoming transfer
+///
+/// Reallocates the account for the `MemoTransfer` extension first, paid by
+/// the test payer.
+pub fn require_transfer_memos(
+    env: &TestEnv,
+    token_account: &Pubkey,
+    owner: &Keypair,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("📝 Requiring transfer memos on {}", token_account);
+
+    let instructions = [
+        spl_token_2022::instruction::reallocate(
+            &spl_token_2022::id(),
+            token_account,
+            &env.payer.pubkey(),
+            &owner.pubkey(),
+            &[],
+            &[ExtensionType::MemoTransfer],
+        )?,
+        enable_required_transfer_memos(&spl_token_2022::id(), token_account, &owner.pubkey(), &[])?,
+    ];
+
+    let recent_blockhash = env.client.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&env.payer.pubkey()),
+        &[&env.payer, owner],
+        recent_blockhash,
+    );
+    let signature = env.client.send_and_confirm_transaction(&transaction)?;
+    println!("✅ Transfer memos required: {}", signature);
+
+    Ok(())
+}
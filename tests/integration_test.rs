@@ -16,7 +16,20 @@ mod common;
 
 use common::*;
 use conf_balances_examples::*;
-use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::{
+    message::{Message, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::VersionedTransaction,
+};
+use spl_token_2022::{
+    extension::confidential_transfer::instruction::{configure_account, inner_transfer},
+    solana_zk_sdk::{
+        encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair, pod::elgamal::PodElGamalPubkey},
+        zk_elgamal_proof_program::proof_data::PubkeyValidityProofData,
+    },
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
 
 #[tokio::test(flavor = "multi_thread")]
 async fn test_configure_account() {
@@ -179,9 +192,8 @@ async fn test_full_flow_deposit_apply_withdraw() {
     ).await.expect("Failed to apply pending balance");
 
     // Step 3: Withdraw part of the confidential balance
-    // Note: Withdraw with inline proofs has transaction size limits.
-    // For larger amounts, use proof context state accounts (see rust-deps.md)
-    let withdraw_amount = 100_000_000u64; // 0.1 tokens (smaller amount to fit in transaction)
+    // The proofs don't fit inline together, so the range proof goes to a context state account
+    let withdraw_amount = 100_000_000u64; // 0.1 tokens
     let withdraw_result = withdraw::withdraw_from_confidential(
         &env.client,
         &env.payer,
@@ -190,17 +202,37 @@ async fn test_full_flow_deposit_apply_withdraw() {
         withdraw_amount,
         9,
     ).await;
+    assert!(withdraw_result.is_ok(), "Failed to withdraw: {:?}", withdraw_result.as_ref().err());
+
+    // Step 4: Withdraw again with both proofs verified from record accounts
+    let options = proof_placement::WithdrawOptions {
+        placements: Some(proof_placement::WithdrawPlacements {
+            equality: proof_placement::ProofPlacement::Record,
+            range: proof_placement::ProofPlacement::Record,
+        }),
+        memo: None,
+    };
+    let receipt = withdraw::withdraw_with_options(
+        &env.client,
+        &env.payer,
+        &user,
+        &mint.pubkey(),
+        withdraw_amount,
+        9,
+        &key_provider::SignerKeyProvider::new(&user),
+        &options,
+    ).await.expect("Failed to withdraw with record proofs");
+    // Per proof: record writes, verification, record close and context close, plus the withdraw
+    assert!(receipt.transactions.len() >= 9);
+    // Records and context states are all closed again
+    assert!(receipt.rent_spent > 0);
+    assert_eq!(receipt.rent_spent, receipt.rent_reclaimed);
+    let balances = watch::WatchOnlyAccount::from_provider(
+        token_account,
+        &key_provider::SignerKeyProvider::new(&user),
+    ).unwrap().balances(&env.client).unwrap();
+    assert_eq!(balances.available, deposit_amount - 2 * withdraw_amount);
 
-    if let Err(e) = &withdraw_result {
-        // Transaction size errors are expected with inline proofs
-        if e.to_string().contains("too large") {
-            println!("⚠️  Withdraw failed due to transaction size (expected with inline proofs)");
-            println!("✅ test_full_flow_deposit_apply_withdraw PASSED (withdraw shows expected size limitation)");
-            return;
-        }
-    }
-
-    assert!(withdraw_result.is_ok(), "Failed to withdraw: {:?}", withdraw_result.err());
     println!("✅ test_full_flow_deposit_apply_withdraw PASSED");
 
     // TODO: Add negative test cases:
@@ -259,14 +291,14 @@ async fn test_multiple_deposits_and_applies() {
             &mint.pubkey(),
             deposit_amount,
             9,
-        ).await.expect(&format!("Failed to deposit iteration {}", i));
+        ).await.unwrap_or_else(|e| panic!("Failed to deposit iteration {}: {}", i, e));
 
         apply_pending::apply_pending_balance(
             &env.client,
             &env.payer,
             &user,
             &mint.pubkey(),
-        ).await.expect(&format!("Failed to apply pending iteration {}", i));
+        ).await.unwrap_or_else(|e| panic!("Failed to apply pending iteration {}: {}", i, e));
 
         println!("✅ Completed deposit/apply cycle {}", i);
     }
@@ -369,3 +401,1930 @@ async fn test_confidential_transfer_between_accounts() {
     assert!(apply_result.is_ok(), "Failed to apply recipient pending balance: {:?}", apply_result.err());
     println!("✅ test_confidential_transfer_between_accounts PASSED");
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transfer_recipient_resolution() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let (mint, sender, recipient) = create_transfer_parties(&env, 100_000_000)
+        .await.expect("Failed to set up sender and recipient");
+
+    // An owner without a token account is rejected before any proof is generated
+    let nobody = Pubkey::new_unique();
+    let error = transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint, &nobody, 1)
+        .await.unwrap_err();
+    let invalid = error.downcast_ref::<recipient::InvalidRecipient>().expect("Expected a typed recipient error");
+    assert_eq!(invalid.reason, recipient::RecipientReason::AccountNotFound);
+
+    // The destination can also be given as the token account itself
+    let recipient_token_account = recipient::validate_recipient(&env.client, &mint, &recipient.pubkey())
+        .expect("Recipient rejected").token_account;
+    assert!(matches!(
+        recipient::resolve_recipient(&env.client, &mint, &recipient_token_account).unwrap(),
+        recipient::RecipientAddress::TokenAccount { .. }
+    ));
+    transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint, &recipient_token_account, 1_000_000)
+        .await.expect("Transfer to explicit token account failed");
+
+    // A missing account can be created, but the new owner must configure it before receiving
+    let newcomer = Pubkey::new_unique();
+    let error = recipient::ensure_recipient_account(&env.client, &env.payer, &mint, &newcomer)
+        .await.unwrap_err();
+    let invalid = error.downcast_ref::<recipient::InvalidRecipient>().expect("Expected a typed recipient error");
+    assert_eq!(invalid.reason, recipient::RecipientReason::NotConfigured);
+    assert!(env.client.get_account(&invalid.token_account).is_ok());
+
+    println!("✅ test_transfer_recipient_resolution PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transfer_proof_placements() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let (mint, sender, recipient) = create_transfer_parties(&env, 100_000_000)
+        .await.expect("Failed to set up sender and recipient");
+    let sender_keys = key_provider::SignerKeyProvider::new(&sender);
+
+    // At least one proof is inlined by default, saving its create and close transactions
+    let receipt = transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint, &recipient.pubkey(), 1_000_000)
+        .await.expect("Transfer failed");
+    assert!(receipt.transactions.len() < 7);
+
+    // Every proof can still be forced into a context state account
+    let options = proof_placement::TransferOptions {
+        placements: Some(proof_placement::TransferPlacements::CONTEXT_STATE),
+        lookup_table: None,
+        memo: None,
+    };
+    let receipt = transfer::transfer_confidential_with_options(
+        &env.client,
+        &env.payer,
+        &sender,
+        &mint,
+        &recipient.pubkey(),
+        1_000_000,
+        &sender_keys,
+        &options,
+    ).await.expect("Context state transfer failed");
+    assert_eq!(receipt.transactions.len(), 8);
+
+    // Or the range proof into a record account first
+    let options = proof_placement::TransferOptions {
+        placements: Some(proof_placement::TransferPlacements {
+            range: proof_placement::ProofPlacement::Record,
+            ..proof_placement::TransferPlacements::CONTEXT_STATE
+        }),
+        lookup_table: None,
+        memo: None,
+    };
+    let receipt = transfer::transfer_confidential_with_options(
+        &env.client,
+        &env.payer,
+        &sender,
+        &mint,
+        &recipient.pubkey(),
+        1_000_000,
+        &sender_keys,
+        &options,
+    ).await.expect("Record transfer failed");
+    assert!(receipt.transactions.len() > 8);
+
+    println!("✅ test_transfer_proof_placements PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transfer_receipt() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let (mint, sender, recipient) = create_transfer_parties(&env, 100_000_000)
+        .await.expect("Failed to set up sender and recipient");
+    let sender_keys = key_provider::SignerKeyProvider::new(&sender);
+    let sender_token_account = spl_associated_token_account::get_associated_token_address_with_program_id(
+        &sender.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+
+    // Receipts give every transaction's role, its costs and the remaining balances
+    let receipt = transfer::transfer_with_authority(
+        &env.client,
+        &env.payer,
+        &authority::Authority::Signer(&sender),
+        &mint,
+        &recipient.pubkey(),
+        1_000_000,
+        &sender_keys,
+        &proof_placement::TransferOptions::default(),
+    ).await.expect("Receipt transfer failed");
+    let roles: Vec<_> = receipt.transactions.iter().map(|transaction| transaction.role).collect();
+    assert_eq!(roles.first(), Some(&receipt::TransactionRole::CiphertextValidityProofCreate));
+    assert!(roles.contains(&receipt::TransactionRole::Transfer));
+    assert!(roles.contains(&receipt::TransactionRole::RangeProofClose));
+    assert!(receipt.fee > 0 && receipt.compute_units > 0);
+    assert!(receipt.rent_spent > 0);
+    assert_eq!(receipt.rent_spent, receipt.rent_reclaimed);
+    assert_eq!(receipt.balances.as_ref().unwrap().available, 99_000_000);
+    let after = watch::WatchOnlyAccount::from_provider(sender_token_account, &sender_keys).unwrap()
+        .balances(&env.client).unwrap();
+    assert_eq!(after.available, 99_000_000);
+    let json = serde_json::to_value(&receipt).unwrap();
+    assert_eq!(json["transactions"][0]["role"], "ciphertext_validity_proof_create");
+
+    println!("✅ test_transfer_receipt PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transfer_memo_required() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let (mint, sender, recipient) = create_transfer_parties(&env, 100_000_000)
+        .await.expect("Failed to set up sender and recipient");
+
+    // Once the recipient requires memos, transfers need one right before them
+    let recipient_token_account = recipient::validate_recipient(&env.client, &mint, &recipient.pubkey())
+        .unwrap().token_account;
+    require_transfer_memos(&env, &recipient_token_account, &recipient).expect("Failed to require memos");
+    assert!(recipient::validate_recipient(&env.client, &mint, &recipient.pubkey()).unwrap().memo_required);
+
+    let error = transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint, &recipient.pubkey(), 1_000_000)
+        .await.unwrap_err();
+    let invalid = error.downcast_ref::<recipient::InvalidRecipient>().expect("Expected a typed recipient error");
+    assert_eq!(invalid.reason, recipient::RecipientReason::MemoRequired);
+
+    let options = proof_placement::TransferOptions { memo: Some("invoice 42".to_string()), ..Default::default() };
+    transfer::transfer_confidential_with_options(
+        &env.client,
+        &env.payer,
+        &sender,
+        &mint,
+        &recipient.pubkey(),
+        1_000_000,
+        &key_provider::SignerKeyProvider::new(&sender),
+        &options,
+    ).await.expect("Transfer with memo failed");
+
+    println!("✅ test_transfer_memo_required PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_batch_payouts() {
+    use payouts::{Payout, PayoutStatus};
+
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let mint = create_confidential_mint(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    // Treasury funds the batch
+    let treasury = Keypair::new();
+    env.airdrop_if_needed(&treasury.pubkey(), 1_000_000_000)
+        .expect("Airdrop to treasury failed");
+    let treasury_token_account = create_token_account(&env, &mint.pubkey(), &treasury.pubkey())
+        .expect("Failed to create treasury token account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &treasury, &mint.pubkey())
+        .await.expect("Failed to configure treasury account");
+    mint_tokens(&env, &mint.pubkey(), &treasury_token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &treasury, &mint.pubkey(), 600_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &treasury, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+
+    let recipients: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+    for recipient in &recipients {
+        env.airdrop_if_needed(&recipient.pubkey(), 100_000_000)
+            .expect("Airdrop to recipient failed");
+        create_token_account(&env, &mint.pubkey(), &recipient.pubkey())
+            .expect("Failed to create recipient token account");
+        configure::configure_account_for_confidential_transfers(&env.client, &env.payer, recipient, &mint.pubkey())
+            .await.expect("Failed to configure recipient account");
+    }
+    let payouts: Vec<Payout> = recipients.iter().zip([100_000_000, 150_000_000, 200_000_000])
+        .map(|(recipient, amount)| Payout { recipient: recipient.pubkey(), amount })
+        .collect();
+
+    // Nothing is sent when a recipient can't receive or the total isn't covered
+    let unconfigured = Payout { recipient: Pubkey::new_unique(), amount: 1 };
+    let result = payouts::batch_transfer_confidential(
+        &env.client, &env.payer, &treasury, &mint.pubkey(), &[payouts[0], unconfigured],
+    ).await;
+    let error = result.unwrap_err();
+    let invalid = error.downcast_ref::<payouts::InvalidPayoutRecipients>().expect("Expected typed payout recipients");
+    assert_eq!(invalid.recipients.len(), 1);
+    assert_eq!(invalid.recipients[0].0, unconfigured.recipient);
+    assert_eq!(invalid.recipients[0].1.reason, recipient::RecipientReason::AccountNotFound);
+    let too_much = Payout { amount: 600_000_000, ..payouts[0] };
+    assert!(payouts::batch_transfer_confidential(
+        &env.client, &env.payer, &treasury, &mint.pubkey(), &[too_much, payouts[1]],
+    ).await.is_err());
+
+    let report = payouts::batch_transfer_confidential(&env.client, &env.payer, &treasury, &mint.pubkey(), &payouts)
+        .await.expect("Batch payout failed");
+    assert!(report.is_complete(), "Payouts failed: {:?}", report.results);
+    assert_eq!(report.total_sent, 450_000_000);
+    assert!(report.results.iter().all(|result| result.status == PayoutStatus::Sent && result.signatures.len() == 7));
+    assert!(report.results.iter().all(|result| {
+        let receipt = result.receipt.as_ref().unwrap();
+        receipt.rent_spent > 0 && receipt.rent_spent == receipt.rent_reclaimed
+    }));
+
+    // Payouts carry no memo, so recipients requiring one are rejected up front
+    let memo_recipient_token_account = spl_associated_token_account::get_associated_token_address_with_program_id(
+        &recipients[0].pubkey(),
+        &mint.pubkey(),
+        &spl_token_2022::id(),
+    );
+    require_transfer_memos(&env, &memo_recipient_token_account, &recipients[0]).expect("Failed to require memos");
+    let error = payouts::batch_transfer_confidential(&env.client, &env.payer, &treasury, &mint.pubkey(), &payouts[..1])
+        .await.unwrap_err();
+    let invalid = error.downcast_ref::<payouts::InvalidPayoutRecipients>().expect("Expected typed payout recipients");
+    assert_eq!(invalid.recipients[0].1.reason, recipient::RecipientReason::MemoRequired);
+
+    // Chained decryptable balances leave the treasury spendable
+    let treasury_balances = watch::WatchOnlyAccount::from_provider(
+        treasury_token_account,
+        &key_provider::SignerKeyProvider::new(&treasury),
+    ).unwrap().balances(&env.client).unwrap();
+    assert_eq!(treasury_balances.available, 150_000_000);
+
+    for (recipient, payout) in recipients.iter().zip(&payouts) {
+        let token_account = spl_associated_token_account::get_associated_token_address_with_program_id(
+            &recipient.pubkey(),
+            &mint.pubkey(),
+            &spl_token_2022::id(),
+        );
+        let balances = watch::WatchOnlyAccount::from_provider(
+            token_account,
+            &key_provider::SignerKeyProvider::new(recipient),
+        ).unwrap().balances(&env.client).unwrap();
+        assert_eq!(balances.pending, payout.amount);
+    }
+
+    println!("✅ test_batch_payouts PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_harvest_and_withdraw_withheld_fees() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    // Mint authority doubles as the withdraw-withheld authority
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    // Create confidential mint with transfer fees (1%, max 1 token)
+    let (fee_basis_points, maximum_fee) = (100, 1_000_000_000);
+    let (mint, withdraw_withheld_elgamal) =
+        create_confidential_fee_mint(&env, &mint_authority, 9, fee_basis_points, maximum_fee)
+            .expect("Failed to create fee mint");
+
+    // Create and configure the fee collector, sender and recipient accounts
+    let sender = Keypair::new();
+    let recipient = Keypair::new();
+    env.airdrop_if_needed(&sender.pubkey(), 100_000_000)
+        .expect("Airdrop to sender failed");
+    env.airdrop_if_needed(&recipient.pubkey(), 100_000_000)
+        .expect("Airdrop to recipient failed");
+
+    let collector_token_account = create_token_account(&env, &mint.pubkey(), &mint_authority.pubkey())
+        .expect("Failed to create collector token account");
+    let sender_token_account = create_token_account(&env, &mint.pubkey(), &sender.pubkey())
+        .expect("Failed to create sender token account");
+    let recipient_token_account = create_token_account(&env, &mint.pubkey(), &recipient.pubkey())
+        .expect("Failed to create recipient token account");
+
+    for owner in [&mint_authority, &sender, &recipient] {
+        configure::configure_account_for_confidential_transfers(
+            &env.client,
+            &env.payer,
+            owner,
+            &mint.pubkey(),
+        ).await.expect("Failed to configure account");
+    }
+
+    // Fund the sender's confidential balance (deposits pay no fee)
+    mint_tokens(&env, &mint.pubkey(), &sender_token_account, &mint_authority, 100_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), 100_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+
+    let collector_balances = || {
+        watch::WatchOnlyAccount::from_provider(
+            collector_token_account,
+            &key_provider::SignerKeyProvider::new(&mint_authority),
+        )
+        .and_then(|account| account.balances(&env.client))
+        .expect("Failed to read collector balances")
+    };
+
+    // 1% of 10 tokens is withheld in the recipient account
+    let (first_amount, first_fee) = (10_000_000, 100_000);
+    confidential_transfer_with_fee(
+        &env, &sender, &mint.pubkey(), &recipient.pubkey(), first_amount,
+        fee_basis_points, maximum_fee, withdraw_withheld_elgamal.pubkey(),
+    ).expect("Failed to transfer with fee");
+
+    let withheld = fees::get_withheld_fees(
+        &env.client,
+        &withdraw_withheld_elgamal,
+        &mint.pubkey(),
+        &[recipient_token_account],
+    ).expect("Failed to decrypt withheld fees");
+    assert_eq!(withheld, fees::WithheldFees { mint: 0, accounts: first_fee, total: first_fee });
+
+    // Harvest moves it to the mint
+    fees::harvest_withheld_tokens_to_mint(
+        &env.client,
+        &env.payer,
+        &mint.pubkey(),
+        &[recipient_token_account],
+    ).await.expect("Failed to harvest withheld fees");
+
+    let withheld = fees::get_withheld_fees(
+        &env.client,
+        &withdraw_withheld_elgamal,
+        &mint.pubkey(),
+        &[recipient_token_account],
+    ).expect("Failed to decrypt withheld fees");
+    assert_eq!(withheld, fees::WithheldFees { mint: first_fee, accounts: 0, total: first_fee });
+
+    // Withdraw from the mint into the collector's available balance
+    fees::withdraw_withheld_tokens_from_mint(
+        &env.client,
+        &env.payer,
+        &mint_authority,
+        &withdraw_withheld_elgamal,
+        &mint.pubkey(),
+    ).await.expect("Failed to withdraw withheld fees from mint");
+
+    let withheld = fees::get_withheld_fees(
+        &env.client,
+        &withdraw_withheld_elgamal,
+        &mint.pubkey(),
+        &[recipient_token_account],
+    ).expect("Failed to decrypt withheld fees");
+    assert_eq!(withheld.total, 0);
+    assert_eq!(collector_balances().available, first_fee);
+
+    // A second fee withdrawn straight from the recipient account
+    let (second_amount, second_fee) = (5_000_000, 50_000);
+    confidential_transfer_with_fee(
+        &env, &sender, &mint.pubkey(), &recipient.pubkey(), second_amount,
+        fee_basis_points, maximum_fee, withdraw_withheld_elgamal.pubkey(),
+    ).expect("Failed to transfer with fee");
+
+    fees::withdraw_withheld_tokens_from_accounts(
+        &env.client,
+        &env.payer,
+        &mint_authority,
+        &withdraw_withheld_elgamal,
+        &mint.pubkey(),
+        &[recipient_token_account],
+    ).await.expect("Failed to withdraw withheld fees from accounts");
+
+    let withheld = fees::get_withheld_fees(
+        &env.client,
+        &withdraw_withheld_elgamal,
+        &mint.pubkey(),
+        &[recipient_token_account],
+    ).expect("Failed to decrypt withheld fees");
+    assert_eq!(withheld.total, 0);
+    assert_eq!(collector_balances().available, first_fee + second_fee);
+
+    // The recipient got the transfers minus the fees
+    let recipient_balances = watch::WatchOnlyAccount::from_provider(
+        recipient_token_account,
+        &key_provider::SignerKeyProvider::new(&recipient),
+    )
+    .and_then(|account| account.balances(&env.client))
+    .expect("Failed to read recipient balances");
+    assert_eq!(recipient_balances.pending, first_amount - first_fee + second_amount - second_fee);
+
+    println!("✅ test_harvest_and_withdraw_withheld_fees PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_confidential_mint_and_burn() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    // Mint authority pays for proof context accounts
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 1_000_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let (mint, supply_elgamal, supply_aes) =
+        create_confidential_mint_burn_mint(&env, &mint_authority, 9)
+            .expect("Failed to create mint/burn mint");
+
+    // Create and configure holder account
+    let holder = Keypair::new();
+    env.airdrop_if_needed(&holder.pubkey(), 1_000_000_000)
+        .expect("Airdrop to holder failed");
+
+    let _holder_token_account = create_token_account(&env, &mint.pubkey(), &holder.pubkey())
+        .expect("Failed to create holder token account");
+
+    configure::configure_account_for_confidential_transfers(
+        &env.client,
+        &env.payer,
+        &holder,
+        &mint.pubkey(),
+    ).await.expect("Failed to configure holder account");
+
+    // Mint directly into the holder's pending confidential balance
+    let mint_amount = 100_000_000u64;
+    mint_burn::confidential_mint_to(
+        &env.client,
+        &env.payer,
+        &mint_authority,
+        &supply_elgamal,
+        &supply_aes,
+        &mint.pubkey(),
+        &holder.pubkey(),
+        mint_amount,
+    ).await.expect("Failed to mint confidentially");
+
+    let supply = mint_burn::get_confidential_supply(&env.client, &supply_elgamal, &supply_aes, &mint.pubkey())
+        .expect("Failed to decrypt supply");
+    assert_eq!(supply, mint_amount);
+
+    apply_pending::apply_pending_balance(
+        &env.client,
+        &env.payer,
+        &holder,
+        &mint.pubkey(),
+    ).await.expect("Failed to apply pending balance");
+
+    // Burn part of the available balance and fold it into the supply
+    let burn_amount = 40_000_000u64;
+    mint_burn::confidential_burn(
+        &env.client,
+        &env.payer,
+        &holder,
+        &mint.pubkey(),
+        burn_amount,
+    ).await.expect("Failed to burn confidentially");
+
+    mint_burn::apply_pending_burn(
+        &env.client,
+        &env.payer,
+        &mint_authority,
+        &mint.pubkey(),
+    ).await.expect("Failed to apply pending burn");
+
+    let supply = mint_burn::get_confidential_supply(&env.client, &supply_elgamal, &supply_aes, &mint.pubkey())
+        .expect("Failed to decrypt supply");
+    assert_eq!(supply, mint_amount - burn_amount);
+
+    println!("✅ test_confidential_mint_and_burn PASSED");
+}
+
+#[test]
+fn test_auditor_decrypts_transfer_instruction() {
+    let auditor_elgamal = ElGamalKeypair::new_rand();
+    let amount = 0x0012_3456u64;
+
+    // Transfer amounts are encrypted for the auditor as 16-bit lo and 32-bit hi parts
+    let ciphertext_lo = auditor_elgamal.pubkey().encrypt(amount & 0xFFFF);
+    let ciphertext_hi = auditor_elgamal.pubkey().encrypt(amount >> 16);
+
+    let (source, mint, destination, owner) =
+        (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (equality, validity, range) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+    let transfer_ix = inner_transfer(
+        &spl_token_2022::id(),
+        &source,
+        &mint,
+        &destination,
+        &AeKey::new_rand().encrypt(0).into(),
+        &ciphertext_lo.into(),
+        &ciphertext_hi.into(),
+        &owner,
+        &[],
+        ProofLocation::ContextStateAccount(&equality),
+        ProofLocation::ContextStateAccount(&validity),
+        ProofLocation::ContextStateAccount(&range),
+    ).expect("Failed to build transfer instruction");
+
+    // Raw instruction data
+    let decrypted = auditor::decrypt_transfer_instruction_data(&auditor_elgamal, &transfer_ix.data)
+        .expect("Failed to decrypt transfer instruction data");
+    assert_eq!(decrypted, amount);
+
+    // Another auditor key must not decrypt the amount
+    let other_auditor = ElGamalKeypair::new_rand();
+    assert!(auditor::decrypt_transfer_instruction_data(&other_auditor, &transfer_ix.data).is_err());
+
+    // Full transaction
+    let message = Message::new(&[transfer_ix], Some(&owner));
+    let transaction = VersionedTransaction {
+        signatures: vec![Default::default()],
+        message: VersionedMessage::Legacy(message.clone()),
+    };
+    let transfers = auditor::extract_transfer_ciphertexts(&transaction, &message.account_keys)
+        .expect("Failed to extract transfer ciphertexts");
+
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].source, source);
+    assert_eq!(transfers[0].mint, mint);
+    assert_eq!(transfers[0].destination, destination);
+    assert_eq!(transfers[0].decrypt(&auditor_elgamal), Some(amount));
+
+    println!("✅ test_auditor_decrypts_transfer_instruction PASSED");
+}
+
+#[test]
+fn test_decode_confidential_transaction() {
+    let elgamal_keypair = ElGamalKeypair::new_rand();
+    let (token_account, mint, destination, owner) =
+        (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (equality, validity, range) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+    // ConfigureAccount followed by its inline pubkey validity proof
+    let proof_data = PubkeyValidityProofData::new(&elgamal_keypair)
+        .expect("Failed to generate pubkey validity proof");
+    let mut instructions = configure_account(
+        &spl_token_2022::id(),
+        &token_account,
+        &mint,
+        &AeKey::new_rand().encrypt(0).into(),
+        65536,
+        &owner,
+        &[],
+        ProofLocation::InstructionOffset(1.try_into().unwrap(), &proof_data),
+    ).expect("Failed to build configure instructions");
+
+    // Transfer reading its proofs from context state accounts
+    let auditor_ciphertext = ElGamalKeypair::new_rand().pubkey().encrypt(0_u64);
+    instructions.push(inner_transfer(
+        &spl_token_2022::id(),
+        &token_account,
+        &mint,
+        &destination,
+        &AeKey::new_rand().encrypt(0).into(),
+        &auditor_ciphertext.into(),
+        &auditor_ciphertext.into(),
+        &owner,
+        &[],
+        ProofLocation::ContextStateAccount(&equality),
+        ProofLocation::ContextStateAccount(&validity),
+        ProofLocation::ContextStateAccount(&range),
+    ).expect("Failed to build transfer instruction"));
+
+    let message = Message::new(&instructions, Some(&owner));
+    let transaction = VersionedTransaction {
+        signatures: vec![Default::default()],
+        message: VersionedMessage::Legacy(message),
+    };
+    let bytes = bincode::serialize(&transaction).expect("Failed to serialize transaction");
+
+    let decoded = decoder::decode_transaction_bytes(&bytes).expect("Failed to decode transaction");
+    assert_eq!(decoded.instructions.len(), 3);
+
+    match &decoded.instructions[0].operation {
+        decoder::Operation::ConfidentialTransfer(decoder::ConfidentialTransferOperation::ConfigureAccount {
+            token_account: decoded_account,
+            maximum_pending_balance_credit_counter,
+            pubkey_validity_proof: decoder::ProofReference::InstructionOffset { offset: 1, instruction_index: Some(1) },
+            ..
+        }) => {
+            assert_eq!(*decoded_account, token_account);
+            assert_eq!(*maximum_pending_balance_credit_counter, 65536);
+        }
+        other => panic!("Unexpected operation: {:?}", other),
+    }
+
+    match &decoded.instructions[1].operation {
+        decoder::Operation::ZkElGamalProof(decoder::ProofOperation::Verify {
+            proof_type,
+            source: decoder::ProofSource::InstructionData,
+            context_state: None,
+            context: Some(decoder::ProofContextData::PubkeyValidity { pubkey }),
+            ..
+        }) => {
+            assert_eq!(proof_type, "VerifyPubkeyValidity");
+            assert_eq!(*pubkey, PodElGamalPubkey::from(*elgamal_keypair.pubkey()).to_string());
+        }
+        other => panic!("Unexpected operation: {:?}", other),
+    }
+
+    match &decoded.instructions[2].operation {
+        decoder::Operation::ConfidentialTransfer(decoder::ConfidentialTransferOperation::Transfer {
+            destination: decoded_destination,
+            equality_proof: decoder::ProofReference::ContextStateAccount { account: equality_account },
+            ciphertext_validity_proof: decoder::ProofReference::ContextStateAccount { account: validity_account },
+            range_proof: decoder::ProofReference::ContextStateAccount { account: range_account },
+            ..
+        }) => {
+            assert_eq!(*decoded_destination, destination);
+            assert_eq!((*equality_account, *validity_account, *range_account), (equality, validity, range));
+        }
+        other => panic!("Unexpected operation: {:?}", other),
+    }
+
+    // Text and JSON renderings
+    let text = decoded.to_string();
+    assert!(text.contains("Token-2022 Confidential Transfer: ConfigureAccount"));
+    assert!(text.contains(&format!("context state account {}", validity)));
+
+    let json: serde_json::Value = serde_json::from_str(&decoded.to_json().expect("Failed to render JSON"))
+        .expect("Invalid JSON");
+    assert_eq!(json["instructions"][0]["operation"]["confidential_transfer"]["instruction"], "configure_account");
+    assert_eq!(json["instructions"][2]["operation"]["confidential_transfer"]["source"], token_account.to_string());
+
+    println!("✅ test_decode_confidential_transaction PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_auditor_decrypts_confidential_transfer() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    // Keep the auditor keypair so we can decrypt transfers afterwards
+    let (mint, auditor_elgamal) = create_confidential_mint_with_auditor(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    let sender = Keypair::new();
+    let recipient = Keypair::new();
+    env.airdrop_if_needed(&sender.pubkey(), 100_000_000)
+        .expect("Airdrop to sender failed");
+    env.airdrop_if_needed(&recipient.pubkey(), 100_000_000)
+        .expect("Airdrop to recipient failed");
+
+    let sender_token_account = create_token_account(&env, &mint.pubkey(), &sender.pubkey())
+        .expect("Failed to create sender token account");
+    let recipient_token_account = create_token_account(&env, &mint.pubkey(), &recipient.pubkey())
+        .expect("Failed to create recipient token account");
+
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to configure sender account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &recipient, &mint.pubkey())
+        .await.expect("Failed to configure recipient account");
+
+    mint_tokens(&env, &mint.pubkey(), &sender_token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), 800_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+
+    let transfer_amount = 50_000_000u64;
+    let receipt = transfer::transfer_confidential(
+        &env.client,
+        &env.payer,
+        &sender,
+        &mint.pubkey(),
+        &recipient.pubkey(),
+        transfer_amount,
+    ).await.expect("Transfer failed");
+
+    let signature = receipt.signature(receipt::TransactionRole::Transfer).unwrap();
+    let records = auditor::audit_transaction(&env.client, &auditor_elgamal, &signature)
+        .expect("Failed to audit transfer");
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].source, sender_token_account);
+    assert_eq!(records[0].destination, recipient_token_account);
+    assert_eq!(records[0].amount, transfer_amount);
+
+    println!("✅ test_auditor_decrypts_confidential_transfer PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mint_audit_report() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let (mint, auditor_elgamal) = create_confidential_mint_with_auditor(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    let sender = Keypair::new();
+    let recipient = Keypair::new();
+    env.airdrop_if_needed(&sender.pubkey(), 100_000_000)
+        .expect("Airdrop to sender failed");
+    env.airdrop_if_needed(&recipient.pubkey(), 100_000_000)
+        .expect("Airdrop to recipient failed");
+
+    let sender_token_account = create_token_account(&env, &mint.pubkey(), &sender.pubkey())
+        .expect("Failed to create sender token account");
+    let recipient_token_account = create_token_account(&env, &mint.pubkey(), &recipient.pubkey())
+        .expect("Failed to create recipient token account");
+
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to configure sender account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &recipient, &mint.pubkey())
+        .await.expect("Failed to configure recipient account");
+
+    mint_tokens(&env, &mint.pubkey(), &sender_token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), 800_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+
+    transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), &recipient.pubkey(), 50_000_000)
+        .await.expect("First transfer failed");
+
+    let cache_path = std::env::temp_dir().join(format!("audit-{}.json", mint.pubkey()));
+    let mut config = audit_report::AuditReportConfig::new(mint.pubkey());
+    config.cache_path = Some(cache_path.clone());
+
+    let report = audit_report::generate_audit_report(&env.client, &auditor_elgamal, &config)
+        .expect("Failed to generate audit report");
+    assert_eq!(report.entries.len(), 1);
+    assert_eq!(report.accounts[&sender_token_account].total_out, 50_000_000);
+    assert_eq!(report.accounts[&recipient_token_account].total_in, 50_000_000);
+    assert_eq!(report.undecryptable().count(), 0);
+
+    // A rerun only picks up the new transfer from the cached cursor
+    transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), &recipient.pubkey(), 25_000_000)
+        .await.expect("Second transfer failed");
+
+    let report = audit_report::generate_audit_report(&env.client, &auditor_elgamal, &config)
+        .expect("Failed to regenerate audit report");
+    assert_eq!(report.entries.len(), 2);
+    assert_eq!(report.accounts[&sender_token_account].total_out, 75_000_000);
+    assert_eq!(report.accounts[&recipient_token_account].transfers_in, 2);
+    assert!(report.transfers_csv().lines().count() == 3);
+    assert!(report.to_json().is_ok());
+
+    let _ = std::fs::remove_file(cache_path);
+    println!("✅ test_mint_audit_report PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_history() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let mint = create_confidential_mint(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    let sender = Keypair::new();
+    let recipient = Keypair::new();
+    env.airdrop_if_needed(&sender.pubkey(), 100_000_000)
+        .expect("Airdrop to sender failed");
+    env.airdrop_if_needed(&recipient.pubkey(), 100_000_000)
+        .expect("Airdrop to recipient failed");
+
+    let sender_token_account = create_token_account(&env, &mint.pubkey(), &sender.pubkey())
+        .expect("Failed to create sender token account");
+    let recipient_token_account = create_token_account(&env, &mint.pubkey(), &recipient.pubkey())
+        .expect("Failed to create recipient token account");
+
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to configure sender account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &recipient, &mint.pubkey())
+        .await.expect("Failed to configure recipient account");
+
+    mint_tokens(&env, &mint.pubkey(), &sender_token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), 800_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &sender, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+    transfer::transfer_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), &recipient.pubkey(), 50_000_000)
+        .await.expect("Transfer failed");
+    withdraw::withdraw_from_confidential(&env.client, &env.payer, &sender, &mint.pubkey(), 100_000_000, 9)
+        .await.expect("Withdraw failed");
+
+    // Sender: deposit -> apply -> transfer out -> withdraw
+    let sender_history = history::get_transaction_history(&env.client, &sender, &mint.pubkey())
+        .expect("Failed to reconstruct sender history");
+    let events: Vec<_> = sender_history.entries.iter().map(|entry| (entry.event, entry.amount)).collect();
+    assert_eq!(events, vec![
+        (history::HistoryEvent::Deposit, Some(800_000_000)),
+        (history::HistoryEvent::ApplyPendingBalance, Some(800_000_000)),
+        (history::HistoryEvent::TransferOut { destination: recipient_token_account }, Some(50_000_000)),
+        (history::HistoryEvent::Withdraw, Some(100_000_000)),
+    ]);
+    assert_eq!(sender_history.pending_balance(), Some(0));
+    assert_eq!(sender_history.available_balance(), 650_000_000);
+
+    // Recipient: transfer in lands in pending, then apply
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &recipient, &mint.pubkey())
+        .await.expect("Failed to apply recipient pending balance");
+
+    let recipient_history = history::get_transaction_history(&env.client, &recipient, &mint.pubkey())
+        .expect("Failed to reconstruct recipient history");
+    assert_eq!(recipient_history.entries.len(), 2);
+    assert_eq!(recipient_history.entries[0].event, history::HistoryEvent::TransferIn { source: sender_token_account });
+    assert_eq!(recipient_history.entries[0].amount, Some(50_000_000));
+    assert_eq!(recipient_history.entries[0].pending_balance, Some(50_000_000));
+    assert_eq!(recipient_history.available_balance(), 50_000_000);
+
+    println!("✅ test_transaction_history PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rotate_encryption_keys() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let mint = create_confidential_mint(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    let owner = Keypair::new();
+    env.airdrop_if_needed(&owner.pubkey(), 100_000_000)
+        .expect("Airdrop to owner failed");
+
+    let token_account = create_token_account(&env, &mint.pubkey(), &owner.pubkey())
+        .expect("Failed to create token account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to configure account");
+
+    mint_tokens(&env, &mint.pubkey(), &token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 600_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+    // Leave some tokens pending to check they are carried over
+    deposit::deposit_to_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 100_000_000, 9)
+        .await.expect("Failed to deposit");
+
+    // Rotate to keys derived from a new custody signer
+    let new_custody = Keypair::new();
+    let current_keys = key_provider::SignerKeyProvider::cached(&owner);
+    let new_keys = key_provider::SignerKeyProvider::cached(&new_custody);
+    let progress_path = std::env::temp_dir().join(format!("rotation-{}.json", token_account));
+
+    let progress = rotation::rotate_encryption_keys(
+        &env.client, &env.payer, &owner, &mint.pubkey(), &current_keys, &new_keys, &progress_path,
+    ).await.expect("Key rotation failed");
+    assert_eq!(progress.step, rotation::RotationStep::Completed);
+    assert_eq!(progress.confidential_amount, 700_000_000);
+
+    // Rerunning a completed rotation is a no-op
+    let resumed = rotation::rotate_encryption_keys(
+        &env.client, &env.payer, &owner, &mint.pubkey(), &current_keys, &new_keys, &progress_path,
+    ).await.expect("Resuming a completed rotation failed");
+    assert_eq!(resumed.signatures, progress.signatures);
+
+    // The rotated balance is spendable with the new keys
+    withdraw::withdraw_with_keys(
+        &env.client, &env.payer, &owner, &mint.pubkey(), 700_000_000, 9,
+        &new_keys,
+    ).await.expect("Withdraw with new keys failed");
+
+    let _ = std::fs::remove_file(progress_path);
+    println!("✅ test_rotate_encryption_keys PASSED");
+}
+
+#[test]
+fn test_key_providers() {
+    use key_provider::*;
+
+    let token_account = Pubkey::new_unique();
+    let owner = Keypair::new();
+    let elgamal_keypair = ElGamalKeypair::new_from_signer(&owner, &token_account.to_bytes()).unwrap();
+    let aes_key = AeKey::new_from_signer(&owner, &token_account.to_bytes()).unwrap();
+    let expected_pubkey = elgamal_keypair.pubkey().to_string();
+
+    let same_keys = |provider: &dyn KeyProvider| {
+        let provided = provider.elgamal_keypair(&token_account).expect("Failed to provide ElGamal keypair");
+        assert_eq!(provided.pubkey().to_string(), expected_pubkey);
+        let provided_aes = provider.aes_key(&token_account).expect("Failed to provide AE key");
+        assert_eq!(provided_aes.decrypt(&aes_key.encrypt(42)), Some(42));
+    };
+
+    // Signer-derived and explicit keys agree
+    same_keys(&SignerKeyProvider::new(&owner));
+    let explicit = ExplicitKeyProvider::new(elgamal_keypair.clone(), aes_key.clone());
+    same_keys(&explicit);
+
+    // Caching returns the same keys
+    let cached = SignerKeyProvider::cached(&owner);
+    same_keys(&cached);
+    same_keys(&cached);
+
+    // Encrypted key file round trip, bound to the token account
+    let path = std::env::temp_dir().join(format!("ct-keys-{}.json", token_account));
+    EncryptedKeyFile::seal(Some(token_account), &elgamal_keypair, &aes_key, "hunter2", 10)
+        .and_then(|file| file.save(&path))
+        .expect("Failed to write key file");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    same_keys(&EncryptedFileKeyProvider::new(&path, "hunter2"));
+    assert!(EncryptedFileKeyProvider::new(&path, "wrong").aes_key(&token_account).is_err());
+    assert!(EncryptedFileKeyProvider::new(&path, "hunter2").aes_key(&Pubkey::new_unique()).is_err());
+    let _ = std::fs::remove_file(path);
+
+    // A crafted scrypt cost is rejected before deriving anything
+    let mut costly = EncryptedKeyFile::seal(None, &elgamal_keypair, &aes_key, "hunter2", 10).unwrap();
+    costly.scrypt_log_n = 60;
+    let Err(error) = costly.open("hunter2") else { panic!("Accepted scrypt cost 2^60") };
+    assert!(error.to_string().contains("exceeds"), "{}", error);
+    let mut costly = EncryptedKeyFile::seal(None, &elgamal_keypair, &aes_key, "hunter2", 1).unwrap();
+    costly.scrypt_r = 1 << 29;
+    assert!(costly.open("hunter2").is_err());
+    costly.scrypt_r = 8;
+    costly.scrypt_p = u32::MAX / 8;
+    assert!(costly.open("hunter2").is_err());
+
+    // Environment variables
+    let elgamal_var = format!("CT_TEST_ELGAMAL_{}", token_account);
+    let aes_var = format!("CT_TEST_AE_{}", token_account);
+    std::env::set_var(&elgamal_var, hex::encode(elgamal_keypair.secret().as_bytes()));
+    std::env::set_var(&aes_var, hex::encode(<[u8; 16]>::from(aes_key.clone())));
+    same_keys(&EnvKeyProvider::with_vars(&elgamal_var, &aes_var));
+    std::env::remove_var(&aes_var);
+    assert!(EnvKeyProvider::with_vars(&elgamal_var, &aes_var).aes_key(&token_account).is_err());
+
+    // Remote key service stand-in on localhost
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        serve_key_requests(listener, &explicit, "s3cret", Some(6)).map_err(|e| e.to_string())
+    });
+    same_keys(&RemoteKeyProvider::new(address, "s3cret"));
+    let error = RemoteKeyProvider::new(address, "guess").aes_key(&token_account).unwrap_err();
+    assert!(error.to_string().contains("Unauthorized"), "{}", error);
+    // A client hanging up without a request doesn't stop the service
+    drop(std::net::TcpStream::connect(address).unwrap());
+    same_keys(&RemoteKeyProvider::new(address, "s3cret"));
+    server.join().unwrap().expect("Key service failed");
+
+    println!("✅ test_key_providers PASSED");
+}
+
+#[test]
+fn test_keystore_export_import_list() {
+    use key_provider::KeyProvider;
+    use keystore::Keystore;
+
+    let owner = Keypair::new();
+    let mint = Pubkey::new_unique();
+    let token_account = spl_associated_token_account::get_associated_token_address_with_program_id(
+        &owner.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+    let dir = std::env::temp_dir().join(format!("ct-keystore-{}", owner.pubkey()));
+    let keystore = Keystore::open(&dir).expect("Failed to open keystore").with_scrypt_log_n(10);
+
+    // Export the signer-derived keys, then refuse to overwrite them
+    keystore.export_from_signer(&owner, &mint, "correct horse").expect("Export failed");
+    assert!(keystore.export_from_signer(&owner, &mint, "correct horse").is_err());
+
+    let entries = keystore.list().expect("List failed");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].token_account, token_account);
+    let expected = ElGamalKeypair::new_from_signer(&owner, &token_account.to_bytes()).unwrap();
+    assert_eq!(entries[0].elgamal_pubkey, expected.pubkey().to_string());
+
+    // Keys come back without the owner's signer
+    let imported = keystore.import(&token_account, "correct horse").expect("Import failed");
+    assert_eq!(
+        imported.elgamal_keypair(&token_account).unwrap().pubkey().to_string(),
+        expected.pubkey().to_string(),
+    );
+    assert!(keystore.import(&token_account, "wrong").is_err());
+
+    let unlocked = keystore.unlock("correct horse");
+    let aes_key = AeKey::new_from_signer(&owner, &token_account.to_bytes()).unwrap();
+    let ciphertext = aes_key.encrypt(1234);
+    assert_eq!(unlocked.aes_key(&token_account).unwrap().decrypt(&ciphertext), Some(1234));
+
+    // Exported files can be moved into another keystore
+    let other_dir = dir.join("other");
+    let other = Keystore::open(&other_dir).expect("Failed to open second keystore");
+    assert!(other.import_file(&entries[0].path, "wrong").is_err());
+    assert_eq!(other.import_file(&entries[0].path, "correct horse").unwrap(), token_account);
+    assert_eq!(other.list().unwrap().len(), 1);
+
+    keystore.remove(&token_account).expect("Remove failed");
+    assert!(keystore.list().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(dir);
+    println!("✅ test_keystore_export_import_list PASSED");
+}
+
+#[test]
+fn test_operation_events() {
+    use events::{Operation, OperationEvent};
+    use keystore::Keystore;
+
+    let receiver = events::subscribe();
+    // Listeners may subscribe (or emit) themselves without deadlocking; dropped receivers are removed
+    let listener = events::add_listener(|_| drop(events::subscribe()));
+
+    let owner = Keypair::new();
+    let mint = Pubkey::new_unique();
+    let dir = std::env::temp_dir().join(format!("ct-events-{}", owner.pubkey()));
+    let keystore = Keystore::open(&dir).expect("Failed to open keystore").with_scrypt_log_n(10);
+    let path = keystore.export_from_signer(&owner, &mint, "correct horse").expect("Export failed");
+
+    // Listeners are process-wide, so skip events of other tests
+    let event = receiver.try_iter()
+        .find(|event| matches!(event, OperationEvent::Completed { operation: Operation::KeyExport, summary }
+            if summary.contains(&path.display().to_string())))
+        .expect("No key export event");
+    assert!(event.to_string().starts_with("✅ Exported keys of"));
+
+    let json = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["event"], "completed");
+    assert_eq!(json["operation"], "key_export");
+
+    // Removing a listener only removes that one
+    assert!(events::remove_listener(listener));
+    assert!(!events::remove_listener(listener));
+
+    let _ = std::fs::remove_dir_all(dir);
+    println!("✅ test_operation_events PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_parallel_transfer_proofs() {
+    use spl_token_2022::{
+        extension::confidential_transfer::account_info::TransferAccountInfo,
+        solana_zk_sdk::{
+            encryption::elgamal::ElGamalCiphertext,
+            zk_elgamal_proof_program::proof_data::ZkProofData,
+        },
+    };
+
+    let sender = ElGamalKeypair::new_rand();
+    let sender_aes = AeKey::new_rand();
+    let recipient = ElGamalKeypair::new_rand();
+    let auditor = ElGamalKeypair::new_rand();
+    let account_info = TransferAccountInfo {
+        available_balance: sender.pubkey().encrypt(1_000_000u64).into(),
+        decryptable_available_balance: sender_aes.encrypt(1_000_000).into(),
+    };
+
+    let transfers_before = proofs::proof_metrics().transfers;
+    let (proof_data, timings) = proofs::generate_transfer_proofs(
+        account_info,
+        70_000,
+        sender.clone(),
+        sender_aes.clone(),
+        *recipient.pubkey(),
+        Some(*auditor.pubkey()),
+    ).await.expect("Proof generation failed");
+
+    // Every proof verifies and the remaining balance is what the program will compute
+    proof_data.equality_proof_data.verify_proof().expect("Equality proof invalid");
+    proof_data.ciphertext_validity_proof_data_with_ciphertext.proof_data.verify_proof()
+        .expect("Ciphertext validity proof invalid");
+    proof_data.range_proof_data.verify_proof().expect("Range proof invalid");
+    let remaining: ElGamalCiphertext = proof_data.equality_proof_data.context_data().ciphertext.try_into().unwrap();
+    assert_eq!(remaining.decrypt_u32(sender.secret()), Some(930_000));
+
+    // The auditor can decrypt the amount
+    let auditor_lo: ElGamalCiphertext = proof_data.ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo.try_into().unwrap();
+    let auditor_hi: ElGamalCiphertext = proof_data.ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi.try_into().unwrap();
+    let amount = auditor_lo.decrypt_u32(auditor.secret()).unwrap() + (auditor_hi.decrypt_u32(auditor.secret()).unwrap() << 16);
+    assert_eq!(amount, 70_000);
+
+    assert!(timings.total >= timings.equality.max(timings.ciphertext_validity).max(timings.range));
+    assert!(proofs::proof_metrics().transfers > transfers_before);
+
+    let error = proofs::transfer_proofs(&account_info, 1_000_001, &sender, &sender_aes, recipient.pubkey(), None)
+        .err().expect("Overdraft must fail");
+    assert!(error.to_string().contains("Insufficient balance"));
+
+    println!("✅ test_parallel_transfer_proofs PASSED");
+}
+
+#[tokio::test]
+async fn test_transfer_proof_placement() {
+    use proof_placement::{Placements, ProofPlacement, TransferPlacements, WithdrawPlacements};
+    use spl_token_2022::extension::confidential_transfer::{
+        account_info::{TransferAccountInfo, WithdrawAccountInfo},
+        instruction::{transfer, withdraw},
+    };
+
+    let sender = ElGamalKeypair::new_rand();
+    let sender_aes = AeKey::new_rand();
+    let account_info = TransferAccountInfo {
+        available_balance: sender.pubkey().encrypt(1_000_000u64).into(),
+        decryptable_available_balance: sender_aes.encrypt(1_000_000).into(),
+    };
+    let (proof_data, _) = proofs::generate_transfer_proofs(
+        account_info,
+        70_000,
+        sender.clone(),
+        sender_aes.clone(),
+        *ElGamalKeypair::new_rand().pubkey(),
+        Some(*ElGamalKeypair::new_rand().pubkey()),
+    ).await.expect("Proof generation failed");
+    let validity = &proof_data.ciphertext_validity_proof_data_with_ciphertext;
+
+    // Serialized size of the transfer transaction with inline proofs at offsets 1, 2, ...
+    let payer = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let context_account = Pubkey::new_unique();
+    let size_of = |placements: TransferPlacements| {
+        let mut next_offset = 1;
+        let instructions = transfer(
+            &spl_token_2022::id(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &sender_aes.encrypt(930_000).into(),
+            &validity.ciphertext_lo,
+            &validity.ciphertext_hi,
+            &authority,
+            &[],
+            proof_placement::proof_location(placements.equality, &mut next_offset, &context_account, &proof_data.equality_proof_data)?,
+            proof_placement::proof_location(placements.ciphertext_validity, &mut next_offset, &context_account, &validity.proof_data)?,
+            proof_placement::proof_location(placements.range, &mut next_offset, &context_account, &proof_data.range_proof_data)?,
+        )?;
+        proof_placement::transaction_size(&payer, &instructions, &[])
+    };
+
+    // All three proofs never fit; all context state accounts always do
+    let all_inline = TransferPlacements {
+        equality: ProofPlacement::Inline,
+        ciphertext_validity: ProofPlacement::Inline,
+        range: ProofPlacement::Inline,
+    };
+    assert!(size_of(all_inline).unwrap() > 1232);
+    assert!(size_of(TransferPlacements::CONTEXT_STATE).unwrap() <= 1232);
+    assert_eq!(TransferPlacements::candidates().len(), 8);
+
+    // The equality proof is the one that fits inline
+    let selected: TransferPlacements = proof_placement::select_placements(size_of).expect("No placement fits");
+    assert_eq!(selected, TransferPlacements { equality: ProofPlacement::Inline, ..TransferPlacements::CONTEXT_STATE });
+    assert_eq!(selected.context_accounts(), 2);
+
+    // Nothing fitting is an error
+    assert!(proof_placement::select_placements::<TransferPlacements>(|_| Ok(2000)).is_err());
+
+    // The range proof's verification fits alone only when its authority pays the fee
+    let range_proof = &proof_data.range_proof_data;
+    assert_eq!(
+        proof_placement::with_record_fallback(ProofPlacement::ContextState, &authority, &authority, range_proof).unwrap(),
+        ProofPlacement::ContextState
+    );
+    assert_eq!(
+        proof_placement::with_record_fallback(ProofPlacement::ContextState, &payer, &authority, range_proof).unwrap(),
+        ProofPlacement::Record
+    );
+    assert_eq!(
+        proof_placement::with_record_fallback(ProofPlacement::Inline, &payer, &authority, range_proof).unwrap(),
+        ProofPlacement::Inline
+    );
+
+    // Both withdraw proofs don't fit inline either; the equality proof does
+    let withdraw_info = WithdrawAccountInfo {
+        available_balance: sender.pubkey().encrypt(1_000_000u64).into(),
+        decryptable_available_balance: sender_aes.encrypt(1_000_000).into(),
+    };
+    let withdraw_proofs = withdraw_info.generate_proof_data(70_000, &sender, &sender_aes).unwrap();
+    let selected: WithdrawPlacements = proof_placement::select_placements(|placements: WithdrawPlacements| {
+        let mut next_offset = 1;
+        let instructions = withdraw(
+            &spl_token_2022::id(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            70_000,
+            9,
+            &sender_aes.encrypt(930_000).into(),
+            &authority,
+            &[],
+            proof_placement::proof_location(placements.equality, &mut next_offset, &context_account, &withdraw_proofs.equality_proof_data)?,
+            proof_placement::proof_location(placements.range, &mut next_offset, &context_account, &withdraw_proofs.range_proof_data)?,
+        )?;
+        proof_placement::transaction_size(&payer, &instructions, &[])
+    }).expect("No placement fits");
+    assert_eq!(selected, WithdrawPlacements { equality: ProofPlacement::Inline, range: ProofPlacement::ContextState });
+
+    println!("✅ test_transfer_proof_placement PASSED");
+}
+
+#[test]
+fn test_local_proof_verification() {
+    use spl_token_2022::extension::confidential_transfer::account_info::{TransferAccountInfo, WithdrawAccountInfo};
+    use verification::{InvalidProof, ProofKind};
+
+    // Flip a byte of the proof itself (proof data ends with the proof)
+    fn corrupt<T: bytemuck::Pod>(proof_data: &mut T) {
+        *bytemuck::bytes_of_mut(proof_data).last_mut().unwrap() ^= 1;
+    }
+
+    let keypair = ElGamalKeypair::new_rand();
+    let aes_key = AeKey::new_rand();
+
+    let mut configure_proof = PubkeyValidityProofData::new(&keypair).unwrap();
+    verification::verify_configure_proof(&configure_proof).expect("Valid proof rejected");
+    corrupt(&mut configure_proof);
+    let error = verification::verify_configure_proof(&configure_proof).unwrap_err();
+    assert_eq!((error.operation, error.proof), (events::Operation::Configure, ProofKind::PubkeyValidity));
+
+    let withdraw_info = WithdrawAccountInfo {
+        available_balance: keypair.pubkey().encrypt(1_000u64).into(),
+        decryptable_available_balance: aes_key.encrypt(1_000).into(),
+    };
+    let mut withdraw_proofs = withdraw_info.generate_proof_data(100, &keypair, &aes_key).unwrap();
+    verification::verify_withdraw_proofs(&withdraw_proofs).expect("Valid proofs rejected");
+    corrupt(&mut withdraw_proofs.equality_proof_data);
+    assert_eq!(verification::verify_withdraw_proofs(&withdraw_proofs).unwrap_err().proof, ProofKind::Equality);
+
+    let transfer_info = TransferAccountInfo {
+        available_balance: keypair.pubkey().encrypt(1_000u64).into(),
+        decryptable_available_balance: aes_key.encrypt(1_000).into(),
+    };
+    let recipient = ElGamalKeypair::new_rand();
+    let (mut transfer_proofs, _) =
+        proofs::transfer_proofs(&transfer_info, 100, &keypair, &aes_key, recipient.pubkey(), None).unwrap();
+    verification::verify_transfer_proofs(events::Operation::Transfer, &transfer_proofs).expect("Valid proofs rejected");
+    corrupt(&mut transfer_proofs.range_proof_data);
+    let error = verification::verify_transfer_proofs(events::Operation::Transfer, &transfer_proofs).unwrap_err();
+    assert_eq!(error.proof, ProofKind::Range);
+
+    // Typed through the library's boxed errors
+    let boxed: Box<dyn std::error::Error> = error.into();
+    let invalid = boxed.downcast_ref::<InvalidProof>().expect("Expected a typed proof error");
+    assert!(boxed.to_string().contains("transfer range proof"));
+    assert_eq!(serde_json::to_value(invalid).unwrap()["proof"], "range");
+
+    println!("✅ test_local_proof_verification PASSED");
+}
+
+#[test]
+fn test_decryption_engine() {
+    use spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalCiphertext;
+
+    let keypair = ElGamalKeypair::new_rand();
+    let engine = decryption::DecryptionEngine::build(16).expect("Table build failed");
+
+    // Same results as the SDK's decrypt_u32, including the table's edge entries
+    for amount in [0u64, 1, 65_535, 65_536, 1_000_000, (1 << 32) - 1] {
+        let ciphertext = keypair.pubkey().encrypt(amount);
+        assert_eq!(engine.decrypt_u32(keypair.secret(), &ciphertext), Some(amount));
+    }
+    assert_eq!(engine.decrypt_u32(keypair.secret(), &keypair.pubkey().encrypt(1u64 << 32)), None);
+    let other = ElGamalKeypair::new_rand();
+    assert_eq!(engine.decrypt_u32(other.secret(), &keypair.pubkey().encrypt(42u64)), None);
+
+    // Pending lo/hi and available decrypted together
+    let ciphertexts: Vec<ElGamalCiphertext> = [7u64, 3, 900_000].iter()
+        .map(|amount| keypair.pubkey().encrypt(*amount))
+        .collect();
+    assert_eq!(engine.decrypt_many(keypair.secret(), &ciphertexts), vec![Some(7), Some(3), Some(900_000)]);
+    assert_eq!(
+        engine.decrypt_lo_hi(keypair.secret(), &ciphertexts[0].into(), &ciphertexts[1].into()),
+        Some(7 + (3 << 16))
+    );
+
+    // The cached table round-trips and corrupt files are rejected
+    let path = std::env::temp_dir().join(format!("dlog-{}.bin", keypair.pubkey()));
+    engine.save(&path).expect("Save failed");
+    let loaded = decryption::DecryptionEngine::load(&path).expect("Load failed");
+    assert_eq!(loaded.table_bits(), 16);
+    assert_eq!(loaded.decrypt_u32(keypair.secret(), &ciphertexts[2]), Some(900_000));
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(decryption::DecryptionEngine::load(&path).is_err());
+    let rebuilt = decryption::DecryptionEngine::load_or_build(&path, 16).expect("Rebuild failed");
+    assert_eq!(rebuilt.decrypt_u32(keypair.secret(), &ciphertexts[2]), Some(900_000));
+    assert!(decryption::DecryptionEngine::load(&path).is_ok());
+    assert!(decryption::DecryptionEngine::build(32).is_err());
+
+    let _ = std::fs::remove_file(path);
+    println!("✅ test_decryption_engine PASSED");
+}
+
+#[test]
+fn test_recipient_validation() {
+    use recipient::{check_recipient_account, RecipientReason};
+    use solana_sdk::account::Account;
+    use spl_token_2022::{
+        extension::{
+            confidential_transfer::ConfidentialTransferAccount, memo_transfer::MemoTransfer,
+            BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+        },
+        state::{Account as TokenAccount, AccountState},
+    };
+
+    let mint = Pubkey::new_unique();
+    let token_account = Pubkey::new_unique();
+    let elgamal = ElGamalKeypair::new_rand();
+    let account = |configure: &dyn Fn(&mut TokenAccount, &mut ConfidentialTransferAccount)| {
+        let space = ExtensionType::try_calculate_account_len::<TokenAccount>(&[ExtensionType::ConfidentialTransferAccount]).unwrap();
+        let mut data = vec![0; space];
+        let mut state = StateWithExtensionsMut::<TokenAccount>::unpack_uninitialized(&mut data).unwrap();
+        let mut base = TokenAccount { mint, owner: Pubkey::new_unique(), state: AccountState::Initialized, ..Default::default() };
+        let mut ct_extension = ConfidentialTransferAccount {
+            approved: true.into(),
+            elgamal_pubkey: (*elgamal.pubkey()).into(),
+            allow_confidential_credits: true.into(),
+            maximum_pending_balance_credit_counter: 65_536u64.into(),
+            ..Default::default()
+        };
+        configure(&mut base, &mut ct_extension);
+        *state.init_extension::<ConfidentialTransferAccount>(true).unwrap() = ct_extension;
+        state.base = base;
+        state.pack_base();
+        state.init_account_type().unwrap();
+        Account { lamports: 1, data, owner: spl_token_2022::id(), executable: false, rent_epoch: 0 }
+    };
+    let reason = |account_data: Option<&Account>| check_recipient_account(&mint, &token_account, account_data).unwrap_err().reason;
+
+    // Token-2022 accounts are destinations; wallets, multisig owners and unused addresses are owners
+    assert_eq!(
+        recipient::RecipientAddress::classify(&mint, &token_account, Some(&account(&|_, _| {}))),
+        recipient::RecipientAddress::TokenAccount { token_account }
+    );
+    let wallet = Pubkey::new_unique();
+    let wallet_account = Account { lamports: 1, data: vec![], owner: solana_system_interface::program::id(), executable: false, rent_epoch: 0 };
+    let multisig_account = Account {
+        lamports: 1,
+        data: vec![0; <spl_token_2022::state::Multisig as solana_sdk::program_pack::Pack>::LEN],
+        owner: spl_token_2022::id(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    for account_data in [Some(&wallet_account), Some(&multisig_account), None] {
+        let address = recipient::RecipientAddress::classify(&mint, &wallet, account_data);
+        assert_eq!(address.token_account(), spl_associated_token_account::get_associated_token_address_with_program_id(
+            &wallet, &mint, &spl_token_2022::id(),
+        ));
+    }
+
+    let valid = check_recipient_account(&mint, &token_account, Some(&account(&|_, _| {}))).expect("Valid recipient rejected");
+    assert_eq!(valid.elgamal_pubkey, *elgamal.pubkey());
+    assert!(!valid.memo_required);
+    valid.check_memo(None).expect("Memo not required");
+
+    // Accounts requiring memos are valid, but transfers to them need one
+    let mut memo_account = account(&|_, _| {});
+    let extension_types = [ExtensionType::ConfidentialTransferAccount, ExtensionType::MemoTransfer];
+    memo_account.data.resize(ExtensionType::try_calculate_account_len::<TokenAccount>(&extension_types).unwrap(), 0);
+    StateWithExtensionsMut::<TokenAccount>::unpack(&mut memo_account.data).unwrap()
+        .init_extension::<MemoTransfer>(true).unwrap()
+        .require_incoming_transfer_memos = true.into();
+    let valid = check_recipient_account(&mint, &token_account, Some(&memo_account)).expect("Memo recipient rejected");
+    assert!(valid.memo_required);
+    assert_eq!(valid.check_memo(None).unwrap_err().reason, RecipientReason::MemoRequired);
+    valid.check_memo(Some("invoice 42")).expect("Memo given");
+    let instructions = memo::with_memo(Some("invoice 42"), vec![solana_sdk::instruction::Instruction::new_with_bytes(
+        spl_token_2022::id(), &[], vec![],
+    )]);
+    assert_eq!(instructions[0].program_id, spl_memo_interface::v3::id());
+    assert_eq!(instructions[0].data, b"invoice 42");
+    assert_eq!(instructions[1].program_id, spl_token_2022::id());
+
+    let other_mint = Pubkey::new_unique();
+    assert_eq!(reason(None), RecipientReason::AccountNotFound);
+    assert_eq!(reason(Some(&Account { owner: Pubkey::new_unique(), ..account(&|_, _| {}) })), RecipientReason::NotATokenAccount);
+    assert_eq!(reason(Some(&account(&|base, _| base.mint = other_mint))), RecipientReason::WrongMint { mint: other_mint });
+    assert_eq!(reason(Some(&account(&|base, _| base.state = AccountState::Frozen))), RecipientReason::Frozen);
+    assert_eq!(reason(Some(&account(&|_, ct| ct.approved = false.into()))), RecipientReason::NotApproved);
+    assert_eq!(
+        reason(Some(&account(&|_, ct| ct.allow_confidential_credits = false.into()))),
+        RecipientReason::ConfidentialCreditsDisabled
+    );
+    assert_eq!(
+        reason(Some(&account(&|_, ct| ct.pending_balance_credit_counter = 65_536u64.into()))),
+        RecipientReason::CreditCounterFull { pending_balance_credit_counter: 65_536, maximum_pending_balance_credit_counter: 65_536 }
+    );
+
+    // UIs get the reason both as text and as JSON
+    let invalid = check_recipient_account(&mint, &token_account, None).unwrap_err();
+    let error: Box<dyn std::error::Error> = invalid.into();
+    assert!(error.to_string().contains("token account not found"));
+    let json = serde_json::to_value(error.downcast_ref::<recipient::InvalidRecipient>().unwrap()).unwrap();
+    assert_eq!(json["reason"], "account_not_found");
+    assert_eq!(json["token_account"], token_account.to_string());
+
+    println!("✅ test_recipient_validation PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_watch_only_balances() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let mint = create_confidential_mint(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    let owner = Keypair::new();
+    env.airdrop_if_needed(&owner.pubkey(), 100_000_000)
+        .expect("Airdrop to owner failed");
+
+    let token_account = create_token_account(&env, &mint.pubkey(), &owner.pubkey())
+        .expect("Failed to create token account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to configure account");
+
+    mint_tokens(&env, &mint.pubkey(), &token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 600_000_000, 9)
+        .await.expect("Failed to deposit");
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 100_000_000, 9)
+        .await.expect("Failed to deposit");
+
+    // The accounting service only gets the key material, never the owner
+    let watched = watch::WatchOnlyAccount::from_provider(
+        token_account,
+        &key_provider::SignerKeyProvider::new(&owner),
+    ).expect("Failed to derive keys");
+
+    let balances = watched.balances(&env.client).expect("Failed to decrypt balances");
+    assert_eq!(balances.public, 300_000_000);
+    assert_eq!(balances.pending, 100_000_000);
+    assert_eq!(balances.available, 600_000_000);
+    assert_eq!(balances.total, 1_000_000_000);
+
+    let missing = watch::WatchOnlyAccount::from_provider(
+        Pubkey::new_unique(),
+        &key_provider::SignerKeyProvider::new(&owner),
+    ).unwrap();
+    let results = watch::get_balances(&env.client, &[watched, missing])
+        .expect("Failed to fetch balances");
+    assert_eq!(results[0].as_ref().unwrap(), &balances);
+    assert!(results[1].is_err());
+
+    println!("✅ test_watch_only_balances PASSED");
+}
+
+#[tokio::test]
+async fn test_watch_only_refuses_state_changes() {
+    // Nothing listens here: the refusal must happen before any RPC call
+    let client = solana_client::rpc_client::RpcClient::new("http://127.0.0.1:1".to_string());
+    let owner = Keypair::new();
+    let token_account = Pubkey::new_unique();
+    let watched = watch::WatchOnlyAccount::from_provider(
+        token_account,
+        &key_provider::SignerKeyProvider::new(&owner),
+    ).unwrap();
+
+    let error = withdraw::withdraw_with_keys(&client, &owner, &owner, &Pubkey::new_unique(), 1, 9, &watched)
+        .await.expect_err("Watch-only withdraw must fail");
+    assert!(error.to_string().contains("watch-only"), "{}", error);
+
+    let error = apply_pending::apply_pending_balance_with_keys(&client, &owner, &owner, &Pubkey::new_unique(), &watched)
+        .await.expect_err("Watch-only apply must fail");
+    assert!(error.to_string().contains("watch-only"), "{}", error);
+
+    println!("✅ test_watch_only_refuses_state_changes PASSED");
+}
+
+#[test]
+fn test_apply_policy() {
+    use pending_watcher::ApplyPolicy;
+    use std::time::Duration;
+
+    let policy = ApplyPolicy {
+        min_pending_amount: Some(1_000),
+        counter_headroom: Some(2),
+        interval: Some(Duration::from_secs(60)),
+    };
+
+    // Nothing pending, nothing to apply
+    assert!(!policy.should_apply(0, 0, 65536, Duration::from_secs(3600)));
+
+    assert!(!policy.should_apply(999, 1, 10, Duration::from_secs(1)));
+    assert!(policy.should_apply(1_000, 1, 10, Duration::from_secs(1)));
+    assert!(policy.should_apply(1, 8, 10, Duration::from_secs(1)));
+    assert!(policy.should_apply(1, 1, 10, Duration::from_secs(60)));
+
+    assert!(!ApplyPolicy::default().should_apply(u64::MAX, 1, 65536, Duration::MAX));
+    assert!(ApplyPolicy::default().should_apply(1, 65534, 65536, Duration::ZERO));
+    assert!(ApplyPolicy::immediate().should_apply(0, 1, 65536, Duration::ZERO));
+
+    println!("✅ test_apply_policy PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pending_watcher_auto_applies() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+
+    let mint = create_confidential_mint(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    let owner = Keypair::new();
+    env.airdrop_if_needed(&owner.pubkey(), 100_000_000)
+        .expect("Airdrop to owner failed");
+
+    let token_account = create_token_account(&env, &mint.pubkey(), &owner.pubkey())
+        .expect("Failed to create token account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to configure account");
+
+    mint_tokens(&env, &mint.pubkey(), &token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 400_000_000, 9)
+        .await.expect("Failed to deposit");
+
+    let keys = key_provider::SignerKeyProvider::new(&owner);
+    let watcher = pending_watcher::PendingBalanceWatcher::new(
+        &env.client,
+        &env.payer,
+        &owner,
+        &mint.pubkey(),
+        &keys,
+        pending_watcher::ApplyPolicy::immediate(),
+    )
+    .polling_only()
+    .with_poll_interval(Duration::from_millis(500));
+
+    // Stop once the deposit shows up as available
+    let stop = AtomicBool::new(false);
+    let watched = watch::WatchOnlyAccount::from_provider(token_account, &keys).unwrap();
+    let (signatures, ()) = tokio::join!(watcher.run(&stop), async {
+        for _ in 0..60 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            if watched.balances(&env.client).is_ok_and(|balances| balances.available == 400_000_000) {
+                break;
+            }
+        }
+        stop.store(true, Ordering::SeqCst);
+    });
+
+    let signatures = signatures.expect("Watcher failed");
+    assert_eq!(signatures.len(), 1);
+    let balances = watched.balances(&env.client).unwrap();
+    assert_eq!(balances.pending, 0);
+    assert_eq!(balances.available, 400_000_000);
+
+    println!("✅ test_pending_watcher_auto_applies PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_create_and_close_confidential_account() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint = Keypair::new();
+    let auditor = ElGamalKeypair::new_rand();
+    accounts::create_confidential_mint(
+        &env.client, &env.payer, &mint, &env.payer_pubkey(), 6,
+        Some((*auditor.pubkey()).into()), true,
+    ).await.expect("Failed to create mint");
+
+    let owner = Keypair::new();
+    env.airdrop_if_needed(&owner.pubkey(), 100_000_000)
+        .expect("Airdrop to owner failed");
+    let receipt = accounts::create_token_account(&env.client, &env.payer, &owner.pubkey(), &mint.pubkey())
+        .await.expect("Failed to create token account");
+    assert!(receipt.rent_spent > 0);
+    // Creating again is a no-op
+    let receipt = accounts::create_token_account(&env.client, &env.payer, &owner.pubkey(), &mint.pubkey())
+        .await.expect("Idempotent create failed");
+    assert_eq!(receipt.rent_spent, 0);
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to configure account");
+
+    let token_account = spl_associated_token_account::get_associated_token_address_with_program_id(
+        &owner.pubkey(),
+        &mint.pubkey(),
+        &spl_token_2022::id(),
+    );
+
+    // Non-empty accounts can't be closed
+    mint_tokens(&env, &mint.pubkey(), &token_account, &env.payer, 1_000)
+        .expect("Failed to mint tokens");
+    deposit::deposit_to_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 1_000, 6)
+        .await.expect("Failed to deposit");
+    assert!(accounts::close_confidential_account(&env.client, &env.payer, &owner, &mint.pubkey(), &owner.pubkey())
+        .await.is_err());
+
+    apply_pending::apply_pending_balance(&env.client, &env.payer, &owner, &mint.pubkey())
+        .await.expect("Failed to apply pending balance");
+    withdraw::withdraw_from_confidential(&env.client, &env.payer, &owner, &mint.pubkey(), 1_000, 6)
+        .await.expect("Failed to withdraw");
+    burn_public_balance(&env, &mint.pubkey(), &token_account, &owner, 1_000);
+
+    accounts::close_confidential_account(&env.client, &env.payer, &owner, &mint.pubkey(), &owner.pubkey())
+        .await.expect("Failed to close account");
+    assert!(env.client.get_account(&token_account).is_err());
+
+    println!("✅ test_create_and_close_confidential_account PASSED");
+}
+
+/// Burn the whole public balance so the account can be closed
+fn burn_public_balance(env: &TestEnv, mint: &Pubkey, token_account: &Pubkey, owner: &Keypair, amount: u64) {
+    let burn_ix = spl_token_2022::instruction::burn(
+        &spl_token_2022::id(),
+        token_account,
+        mint,
+        &owner.pubkey(),
+        &[],
+        amount,
+    ).unwrap();
+    let recent_blockhash = env.client.get_latest_blockhash().unwrap();
+    let transaction = solana_sdk::transaction::Transaction::new_signed_with_payer(
+        &[burn_ix],
+        Some(&env.payer_pubkey()),
+        &[&env.payer, owner],
+        recent_blockhash,
+    );
+    env.client.send_and_confirm_transaction(&transaction).expect("Failed to burn tokens");
+}
+
+#[test]
+fn test_cli_reports_errors_as_json() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_conf-balances"))
+        .args(["--keypair", "/nonexistent/id.json", "--output", "json", "balance"])
+        .arg(Pubkey::new_unique().to_string())
+        .output()
+        .expect("Failed to run conf-balances");
+
+    assert!(!output.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).expect("stderr isn't JSON");
+    assert!(error["error"].as_str().unwrap().contains("/nonexistent/id.json"));
+
+    println!("✅ test_cli_reports_errors_as_json PASSED");
+}
+
+#[test]
+fn test_signer_sources() {
+    use signer::*;
+
+    let keypair = Keypair::new();
+
+    // Keypair file, JSON bytes and base58 all load the same keypair
+    let path = std::env::temp_dir().join(format!("ct-signer-{}.json", keypair.pubkey()));
+    solana_sdk::signature::write_keypair_file(&keypair, &path).unwrap();
+    let json = serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap();
+    for source in [
+        path.display().to_string(),
+        format!("file:{}", path.display()),
+        json,
+        keypair.to_base58_string(),
+    ] {
+        assert_eq!(load_signer(&source).expect("Failed to load signer").pubkey(), keypair.pubkey());
+    }
+    let _ = std::fs::remove_file(path);
+
+    // Mismatched pubkey bytes are rejected instead of silently truncated
+    let mut bytes = keypair.to_bytes();
+    bytes[63] ^= 1;
+    assert!(load_keypair(&serde_json::to_string(&bytes.to_vec()).unwrap()).is_err());
+
+    // Seed phrases honour the derivation path and ignore extra whitespace
+    let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    let account_0 = load_keypair(&format!("seed:{}?key=0/0", phrase)).unwrap();
+    let account_1 = load_keypair(&format!("seed:{}?key=1/0", phrase)).unwrap();
+    assert_ne!(account_0.pubkey(), account_1.pubkey());
+    assert_eq!(
+        load_keypair(&format!("seed:  {}  ?key=0/0", phrase.replace(' ', "   "))).unwrap().pubkey(),
+        account_0.pubkey(),
+    );
+    assert!(load_keypair("seed:abandon abandon abandon").is_err());
+    assert_eq!(
+        SignerSource::parse("prompt://?key=0/0").unwrap(),
+        SignerSource::Prompt { derivation_path: Some(solana_derivation_path::DerivationPath::new_bip44(Some(0), Some(0))) },
+    );
+
+    // ASK uses the Solana CLI's legacy derivation (keypair from the seed bytes)
+    assert_eq!(SignerSource::parse("ASK").unwrap(), SignerSource::LegacyPrompt);
+    assert_eq!(
+        keypair_from_legacy_seed_phrase(phrase, "").unwrap().pubkey().to_string(),
+        "EHqmfkN89RJ7Y33CXM6uCzhVeuywHoJXZZLszBHHZy7o",
+    );
+
+    // Remote signer stand-in signs transactions for the keypair it wraps
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let served = keypair.insecure_clone();
+    let server = std::thread::spawn(move || {
+        serve_signing_requests(listener, &served, "s3cret", Some(4)).map_err(|e| e.to_string())
+    });
+
+    // Requests without the shared token are refused
+    assert!(RemoteSigner::connect(&address.to_string(), "wrong").is_err());
+    std::env::remove_var(REMOTE_SIGNER_TOKEN_VAR);
+    assert!(load_signer(&format!("remote://{}", address)).is_err());
+
+    std::env::set_var(REMOTE_SIGNER_TOKEN_VAR, "s3cret");
+    let remote = load_signer(&format!("remote://{}", address)).expect("Failed to connect to remote signer");
+    std::env::remove_var(REMOTE_SIGNER_TOKEN_VAR);
+    assert_eq!(remote.pubkey(), keypair.pubkey());
+    // A client hanging up without a request doesn't stop the service
+    drop(std::net::TcpStream::connect(address).unwrap());
+    let message = b"confidential balances";
+    assert_eq!(remote.try_sign_message(message).unwrap(), keypair.sign_message(message));
+    server.join().unwrap().expect("Remote signer failed");
+
+    // Nothing is accepted past the limit, and only loopback listeners are served
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    serve_signing_requests(listener, &keypair, "s3cret", Some(0)).expect("Remote signer failed");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(serve_signing_requests(listener, &keypair, "", None).is_err());
+    let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    assert!(serve_signing_requests(listener, &keypair, "s3cret", None).is_err());
+    assert!(load_keypair(&format!("remote://{}", address)).is_err());
+
+    println!("✅ test_signer_sources PASSED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multisig_authority() {
+    let env = TestEnv::new();
+
+    // Airdrop to payer if on local
+    env.airdrop_if_needed(&env.payer_pubkey(), 10_000_000_000)
+        .expect("Airdrop failed");
+
+    let mint_authority = Keypair::new();
+    env.airdrop_if_needed(&mint_authority.pubkey(), 100_000_000)
+        .expect("Airdrop to mint authority failed");
+    let mint = create_confidential_mint(&env, &mint_authority, 9)
+        .expect("Failed to create mint");
+
+    // A 2-of-3 treasury; its keys can't be derived from a signature, so they're given explicitly
+    let members = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let multisig = create_multisig(&env, 2, &[&members[0].pubkey(), &members[1].pubkey(), &members[2].pubkey()])
+        .expect("Failed to create multisig");
+    let keys = key_provider::ExplicitKeyProvider::new(ElGamalKeypair::new_rand(), AeKey::new_rand());
+    let signers: [&dyn Signer; 2] = [&members[0], &members[2]];
+    let treasury = authority::Authority::multisig(multisig, &signers);
+
+    let token_account = create_token_account(&env, &mint.pubkey(), &multisig)
+        .expect("Failed to create token account");
+    configure::configure_account_with_authority(&env.client, &env.payer, &treasury, &mint.pubkey(), &keys)
+        .await.expect("Failed to configure multisig account");
+
+    mint_tokens(&env, &mint.pubkey(), &token_account, &mint_authority, 1_000_000_000)
+        .expect("Failed to mint tokens");
+    let receipt = deposit::deposit_with_authority(&env.client, &env.payer, &treasury, &mint.pubkey(), 1_000_000_000, 9, &keys, None)
+        .await.expect("Failed to deposit");
+    assert_eq!(receipt.balances.unwrap().pending, 1_000_000_000);
+    let receipt = apply_pending::apply_pending_balance_with_authority(&env.client, &env.payer, &treasury, &mint.pubkey(), &keys)
+        .await.expect("Failed to apply pending balance");
+    assert_eq!(receipt.balances.unwrap().available, 1_000_000_000);
+    let receipt = withdraw::withdraw_with_authority(
+        &env.client,
+        &env.payer,
+        &treasury,
+        &mint.pubkey(),
+        100_000_000,
+        9,
+        &keys,
+        &proof_placement::WithdrawOptions::default(),
+    ).await.expect("Failed to withdraw");
+    assert_eq!(receipt.balances.as_ref().unwrap().available, 900_000_000);
+    assert!(receipt.signature(receipt::TransactionRole::Withdraw).is_some());
+
+    let recipient = Keypair::new();
+    create_token_account(&env, &mint.pubkey(), &recipient.pubkey())
+        .expect("Failed to create recipient token account");
+    configure::configure_account_for_confidential_transfers(&env.client, &env.payer, &recipient, &mint.pubkey())
+        .await.expect("Failed to configure recipient");
+    let receipt = transfer::transfer_with_authority(
+        &env.client,
+        &env.payer,
+        &treasury,
+        &mint.pubkey(),
+        &recipient.pubkey(),
+        200_000_000,
+        &keys,
+        &proof_placement::TransferOptions::default(),
+    ).await.expect("Failed to transfer");
+    let balances = receipt.balances.unwrap();
+    assert_eq!(balances.available, 700_000_000);
+    assert_eq!(balances.public, 100_000_000);
+
+    // One signer is below the threshold: rejected before anything is sent
+    let lone: [&dyn Signer; 1] = [&members[1]];
+    let error = deposit::deposit_with_authority(
+        &env.client,
+        &env.payer,
+        &authority::Authority::multisig(multisig, &lone),
+        &mint.pubkey(),
+        1,
+        9,
+        &keys,
+        None,
+    ).await.unwrap_err();
+    assert!(error.to_string().contains("needs 2 of 3 signers"));
+
+    println!("✅ test_multisig_authority PASSED");
+}
+
+#[test]
+fn test_multisig_authority_check() {
+    use solana_sdk::{account::Account, program_pack::Pack};
+    use spl_token_2022::state::Multisig;
+
+    let members = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let mut state = Multisig { m: 2, n: 3, is_initialized: true, ..Multisig::default() };
+    for (slot, member) in state.signers.iter_mut().zip(&members) {
+        *slot = member.pubkey();
+    }
+    let mut data = vec![0; Multisig::LEN];
+    Multisig::pack(state, &mut data).unwrap();
+    let account = Account { data, owner: spl_token_2022::id(), ..Account::default() };
+    let multisig = Pubkey::new_unique();
+
+    let two: [&dyn Signer; 2] = [&members[0], &members[1]];
+    authority::Authority::multisig(multisig, &two).check_multisig_account(&account)
+        .expect("2 of 3 signers should pass");
+
+    // A repeated signer counts once
+    let repeated: [&dyn Signer; 2] = [&members[0], &members[0]];
+    let error = authority::Authority::multisig(multisig, &repeated).check_multisig_account(&account).unwrap_err();
+    assert!(error.to_string().contains("needs 2 of 3 signers, got 1"));
+
+    let outsider = Keypair::new();
+    let stranger: [&dyn Signer; 2] = [&members[0], &outsider];
+    let error = authority::Authority::multisig(multisig, &stranger).check_multisig_account(&account).unwrap_err();
+    assert!(error.to_string().contains("isn't a signer of multisig"));
+
+    let foreign = Account { owner: Pubkey::new_unique(), ..account.clone() };
+    assert!(authority::Authority::multisig(multisig, &two).check_multisig_account(&foreign).is_err());
+
+    // A single signer needs no account
+    let owner = Keypair::new();
+    let single = authority::Authority::from(&owner as &dyn Signer);
+    assert_eq!(single.pubkey(), owner.pubkey());
+    assert!(single.multisig_signers().is_empty());
+    single.check_multisig_account(&foreign).expect("Single signer should pass");
+}